@@ -1,12 +1,15 @@
 #version 330 core
 
 in vec3 normal;
+in vec4 color;
 out vec3 out_0;
 
+// Direction towards the light, scaled by its intensity
+uniform vec3 light;
+
 vec3 __impl_main() {
-	vec3 L = normalize(vec3(-0.5, 1, -1));
-	vec3 C = vec3(1, 0.5, 0.5);
-	float cos_a = dot(L, normal);
+	vec3 C = color.rgb;
+	float cos_a = dot(light, normal);
 	float ambient = 0.3;
 	return cos_a * C + ambient * C;
 }
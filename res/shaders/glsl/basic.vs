@@ -1,14 +1,113 @@
 #version 330 core
 
+#define MAX_JOINTS 64
+#define MAX_MORPH_TARGETS 8
+
 layout(location=0) in vec3 position;
 layout(location=1) in vec3 N;
+layout(location=2) in vec2 UV;
+layout(location=3) in vec4 T;
+layout(location=4) in vec4 C;
+layout(location=5) in vec2 UV1;
+layout(location=6) in uvec4 joints;
+layout(location=7) in vec4 weights;
 
+uniform mat4 model;
+uniform mat3 normal_matrix;
 uniform mat4 view;
 uniform mat4 proj;
 
+// 0 for rigid meshes, 1 for linear blend and 2 for dual quaternion skinning
+uniform int skinning;
+// Mesh space bind pose to mesh space current pose. For dual quaternion skinning
+// the first two columns hold the real and dual part as xyzw.
+uniform mat4 joint_matrices[MAX_JOINTS];
+
+// Number of targets in morph_deltas, and the morph_count of them with the
+// largest weights that are blended
+uniform int morph_targets;
+uniform int morph_count;
+uniform int morph_indices[MAX_MORPH_TARGETS];
+uniform float morph_weights[MAX_MORPH_TARGETS];
+// Position and normal delta of every corner for every target in turn
+uniform samplerBuffer morph_deltas;
+
 out vec3 normal;
+out vec2 uv;
+out vec4 color;
+out vec2 uv1;
+out vec3 tangent;
+out vec3 bitangent;
+
+vec3 rotate(vec4 q, vec3 v) {
+    return v + 2.0 * cross(q.xyz, cross(q.xyz, v) + q.w * v);
+}
+
+// Zero instead of NaN for degenerate vectors
+vec3 safe_normalize(vec3 v) {
+    float len2 = dot(v, v);
+    return len2 > 0.0 ? v * inversesqrt(len2) : vec3(0.0);
+}
 
 void main() {
-    gl_Position = proj * view * vec4(position, 1.0);
-    normal = N;
-}
\ No newline at end of file
+    vec3 p = position;
+    vec3 n = N;
+    vec3 t = T.xyz;
+
+    // Shapes are blended in bind pose, before the skeleton moves them
+    if (morph_count > 0) {
+        int vertices = textureSize(morph_deltas) / (2 * morph_targets);
+        for (int i = 0; i < morph_count; i++) {
+            int texel = 2 * (morph_indices[i] * vertices + gl_VertexID);
+            p += texelFetch(morph_deltas, texel).xyz * morph_weights[i];
+            n += texelFetch(morph_deltas, texel + 1).xyz * morph_weights[i];
+        }
+        n = safe_normalize(n);
+    }
+
+    float total = weights.x + weights.y + weights.z + weights.w;
+    if (skinning == 1 && total > 0.0) {
+        mat4 m = mat4(0.0);
+        for (int i = 0; i < 4; i++) {
+            m += joint_matrices[joints[i]] * (weights[i] / total);
+        }
+        p = (m * vec4(p, 1.0)).xyz;
+        n = transpose(inverse(mat3(m))) * n;
+        t = mat3(m) * t;
+    } else if (skinning == 2 && total > 0.0) {
+        // Every joint on the hemisphere of the most weighted one, or blending
+        // takes the long way around
+        int heaviest = 0;
+        for (int i = 1; i < 4; i++) {
+            if (weights[i] > weights[heaviest]) {
+                heaviest = i;
+            }
+        }
+        vec4 reference = joint_matrices[joints[heaviest]][0];
+        vec4 real = vec4(0.0);
+        vec4 dual = vec4(0.0);
+        for (int i = 0; i < 4; i++) {
+            mat4 dq = joint_matrices[joints[i]];
+            float w = weights[i] / total * (dot(reference, dq[0]) < 0.0 ? -1.0 : 1.0);
+            real += dq[0] * w;
+            dual += dq[1] * w;
+        }
+        float len = length(real);
+        real /= len;
+        dual /= len;
+
+        vec3 translation = 2.0 * (real.w * dual.xyz - dual.w * real.xyz + cross(real.xyz, dual.xyz));
+        p = rotate(real, p) + translation;
+        n = rotate(real, n);
+        t = rotate(real, t);
+    }
+
+    gl_Position = proj * view * model * vec4(p, 1.0);
+    // Scaling and skinning leave the normal at any length
+    normal = safe_normalize(normal_matrix * n);
+    uv = UV;
+    color = C;
+    uv1 = UV1;
+    tangent = mat3(model) * t;
+    bitangent = mat3(model) * (cross(n, t) * T.w);
+}
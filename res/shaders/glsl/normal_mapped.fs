@@ -0,0 +1,40 @@
+#version 330 core
+
+in vec3 normal;
+in vec4 color;
+in vec2 uv;
+in vec3 tangent;
+in vec3 bitangent;
+out vec3 out_0;
+
+// Direction towards the light, scaled by its intensity
+uniform vec3 light;
+
+uniform sampler2D albedo;
+uniform sampler2D normal_map;
+// 1 for OpenGL style normal maps, -1 for DirectX style ones
+uniform float normal_map_green;
+
+vec3 mapped_normal() {
+	vec3 n = normalize(normal);
+	vec3 t = normalize(tangent - n * dot(n, tangent));
+	vec3 b = cross(n, t) * sign(dot(cross(n, t), bitangent));
+
+	vec3 m = texture(normal_map, uv).xyz * 2.0 - 1.0;
+	m.y *= normal_map_green;
+	return normalize(mat3(t, b, n) * m);
+}
+
+vec3 __impl_main() {
+	vec3 C = texture(albedo, uv).rgb * color.rgb;
+	float cos_a = dot(light, mapped_normal());
+	float ambient = 0.3;
+	return cos_a * C + ambient * C;
+}
+
+void main() {
+	vec3 rt = __impl_main();
+	out_0 = rt;
+}
+
+
@@ -0,0 +1,25 @@
+#version 330 core
+
+in vec3 normal;
+in vec4 color;
+in vec2 uv;
+out vec3 out_0;
+
+// Direction towards the light, scaled by its intensity
+uniform vec3 light;
+
+uniform sampler2D albedo;
+
+vec3 __impl_main() {
+	vec3 C = texture(albedo, uv).rgb * color.rgb;
+	float cos_a = dot(light, normal);
+	float ambient = 0.3;
+	return cos_a * C + ambient * C;
+}
+
+void main() {
+	vec3 rt = __impl_main();
+	out_0 = rt;
+}
+
+
@@ -0,0 +1,538 @@
+use crate::camera::{Camera, Projection};
+use crate::scene::{NodeId, Scene};
+use crate::transform::Transform;
+
+use cgmath::prelude::*;
+use cgmath::{Point3, Quaternion, Rad, Vector3};
+use serde::Deserialize;
+
+use std::ops::{Add, Mul};
+use std::path::{Path, PathBuf};
+
+/// Values a `Track` can interpolate.
+pub trait Animatable: Copy + Add<Output = Self> + Mul<f32, Output = Self> {
+    /// Straight line interpolation, which for rotations is nlerp.
+    fn lerp(self, other: Self, t: f32) -> Self {
+        self * (1.0 - t) + other * t
+    }
+
+    /// Interpolation at constant speed, only differs from `lerp` for rotations.
+    fn slerp(self, other: Self, t: f32) -> Self {
+        self.lerp(other, t)
+    }
+
+    /// 1, or -1 if `other` negated means the same and is closer to `self`, so
+    /// blending towards it takes the short way around.
+    fn alignment(self, _other: Self) -> f32 {
+        1.0
+    }
+
+    /// Makes a weighted sum of values valid again, e.g. a unit quaternion.
+    fn renormalize(self) -> Self {
+        self
+    }
+}
+
+impl Animatable for f32 {}
+impl Animatable for Rad<f32> {}
+impl Animatable for Vector3<f32> {}
+
+impl Animatable for Quaternion<f32> {
+    fn lerp(self, other: Self, t: f32) -> Self {
+        self.nlerp(other * self.alignment(other), t)
+    }
+
+    fn slerp(self, other: Self, t: f32) -> Self {
+        Quaternion::slerp(self, other * self.alignment(other), t)
+    }
+
+    fn alignment(self, other: Self) -> f32 {
+        if self.dot(other) < 0.0 {
+            -1.0
+        } else {
+            1.0
+        }
+    }
+
+    fn renormalize(self) -> Self {
+        self.normalize()
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Deserialize)]
+pub enum Interpolation {
+    /// Holds every key until the next one.
+    Step,
+    Linear,
+    /// Like `Linear`, but rotations turn at a constant speed.
+    Slerp,
+    /// Cubic Hermite spline through the keys using their tangents, like glTF.
+    CubicSpline,
+}
+
+#[derive(Copy, Clone, Debug)]
+pub struct Key<T> {
+    /// In seconds.
+    pub time: f32,
+    pub value: T,
+    /// Rate of change per second arriving at and leaving the key, only used by
+    /// `CubicSpline`.
+    pub in_tangent: T,
+    pub out_tangent: T,
+}
+
+/// Keyframes of a single value, ordered by time. Before the first and after the
+/// last key the track holds still.
+#[derive(Clone, Debug)]
+pub struct Track<T> {
+    pub interpolation: Interpolation,
+    keys: Vec<Key<T>>,
+}
+
+impl<T: Animatable> Track<T> {
+    pub fn new(interpolation: Interpolation) -> Track<T> {
+        Track {
+            interpolation,
+            keys: Vec::new(),
+        }
+    }
+
+    pub fn with_key(mut self, time: f32, value: T) -> Track<T> {
+        self.insert(time, value);
+        self
+    }
+
+    /// Adds a key with flat tangents, replacing one at the same time.
+    pub fn insert(&mut self, time: f32, value: T) {
+        self.insert_spline(time, value * 0.0, value, value * 0.0);
+    }
+
+    pub fn insert_spline(&mut self, time: f32, in_tangent: T, value: T, out_tangent: T) {
+        let key = Key {
+            time,
+            value,
+            in_tangent,
+            out_tangent,
+        };
+        let i = self.keys.partition_point(|k| k.time < time);
+        match self.keys.get_mut(i) {
+            Some(k) if k.time == time => *k = key,
+            _ => self.keys.insert(i, key),
+        }
+    }
+
+    pub fn keys(&self) -> &[Key<T>] {
+        &self.keys
+    }
+
+    /// Time of the last key.
+    pub fn duration(&self) -> f32 {
+        self.keys.last().map_or(0.0, |k| k.time)
+    }
+
+    /// Sets the tangents of every key so a `CubicSpline` passes smoothly through
+    /// its neighbours (Catmull-Rom). The first and last key only look one way.
+    pub fn smooth_tangents(&mut self) {
+        for i in 0..self.keys.len() {
+            let previous = self.keys[i.saturating_sub(1)];
+            let next = self.keys[(i + 1).min(self.keys.len() - 1)];
+            let value = self.keys[i].value;
+
+            let dt = next.time - previous.time;
+            let tangent = if dt > 0.0 {
+                (next.value * value.alignment(next.value)
+                    + previous.value * -value.alignment(previous.value))
+                    * (1.0 / dt)
+            } else {
+                value * 0.0
+            };
+            self.keys[i].in_tangent = tangent;
+            self.keys[i].out_tangent = tangent;
+        }
+    }
+
+    /// Value at `time` in seconds, `None` without keys.
+    pub fn sample(&self, time: f32) -> Option<T> {
+        let first = self.keys.first()?;
+        let last = self.keys.last()?;
+        if time <= first.time {
+            return Some(first.value);
+        }
+        if time >= last.time {
+            return Some(last.value);
+        }
+
+        let i = self.keys.partition_point(|k| k.time <= time);
+        let (a, b) = (&self.keys[i - 1], &self.keys[i]);
+        let dt = b.time - a.time;
+        let t = (time - a.time) / dt;
+
+        Some(match self.interpolation {
+            Interpolation::Step => a.value,
+            Interpolation::Linear => a.value.lerp(b.value, t),
+            Interpolation::Slerp => a.value.slerp(b.value, t),
+            Interpolation::CubicSpline => {
+                let (t2, t3) = (t * t, t * t * t);
+                let sign = a.value.alignment(b.value);
+                (a.value * (2.0 * t3 - 3.0 * t2 + 1.0)
+                    + a.out_tangent * ((t3 - 2.0 * t2 + t) * dt)
+                    + b.value * (sign * (-2.0 * t3 + 3.0 * t2))
+                    + b.in_tangent * (sign * (t3 - t2) * dt))
+                    .renormalize()
+            }
+        })
+    }
+}
+
+/// Tracks for the parts of a `Transform`. Parts without a track are left alone.
+#[derive(Clone, Debug, Default)]
+pub struct TransformTracks {
+    pub position: Option<Track<Vector3<f32>>>,
+    pub rotation: Option<Track<Quaternion<f32>>>,
+    pub scale: Option<Track<Vector3<f32>>>,
+}
+
+impl TransformTracks {
+    pub fn duration(&self) -> f32 {
+        let position = self.position.as_ref().map_or(0.0, Track::duration);
+        let rotation = self.rotation.as_ref().map_or(0.0, Track::duration);
+        let scale = self.scale.as_ref().map_or(0.0, Track::duration);
+        position.max(rotation).max(scale)
+    }
+
+    pub fn apply(&self, time: f32, transform: &mut Transform) {
+        if let Some(position) = self.position.as_ref().and_then(|t| t.sample(time)) {
+            transform.position = Point3::from_vec(position);
+        }
+        if let Some(rotation) = self.rotation.as_ref().and_then(|t| t.sample(time)) {
+            transform.rotation = rotation;
+        }
+        if let Some(scale) = self.scale.as_ref().and_then(|t| t.sample(time)) {
+            transform.scale = scale;
+        }
+    }
+}
+
+/// Tracks for the morph target weights of a node, indexed like the targets of its
+/// mesh. Targets without a track keep their weight.
+#[derive(Clone, Debug, Default)]
+pub struct MorphTracks {
+    pub weights: Vec<Option<Track<f32>>>,
+}
+
+impl MorphTracks {
+    pub fn duration(&self) -> f32 {
+        self.weights
+            .iter()
+            .flatten()
+            .map(Track::duration)
+            .fold(0.0, f32::max)
+    }
+
+    pub fn apply(&self, time: f32, weights: &mut Vec<f32>) {
+        for (i, track) in self.weights.iter().enumerate() {
+            if let Some(value) = track.as_ref().and_then(|t| t.sample(time)) {
+                if weights.len() <= i {
+                    weights.resize(i + 1, 0.0);
+                }
+                weights[i] = value;
+            }
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct CameraTracks {
+    pub transform: TransformTracks,
+    /// Point the camera keeps looking at, overrides the rotation track.
+    pub look_at: Option<Track<Vector3<f32>>>,
+    pub up: Vector3<f32>,
+    /// Only applies to perspective projections.
+    pub fovy: Option<Track<Rad<f32>>>,
+    /// Only applies to orthographic projections.
+    pub height: Option<Track<f32>>,
+}
+
+impl Default for CameraTracks {
+    fn default() -> CameraTracks {
+        CameraTracks {
+            transform: TransformTracks::default(),
+            look_at: None,
+            up: Vector3::unit_y(),
+            fovy: None,
+            height: None,
+        }
+    }
+}
+
+impl CameraTracks {
+    pub fn duration(&self) -> f32 {
+        let look_at = self.look_at.as_ref().map_or(0.0, Track::duration);
+        let fovy = self.fovy.as_ref().map_or(0.0, Track::duration);
+        let height = self.height.as_ref().map_or(0.0, Track::duration);
+        self.transform.duration().max(look_at).max(fovy).max(height)
+    }
+
+    pub fn apply(&self, time: f32, camera: &mut Camera) {
+        self.transform.apply(time, &mut camera.transform);
+        if let Some(target) = self.look_at.as_ref().and_then(|t| t.sample(time)) {
+            camera.look_at(Point3::from_vec(target), self.up);
+        }
+
+        match &mut camera.projection {
+            Projection::Perspective { fovy, .. } => {
+                if let Some(value) = self.fovy.as_ref().and_then(|t| t.sample(time)) {
+                    *fovy = value;
+                }
+            }
+            Projection::Orthographic { height, .. } => {
+                if let Some(value) = self.height.as_ref().and_then(|t| t.sample(time)) {
+                    *height = value;
+                }
+            }
+            Projection::OffAxis { .. } => {}
+        }
+    }
+}
+
+/// Tracks moving nodes of a scene and its camera.
+#[derive(Clone, Debug, Default)]
+pub struct Animation {
+    pub nodes: Vec<(NodeId, TransformTracks)>,
+    pub morphs: Vec<(NodeId, MorphTracks)>,
+    pub camera: Option<CameraTracks>,
+}
+
+impl Animation {
+    /// Time of the last key of any track.
+    pub fn duration(&self) -> f32 {
+        let nodes = self
+            .nodes
+            .iter()
+            .map(|(_, tracks)| tracks.duration())
+            .fold(0.0, f32::max);
+        let morphs = self
+            .morphs
+            .iter()
+            .map(|(_, tracks)| tracks.duration())
+            .fold(0.0, f32::max);
+        let camera = self.camera.as_ref().map_or(0.0, CameraTracks::duration);
+        nodes.max(morphs).max(camera)
+    }
+
+    /// Moves and reshapes the animated nodes to how they are at `time`.
+    pub fn pose_scene(&self, time: f32, scene: &mut Scene) {
+        for (node, tracks) in self.nodes.iter() {
+            tracks.apply(time, &mut scene.node_mut(*node).transform);
+        }
+        for (node, tracks) in self.morphs.iter() {
+            tracks.apply(time, &mut scene.node_mut(*node).morph_weights);
+        }
+    }
+
+    pub fn pose_camera(&self, time: f32, camera: &mut Camera) {
+        if let Some(tracks) = &self.camera {
+            tracks.apply(time, camera);
+        }
+    }
+}
+
+/// Time range an animation is rendered or played back over, in seconds.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Timeline {
+    pub start: f32,
+    pub end: f32,
+    pub fps: f32,
+    /// Playback starts over after `end`. A looping sequence leaves out the frame
+    /// at `end`, it is the same as the first one.
+    pub looping: bool,
+}
+
+impl Default for Timeline {
+    /// A single frame at time 0.
+    fn default() -> Timeline {
+        Timeline {
+            start: 0.0,
+            end: 0.0,
+            fps: 24.0,
+            looping: false,
+        }
+    }
+}
+
+impl Timeline {
+    pub fn length(&self) -> f32 {
+        (self.end - self.start).max(0.0)
+    }
+
+    pub fn frame_count(&self) -> usize {
+        let frames = self.length() * self.fps;
+        if self.looping && frames > 0.0 {
+            (frames - 1e-3).ceil() as usize
+        } else {
+            (frames + 1e-3).floor() as usize + 1
+        }
+    }
+
+    pub fn frame_time(&self, frame: usize) -> f32 {
+        self.start + frame as f32 / self.fps
+    }
+
+    /// Animation time after playing for `elapsed` seconds.
+    pub fn time_at(&self, elapsed: f32) -> f32 {
+        let length = self.length();
+        if length <= 0.0 {
+            self.start
+        } else if self.looping {
+            self.start + elapsed.rem_euclid(length)
+        } else {
+            self.start + elapsed.min(length)
+        }
+    }
+}
+
+/// `path` with the frame number before the extension, `frames/shot.png` becomes
+/// `frames/shot_0007.png`.
+pub fn frame_path(path: &Path, frame: usize) -> PathBuf {
+    let stem = path.file_stem().and_then(|s| s.to_str()).unwrap_or("frame");
+    let mut name = format!("{}_{:04}", stem, frame);
+    if let Some(extension) = path.extension().and_then(|e| e.to_str()) {
+        name = format!("{}.{}", name, extension);
+    }
+    path.with_file_name(name)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use cgmath::Deg;
+
+    fn track(interpolation: Interpolation) -> Track<f32> {
+        Track::new(interpolation)
+            .with_key(2.0, 4.0)
+            .with_key(0.0, 0.0)
+    }
+
+    fn angle(q: Quaternion<f32>) -> Deg<f32> {
+        Deg::from(Rad(2.0 * q.v.magnitude().atan2(q.s)))
+    }
+
+    #[test]
+    fn tracks_hold_still_outside_their_keys() {
+        assert_eq!(Track::<f32>::new(Interpolation::Linear).sample(1.0), None);
+        for interpolation in [
+            Interpolation::Step,
+            Interpolation::Linear,
+            Interpolation::Slerp,
+            Interpolation::CubicSpline,
+        ]
+        .iter()
+        {
+            let track = track(*interpolation);
+            assert_eq!(track.duration(), 2.0);
+            assert_eq!(track.sample(-1.0), Some(0.0));
+            assert_eq!(track.sample(0.0), Some(0.0));
+            assert_eq!(track.sample(2.0), Some(4.0));
+            assert_eq!(track.sample(3.0), Some(4.0));
+        }
+    }
+
+    #[test]
+    fn tracks_interpolate_between_keys() {
+        assert_eq!(track(Interpolation::Step).sample(1.9), Some(0.0));
+        assert_eq!(track(Interpolation::Linear).sample(0.5), Some(1.0));
+        assert_eq!(track(Interpolation::Slerp).sample(0.5), Some(1.0));
+
+        // Flat tangents ease in and out, h01(0.25) = 0.15625
+        let eased = track(Interpolation::CubicSpline).sample(0.5).unwrap();
+        assert!((eased - 0.625).abs() < 1e-6);
+
+        // Tangents are per second and follow the slope, so the spline is a line
+        let mut spline = Track::new(Interpolation::CubicSpline);
+        spline.insert_spline(0.0, 2.0, 0.0, 2.0);
+        spline.insert_spline(2.0, 2.0, 4.0, 2.0);
+        assert!((spline.sample(0.5).unwrap() - 1.0).abs() < 1e-6);
+        let mut smoothed = track(Interpolation::CubicSpline);
+        smoothed.smooth_tangents();
+        assert!((smoothed.sample(0.5).unwrap() - 1.0).abs() < 1e-6);
+    }
+
+    #[test]
+    fn rotation_tracks_take_the_short_way() {
+        let quarter = Quaternion::from_angle_z(Deg(90.0));
+        for interpolation in [
+            Interpolation::Linear,
+            Interpolation::Slerp,
+            Interpolation::CubicSpline,
+        ]
+        .iter()
+        {
+            // The same rotation, negated
+            let track = Track::new(*interpolation)
+                .with_key(0.0, Quaternion::one())
+                .with_key(1.0, -quarter);
+            let half = track.sample(0.5).unwrap();
+            assert!((half.magnitude() - 1.0).abs() < 1e-6);
+            assert!(
+                (angle(half).0.abs() - 45.0).abs() < 1e-3,
+                "{:?}",
+                interpolation
+            );
+        }
+
+        // Only slerp turns at a constant speed
+        let sample = |interpolation| {
+            let track = Track::new(interpolation)
+                .with_key(0.0, Quaternion::one())
+                .with_key(1.0, quarter);
+            angle(track.sample(0.25).unwrap()).0
+        };
+        assert!((sample(Interpolation::Slerp) - 22.5).abs() < 1e-3);
+        assert!((sample(Interpolation::Linear) - 22.5).abs() > 0.1);
+        assert_eq!(sample(Interpolation::Step), 0.0);
+    }
+
+    #[test]
+    fn looping_timelines_leave_out_the_last_frame() {
+        let timeline = Timeline {
+            start: 1.0,
+            end: 3.0,
+            fps: 24.0,
+            looping: false,
+        };
+        assert_eq!(timeline.frame_count(), 49);
+        assert_eq!(timeline.frame_time(48), 3.0);
+        assert_eq!(timeline.time_at(5.0), 3.0);
+
+        let looping = Timeline {
+            looping: true,
+            ..timeline
+        };
+        assert_eq!(looping.frame_count(), 48);
+        assert_eq!(looping.time_at(2.5), 1.5);
+
+        // Partial frames at the end
+        let partial = Timeline {
+            end: 3.01,
+            ..timeline
+        };
+        assert_eq!(partial.frame_count(), 49);
+        assert_eq!(
+            Timeline {
+                looping: true,
+                ..partial
+            }
+            .frame_count(),
+            49
+        );
+
+        // A single frame, looping or not
+        assert_eq!(Timeline::default().frame_count(), 1);
+        let still = Timeline {
+            looping: true,
+            ..Timeline::default()
+        };
+        assert_eq!(still.frame_count(), 1);
+        assert_eq!(still.time_at(10.0), 0.0);
+    }
+}
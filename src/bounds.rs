@@ -0,0 +1,389 @@
+use crate::convention::{Convention, DepthRange};
+
+use cgmath::prelude::*;
+use cgmath::{Matrix4, Point3, Vector3, Vector4};
+
+/// Axis aligned bounding box. An empty box has `min > max` on every axis.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Aabb {
+    pub min: Point3<f32>,
+    pub max: Point3<f32>,
+}
+
+impl Aabb {
+    pub fn new(min: Point3<f32>, max: Point3<f32>) -> Aabb {
+        Aabb { min, max }
+    }
+
+    pub fn empty() -> Aabb {
+        Aabb {
+            min: Point3::new(f32::INFINITY, f32::INFINITY, f32::INFINITY),
+            max: Point3::new(f32::NEG_INFINITY, f32::NEG_INFINITY, f32::NEG_INFINITY),
+        }
+    }
+
+    pub fn from_points<I: IntoIterator<Item = Point3<f32>>>(points: I) -> Aabb {
+        let mut aabb = Aabb::empty();
+        for p in points {
+            aabb.include(p);
+        }
+        aabb
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.min.x > self.max.x || self.min.y > self.max.y || self.min.z > self.max.z
+    }
+
+    pub fn include(&mut self, p: Point3<f32>) {
+        self.min = Point3::new(
+            self.min.x.min(p.x),
+            self.min.y.min(p.y),
+            self.min.z.min(p.z),
+        );
+        self.max = Point3::new(
+            self.max.x.max(p.x),
+            self.max.y.max(p.y),
+            self.max.z.max(p.z),
+        );
+    }
+
+    pub fn union(&self, other: &Aabb) -> Aabb {
+        if other.is_empty() {
+            return *self;
+        }
+        let mut aabb = *self;
+        aabb.include(other.min);
+        aabb.include(other.max);
+        aabb
+    }
+
+    /// Box around this one after an affine transform.
+    pub fn transformed(&self, m: &Matrix4<f32>) -> Aabb {
+        if self.is_empty() {
+            return *self;
+        }
+
+        // Each axis of the new box spans the absolute projections of the old
+        // half extents (Arvo)
+        let center = Point3::from_homogeneous(m * self.center().to_homogeneous());
+        let half = self.size() * 0.5;
+        let extent = |row: usize| {
+            m.x[row].abs() * half.x + m.y[row].abs() * half.y + m.z[row].abs() * half.z
+        };
+        let extent = Vector3::new(extent(0), extent(1), extent(2));
+        Aabb::new(center - extent, center + extent)
+    }
+
+    /// Sphere through the corners of the box.
+    pub fn bounding_sphere(&self) -> BoundingSphere {
+        if self.is_empty() {
+            return BoundingSphere::new(Point3::new(0.0, 0.0, 0.0), 0.0);
+        }
+        BoundingSphere::new(self.center(), self.size().magnitude() * 0.5)
+    }
+
+    pub fn center(&self) -> Point3<f32> {
+        self.min.midpoint(self.max)
+    }
+
+    pub fn size(&self) -> Vector3<f32> {
+        self.max - self.min
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct BoundingSphere {
+    pub center: Point3<f32>,
+    pub radius: f32,
+}
+
+impl BoundingSphere {
+    pub fn new(center: Point3<f32>, radius: f32) -> BoundingSphere {
+        BoundingSphere { center, radius }
+    }
+
+    /// Approximate minimal sphere using Ritter's algorithm, which is within a few
+    /// percent of optimal and linear in the number of points.
+    pub fn from_points(points: &[Point3<f32>]) -> BoundingSphere {
+        let first = match points.first() {
+            Some(p) => *p,
+            None => return BoundingSphere::new(Point3::new(0.0, 0.0, 0.0), 0.0),
+        };
+
+        let farthest_from = |from: Point3<f32>| {
+            *points
+                .iter()
+                .max_by(|a, b| {
+                    from.distance2(**a)
+                        .partial_cmp(&from.distance2(**b))
+                        .unwrap_or(std::cmp::Ordering::Equal)
+                })
+                .unwrap()
+        };
+        let a = farthest_from(first);
+        let b = farthest_from(a);
+
+        let mut sphere = BoundingSphere::new(a.midpoint(b), a.distance(b) * 0.5);
+        for p in points.iter() {
+            let d = sphere.center.distance(*p);
+            if d > sphere.radius {
+                // Grow just enough to touch p on the far side
+                let radius = (sphere.radius + d) * 0.5;
+                sphere.center += (*p - sphere.center) * ((radius - sphere.radius) / d);
+                sphere.radius = radius;
+            }
+        }
+
+        sphere
+    }
+
+    /// Sphere around this one after an affine transform, which is the smallest
+    /// unless the scale is non-uniform.
+    pub fn transformed(&self, m: &Matrix4<f32>) -> BoundingSphere {
+        let max_scale =
+            m.x.truncate()
+                .magnitude()
+                .max(m.y.truncate().magnitude())
+                .max(m.z.truncate().magnitude());
+        BoundingSphere::new(
+            Point3::from_homogeneous(m * self.center.to_homogeneous()),
+            self.radius * max_scale,
+        )
+    }
+
+    /// Smallest sphere around both.
+    pub fn union(&self, other: &BoundingSphere) -> BoundingSphere {
+        let offset = other.center - self.center;
+        let distance = offset.magnitude();
+
+        if distance + other.radius <= self.radius {
+            return *self;
+        }
+        if distance + self.radius <= other.radius {
+            return *other;
+        }
+
+        let radius = (distance + self.radius + other.radius) * 0.5;
+        let center = self.center + offset * ((radius - self.radius) / distance);
+        BoundingSphere::new(center, radius)
+    }
+}
+
+/// A box and a sphere around the same geometry. The sphere is quicker to test
+/// against, the box usually fits tighter.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Bounds {
+    pub aabb: Aabb,
+    pub sphere: BoundingSphere,
+}
+
+impl Bounds {
+    pub fn from_points(points: &[Point3<f32>]) -> Bounds {
+        Bounds {
+            aabb: Aabb::from_points(points.iter().copied()),
+            sphere: BoundingSphere::from_points(points),
+        }
+    }
+
+    pub fn transformed(&self, m: &Matrix4<f32>) -> Bounds {
+        Bounds {
+            aabb: self.aabb.transformed(m),
+            sphere: self.sphere.transformed(m),
+        }
+    }
+}
+
+/// Points `p` with `normal.dot(p) + distance >= 0` are in front of the plane.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Plane {
+    pub normal: Vector3<f32>,
+    pub distance: f32,
+}
+
+impl Plane {
+    /// Plane from the coefficients `(a, b, c, d)` of `ax + by + cz + d = 0`,
+    /// scaled so `normal` has unit length.
+    pub fn from_coefficients(v: Vector4<f32>) -> Plane {
+        let length = v.truncate().magnitude();
+        Plane {
+            normal: v.truncate() / length,
+            distance: v.w / length,
+        }
+    }
+
+    pub fn signed_distance(&self, p: Point3<f32>) -> f32 {
+        self.normal.dot(p.to_vec()) + self.distance
+    }
+}
+
+/// The volume a camera sees, bounded by six planes facing inwards.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Frustum {
+    /// Left, right, bottom, top, near and far.
+    pub planes: [Plane; 6],
+}
+
+impl Frustum {
+    /// Frustum of a view projection matrix producing clip space of `convention`,
+    /// extracted from its rows (Gribb and Hartmann). The planes are in whatever
+    /// space the matrix takes, world space for `projection * view`.
+    pub fn from_matrix(m: &Matrix4<f32>, convention: &Convention) -> Frustum {
+        let row = |i: usize| Vector4::new(m.x[i], m.y[i], m.z[i], m.w[i]);
+        let (x, y, z, w) = (row(0), row(1), row(2), row(3));
+
+        // Inside means -w <= x <= w, -w <= y <= w and near <= z <= w
+        let near = match convention.depth_range {
+            DepthRange::NegativeOneToOne => w + z,
+            DepthRange::ZeroToOne => z,
+        };
+        Frustum {
+            planes: [
+                Plane::from_coefficients(w + x),
+                Plane::from_coefficients(w - x),
+                Plane::from_coefficients(w + y),
+                Plane::from_coefficients(w - y),
+                Plane::from_coefficients(near),
+                Plane::from_coefficients(w - z),
+            ],
+        }
+    }
+
+    /// False if the sphere is entirely outside. Spheres near the corners of the
+    /// frustum may be reported as intersecting when they are not.
+    pub fn intersects_sphere(&self, sphere: &BoundingSphere) -> bool {
+        self.planes
+            .iter()
+            .all(|p| p.signed_distance(sphere.center) >= -sphere.radius)
+    }
+
+    /// False if the box is entirely outside, with the same caveat as for spheres.
+    pub fn intersects_aabb(&self, aabb: &Aabb) -> bool {
+        if aabb.is_empty() {
+            return false;
+        }
+
+        // Only the corner furthest along the normal needs to be in front
+        self.planes.iter().all(|p| {
+            let corner = Point3::new(
+                if p.normal.x >= 0.0 {
+                    aabb.max.x
+                } else {
+                    aabb.min.x
+                },
+                if p.normal.y >= 0.0 {
+                    aabb.max.y
+                } else {
+                    aabb.min.y
+                },
+                if p.normal.z >= 0.0 {
+                    aabb.max.z
+                } else {
+                    aabb.min.z
+                },
+            );
+            p.signed_distance(corner) >= 0.0
+        })
+    }
+
+    /// Tests the sphere first and the box only if the sphere intersects.
+    pub fn intersects(&self, bounds: &Bounds) -> bool {
+        self.intersects_sphere(&bounds.sphere) && self.intersects_aabb(&bounds.aabb)
+    }
+}
+
+/// Half line starting at `origin`, `direction` is normalized.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Ray {
+    pub origin: Point3<f32>,
+    pub direction: Vector3<f32>,
+}
+
+impl Ray {
+    pub fn new(origin: Point3<f32>, direction: Vector3<f32>) -> Ray {
+        Ray {
+            origin,
+            direction: direction.normalize(),
+        }
+    }
+
+    pub fn at(&self, t: f32) -> Point3<f32> {
+        self.origin + self.direction * t
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::camera::{Camera, Projection};
+    use crate::transform::Transform;
+    use cgmath::{Deg, Quaternion};
+
+    /// Frustum of a camera at +Z looking at the origin.
+    fn frustum(convention: Convention) -> Frustum {
+        let transform = Transform::new(
+            Point3::new(0.0, 0.0, 5.0),
+            Quaternion::one(),
+            Vector3::new(1.0, 1.0, 1.0),
+        );
+        let mut camera = Camera::new(
+            transform,
+            Projection::Perspective {
+                fovy: Deg(60.0).into(),
+                near: 0.1,
+                far: 100.0,
+            },
+        )
+        .with_convention(convention);
+        camera.look_at(Point3::new(0.0, 0.0, 0.0), Vector3::unit_y());
+        camera.frustum(1.5)
+    }
+
+    fn cube(center: Point3<f32>, half_size: f32) -> Bounds {
+        let offset = Vector3::new(half_size, half_size, half_size);
+        Bounds::from_points(&[center - offset, center + offset])
+    }
+
+    #[test]
+    fn frustums_keep_what_the_camera_sees() {
+        for convention in [Convention::GL, Convention::DIRECT3D, Convention::VULKAN].iter() {
+            let frustum = frustum(*convention);
+            let visible =
+                |center: Point3<f32>, half_size: f32| frustum.intersects(&cube(center, half_size));
+
+            assert!(visible(Point3::new(0.0, 0.0, 0.0), 1.0), "{:?}", convention);
+            // Only partly inside the left, top and far planes
+            assert!(visible(Point3::new(-5.5, 0.0, 0.0), 1.0));
+            assert!(visible(Point3::new(0.0, 3.5, 0.0), 1.0));
+            assert!(visible(Point3::new(0.0, 0.0, -95.5), 1.0));
+
+            // Behind the camera, in front of the near plane, beyond the far
+            // plane and off to every side
+            assert!(!visible(Point3::new(0.0, 0.0, 10.0), 1.0));
+            assert!(
+                !visible(Point3::new(0.0, 0.0, 4.95), 0.01),
+                "{:?}",
+                convention
+            );
+            assert!(!visible(Point3::new(0.0, 0.0, -100.0), 1.0));
+            assert!(!visible(Point3::new(-8.0, 0.0, 0.0), 1.0));
+            assert!(!visible(Point3::new(8.0, 0.0, 0.0), 1.0));
+            assert!(!visible(Point3::new(0.0, -5.0, 0.0), 1.0));
+            assert!(!visible(Point3::new(0.0, 5.0, 0.0), 1.0));
+
+            assert!(!frustum.intersects(&Bounds::from_points(&[])));
+        }
+    }
+
+    #[test]
+    fn frustums_check_the_box_when_the_sphere_intersects() {
+        let frustum = frustum(Convention::GL);
+        // The box is outside, a loose sphere around it reaches in
+        let bounds = Bounds {
+            sphere: BoundingSphere::new(Point3::new(-8.0, 0.0, 0.0), 4.0),
+            ..cube(Point3::new(-8.0, 0.0, 0.0), 1.0)
+        };
+        assert!(frustum.intersects_sphere(&bounds.sphere));
+        assert!(!frustum.intersects_aabb(&bounds.aabb));
+        assert!(!frustum.intersects(&bounds));
+    }
+}
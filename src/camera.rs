@@ -1,37 +1,218 @@
+use crate::bounds::{BoundingSphere, Frustum, Ray};
+use crate::convention::{Convention, Handedness};
+use crate::raster::Viewport;
 use crate::transform::Transform;
-use cgmath::Rotation;
-use cgmath::{Matrix4, PerspectiveFov, Vector3};
+use cgmath::prelude::*;
+use cgmath::{Matrix3, Matrix4, Point3, Quaternion, Rad, Vector3};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Projection {
+    /// Symmetric perspective with a vertical field of view, the horizontal one
+    /// follows from the aspect ratio of the viewport.
+    Perspective { fovy: Rad<f32>, near: f32, far: f32 },
+    /// Parallel projection showing `height` world units vertically.
+    Orthographic { height: f32, near: f32, far: f32 },
+    /// Perspective with the extents of the frustum on the near plane given
+    /// directly, which need not be centered. Used to render one tile of a larger
+    /// image or one eye of a stereo pair. Ignores the aspect ratio.
+    OffAxis {
+        left: f32,
+        right: f32,
+        bottom: f32,
+        top: f32,
+        near: f32,
+        far: f32,
+    },
+}
+
+impl Projection {
+    /// Projection matrix in GL conventions, clip space z in [-1, 1].
+    pub fn gl_matrix(&self, aspect: f32) -> Matrix4<f32> {
+        match *self {
+            Projection::Perspective { fovy, near, far } => {
+                cgmath::perspective(fovy, aspect, near, far)
+            }
+            Projection::Orthographic { height, near, far } => {
+                let (x, y) = (height * aspect * 0.5, height * 0.5);
+                cgmath::ortho(-x, x, -y, y, near, far)
+            }
+            Projection::OffAxis {
+                left,
+                right,
+                bottom,
+                top,
+                near,
+                far,
+            } => cgmath::frustum(left, right, bottom, top, near, far),
+        }
+    }
+
+    /// Projection matrix taking view space of `convention` to its clip space.
+    pub fn matrix(&self, aspect: f32, convention: &Convention) -> Matrix4<f32> {
+        convention.adapt_gl_projection(self.gl_matrix(aspect))
+    }
+
+    /// How many pixels one world unit at `distance` from the camera covers on a
+    /// viewport `viewport_height` pixels tall.
+    pub fn pixels_per_unit(&self, distance: f32, viewport_height: u32) -> f32 {
+        let height = match *self {
+            Projection::Perspective { fovy, .. } => 2.0 * distance * (fovy / 2.0).tan(),
+            Projection::Orthographic { height, .. } => height,
+            Projection::OffAxis {
+                bottom, top, near, ..
+            } => (top - bottom) * distance / near,
+        };
+        viewport_height as f32 / height.max(f32::EPSILON)
+    }
+}
 
 #[derive(Debug, Clone)]
 pub struct Camera {
     pub transform: Transform,
-    pub projection: PerspectiveFov<f32>,
+    pub projection: Projection,
+    pub convention: Convention,
 }
 
 impl Camera {
-    pub fn new(transform: Transform, projection: PerspectiveFov<f32>) -> Camera {
+    pub fn new(transform: Transform, projection: Projection) -> Camera {
         Camera {
             transform,
             projection,
+            convention: Convention::default(),
         }
     }
 
+    pub fn with_convention(mut self, convention: Convention) -> Camera {
+        self.convention = convention;
+        self
+    }
+
     pub fn get_view_matrix(&self) -> Matrix4<f32> {
-        Matrix4::look_at_dir(
-            self.transform.position,
-            self.transform
-                .rotation
-                .rotate_vector(Vector3::new(0.0, 0.0, 1.0)),
-            self.transform
-                .rotation
-                .rotate_vector(Vector3::new(0.0, 1.0, 0.0)),
-        )
-    }
-
-    pub fn get_projection_matrix(&self) -> Matrix4<f32> {
-        let mut m = Matrix4::from(self.projection);
-        m = Matrix4::from_nonuniform_scale(-1.0, -1.0, 1.0) * m;
-        // m[3][3] *= -1.0;
-        m
+        self.convention.view_from_right_handed()
+            * Matrix4::look_at_dir(
+                self.transform.position,
+                self.transform
+                    .rotation
+                    .rotate_vector(Vector3::new(0.0, 0.0, 1.0)),
+                self.transform
+                    .rotation
+                    .rotate_vector(Vector3::new(0.0, 1.0, 0.0)),
+            )
+    }
+
+    /// Turns the camera to look at `target`, keeping `up` as close to the screen's
+    /// up direction as possible. Does nothing if the camera is at `target`.
+    pub fn look_at(&mut self, target: Point3<f32>, up: Vector3<f32>) {
+        let offset = target - self.transform.position;
+        if offset.magnitude2() == 0.0 {
+            return;
+        }
+        let forward = offset.normalize();
+
+        // Looking straight along `up` leaves roll undefined, any other axis will do
+        let mut side = up.cross(forward);
+        if side.magnitude2() < 1e-12 {
+            side = Vector3::unit_z().cross(forward);
+            if side.magnitude2() < 1e-12 {
+                side = Vector3::unit_x().cross(forward);
+            }
+        }
+        let side = side.normalize();
+
+        // The local axes are X = Y x Z, Y up and Z forward
+        let basis = Matrix3::from_cols(side, forward.cross(side), forward);
+        self.transform.rotation = Quaternion::from(basis).normalize();
+    }
+
+    /// Moves the camera back along its view axis until `sphere` just fits inside
+    /// a viewport with the given aspect ratio, keeping the orientation. Orthographic
+    /// projections are resized to fit instead. The near and far planes are left
+    /// alone.
+    pub fn frame(&mut self, sphere: &BoundingSphere, aspect: f32) {
+        // The sphere touches a side plane at distance d when r / d is the sine of
+        // the angle between plane and view axis, `slopes` are the tangents
+        let fit = |slopes: &[f32]| {
+            slopes
+                .iter()
+                .map(|s| {
+                    let s = s.max(f32::EPSILON);
+                    sphere.radius * (1.0 + s * s).sqrt() / s
+                })
+                .fold(0.0, f32::max)
+        };
+
+        let distance = match self.projection {
+            Projection::Perspective { fovy, .. } => {
+                let y = (fovy / 2.0).tan();
+                fit(&[y * aspect, y])
+            }
+            Projection::Orthographic { near, far, .. } => {
+                self.projection = Projection::Orthographic {
+                    height: 2.0 * sphere.radius * (1.0 / aspect).max(1.0),
+                    near,
+                    far,
+                };
+                sphere.radius + near
+            }
+            Projection::OffAxis {
+                left,
+                right,
+                bottom,
+                top,
+                near,
+                ..
+            } => fit(&[right / near, -left / near, top / near, -bottom / near]),
+        };
+
+        self.transform.position = sphere.center - self.transform.forward() * distance;
+    }
+
+    /// Ray through a point in window space of `viewport`, starting on the near
+    /// plane. Pixel centers are at half coordinates.
+    pub fn pixel_ray(&self, viewport: &Viewport, x: f32, y: f32) -> Ray {
+        let inverse_view_proj = (self.get_projection_matrix(viewport.aspect())
+            * self.get_view_matrix())
+        .invert()
+        .unwrap();
+        let unproject = |depth: f32| {
+            let p = inverse_view_proj * viewport.window_to_ndc(x, y, depth).extend(1.0);
+            Point3::from_homogeneous(p)
+        };
+
+        let near = unproject(0.0);
+        Ray::new(near, unproject(1.0) - near)
+    }
+
+    /// Window space position of `point` in `viewport` with depth in [0, 1], or
+    /// `None` if it is not in front of the camera.
+    pub fn project(&self, viewport: &Viewport, point: Point3<f32>) -> Option<Vector3<f32>> {
+        let clip = self.get_projection_matrix(viewport.aspect())
+            * self.get_view_matrix()
+            * point.to_homogeneous();
+        if clip.w <= 0.0 {
+            return None;
+        }
+        Some(viewport.clip_to_window(clip))
+    }
+
+    /// World space direction pointing right on screen. The camera looks down its
+    /// local +Z, so with right handed conventions that is local -X.
+    pub fn screen_right(&self) -> Vector3<f32> {
+        match self.convention.handedness {
+            Handedness::Right => -self.transform.right(),
+            Handedness::Left => self.transform.right(),
+        }
+    }
+
+    /// Projection for a viewport with the given width / height ratio.
+    pub fn get_projection_matrix(&self, aspect: f32) -> Matrix4<f32> {
+        self.projection.matrix(aspect, &self.convention)
+    }
+
+    /// What the camera sees on a viewport with the given width / height ratio, in
+    /// world space.
+    pub fn frustum(&self, aspect: f32) -> Frustum {
+        let view_proj = self.get_projection_matrix(aspect) * self.get_view_matrix();
+        Frustum::from_matrix(&view_proj, &self.convention)
     }
 }
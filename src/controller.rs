@@ -0,0 +1,216 @@
+use crate::camera::Camera;
+use crate::convention::Handedness;
+
+use cgmath::prelude::*;
+use cgmath::{Deg, Point3, Quaternion, Rad, Vector2};
+use glutin::event::{
+    ElementState, KeyboardInput, MouseButton, MouseScrollDelta, VirtualKeyCode, WindowEvent,
+};
+
+use std::collections::HashSet;
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Mode {
+    /// Left drag rotates around `target`, right or middle drag pans it and the
+    /// wheel zooms.
+    Orbit,
+    /// WASD moves, Q and E go down and up, shift is faster and right drag looks
+    /// around.
+    Fly,
+}
+
+/// Drives a `Camera` from window input. Tab switches between orbiting and
+/// flying, both keep the current view when switching.
+#[derive(Debug, Clone)]
+pub struct CameraController {
+    pub mode: Mode,
+    pub target: Point3<f32>,
+    pub distance: f32,
+    yaw: Rad<f32>,
+    pitch: Rad<f32>,
+
+    /// Radians per pixel of mouse movement.
+    pub look_speed: f32,
+    /// Fly speed in world units per second.
+    pub move_speed: f32,
+    /// Factor on `move_speed` while shift is held.
+    pub boost: f32,
+
+    keys: HashSet<VirtualKeyCode>,
+    buttons: HashSet<MouseButton>,
+    shift: bool,
+    cursor: Option<Vector2<f32>>,
+    drag: Vector2<f32>,
+    scroll: f32,
+}
+
+impl CameraController {
+    /// Orbits `target`, starting from where `camera` currently is.
+    pub fn orbit(camera: &Camera, target: Point3<f32>) -> CameraController {
+        let offset = target - camera.transform.position;
+        let distance = offset.magnitude();
+        let forward = if distance > 0.0 {
+            offset / distance
+        } else {
+            camera.transform.forward()
+        };
+
+        CameraController {
+            mode: Mode::Orbit,
+            target,
+            distance: distance.max(MIN_DISTANCE),
+            yaw: Rad::atan2(forward.x, forward.z),
+            pitch: Rad::asin(-forward.y.clamp(-1.0, 1.0)),
+            look_speed: 0.005,
+            move_speed: 2.0,
+            boost: 4.0,
+            keys: HashSet::new(),
+            buttons: HashSet::new(),
+            shift: false,
+            cursor: None,
+            drag: Vector2::zero(),
+            scroll: 0.0,
+        }
+    }
+
+    /// Records input, which is applied to the camera on the next `update`.
+    pub fn handle_event(&mut self, event: &WindowEvent) {
+        match event {
+            WindowEvent::KeyboardInput {
+                input:
+                    KeyboardInput {
+                        virtual_keycode: Some(key),
+                        state,
+                        ..
+                    },
+                ..
+            } => match state {
+                ElementState::Pressed => {
+                    if *key == VirtualKeyCode::Tab && !self.keys.contains(key) {
+                        self.toggle_mode();
+                    }
+                    self.keys.insert(*key);
+                }
+                ElementState::Released => {
+                    self.keys.remove(key);
+                }
+            },
+            WindowEvent::ModifiersChanged(modifiers) => self.shift = modifiers.shift(),
+            WindowEvent::MouseInput { state, button, .. } => match state {
+                ElementState::Pressed => {
+                    self.buttons.insert(*button);
+                }
+                ElementState::Released => {
+                    self.buttons.remove(button);
+                }
+            },
+            WindowEvent::CursorMoved { position, .. } => {
+                let position = Vector2::new(position.x as f32, position.y as f32);
+                if let Some(last) = self.cursor {
+                    self.drag += position - last;
+                }
+                self.cursor = Some(position);
+            }
+            WindowEvent::CursorLeft { .. } => self.cursor = None,
+            WindowEvent::MouseWheel { delta, .. } => {
+                self.scroll += match delta {
+                    MouseScrollDelta::LineDelta(_, y) => *y,
+                    MouseScrollDelta::PixelDelta(p) => p.y as f32 / PIXELS_PER_LINE,
+                }
+            }
+            WindowEvent::Focused(false) => {
+                self.keys.clear();
+                self.buttons.clear();
+            }
+            _ => {}
+        }
+    }
+
+    /// Moves `camera` by the input since the last update, `dt` is the frame time
+    /// in seconds. Mouse input is a distance already, only key movement is scaled.
+    pub fn update(&mut self, camera: &mut Camera, dt: f32) {
+        let drag = std::mem::replace(&mut self.drag, Vector2::zero());
+        let scroll = std::mem::replace(&mut self.scroll, 0.0);
+
+        // Dragging right turns the view right, which is the other way around +Y
+        // when the image is mirrored
+        let turn = match camera.convention.handedness {
+            Handedness::Right => -1.0,
+            Handedness::Left => 1.0,
+        };
+        let look = |controller: &mut CameraController| {
+            controller.yaw += Rad(drag.x * controller.look_speed * turn);
+            controller.pitch += Rad(drag.y * controller.look_speed);
+            let limit = Rad::from(Deg(89.0));
+            controller.pitch = Rad(controller.pitch.0.clamp(-limit.0, limit.0));
+        };
+
+        match self.mode {
+            Mode::Orbit => {
+                if self.buttons.contains(&MouseButton::Left) {
+                    look(self);
+                } else if self.buttons.contains(&MouseButton::Right)
+                    || self.buttons.contains(&MouseButton::Middle)
+                {
+                    // Moves the target along with the cursor, scaled so the point
+                    // under it follows roughly a pixel per pixel at the target
+                    let pan = self.distance * self.look_speed;
+                    self.target += camera.screen_right() * -drag.x * pan
+                        + camera.transform.up() * drag.y * pan;
+                }
+
+                self.distance = (self.distance * ZOOM_PER_LINE.powf(scroll)).max(MIN_DISTANCE);
+
+                camera.transform.rotation = self.rotation();
+                camera.transform.position =
+                    self.target - camera.transform.forward() * self.distance;
+            }
+            Mode::Fly => {
+                if self.buttons.contains(&MouseButton::Right) {
+                    look(self);
+                }
+                camera.transform.rotation = self.rotation();
+
+                let axis = |positive, negative| {
+                    self.keys.contains(&positive) as i32 as f32
+                        - self.keys.contains(&negative) as i32 as f32
+                };
+                let direction = camera.transform.forward()
+                    * axis(VirtualKeyCode::W, VirtualKeyCode::S)
+                    + camera.screen_right() * axis(VirtualKeyCode::D, VirtualKeyCode::A)
+                    + camera.transform.up() * axis(VirtualKeyCode::E, VirtualKeyCode::Q);
+
+                if direction.magnitude2() > 0.0 {
+                    let speed = if self.shift {
+                        self.move_speed * self.boost
+                    } else {
+                        self.move_speed
+                    };
+                    camera.transform.position += direction.normalize() * speed * dt;
+                }
+
+                // Keeps the target in front, so orbiting picks up from here
+                self.target =
+                    camera.transform.position + camera.transform.forward() * self.distance;
+            }
+        }
+    }
+
+    fn toggle_mode(&mut self) {
+        self.mode = match self.mode {
+            Mode::Orbit => Mode::Fly,
+            Mode::Fly => Mode::Orbit,
+        };
+    }
+
+    /// Yaw around world +Y, then pitch with positive values looking down.
+    fn rotation(&self) -> Quaternion<f32> {
+        Quaternion::from_angle_y(self.yaw) * Quaternion::from_angle_x(self.pitch)
+    }
+}
+
+const MIN_DISTANCE: f32 = 0.01;
+/// Distance factor per wheel notch.
+const ZOOM_PER_LINE: f32 = 0.9;
+/// Touchpads scroll in pixels, this many make up a notch.
+const PIXELS_PER_LINE: f32 = 20.0;
@@ -0,0 +1,342 @@
+use cgmath::Matrix4;
+use serde::Deserialize;
+
+#[derive(Copy, Clone, Debug, PartialEq, Deserialize)]
+pub enum Handedness {
+    /// The camera looks down -Z in view space and front faces wind counter
+    /// clockwise, like GL.
+    Right,
+    /// The camera looks down +Z in view space and front faces wind clockwise,
+    /// like Direct3D.
+    Left,
+}
+
+/// Range of clip space z after the perspective divide.
+#[derive(Copy, Clone, Debug, PartialEq, Deserialize)]
+pub enum DepthRange {
+    /// [-1, 1], the GL default.
+    NegativeOneToOne,
+    /// [0, 1], as in Direct3D and Vulkan, or GL with `glClipControl`.
+    ZeroToOne,
+}
+
+/// Direction of increasing rows in a framebuffer.
+#[derive(Copy, Clone, Debug, PartialEq, Deserialize)]
+pub enum FramebufferY {
+    /// Row 0 is the bottom row, like GL window space.
+    Up,
+    /// Row 0 is the top row, like images and Direct3D or Vulkan.
+    Down,
+}
+
+/// Coordinate conventions shared by the camera, the software rasterizer and the
+/// GL renderer. Whatever is chosen the picture comes out upright, the choice
+/// decides what view, clip and framebuffer coordinates mean.
+#[derive(Copy, Clone, Debug, PartialEq, Deserialize)]
+pub struct Convention {
+    pub handedness: Handedness,
+    pub depth_range: DepthRange,
+    pub framebuffer_y: FramebufferY,
+}
+
+impl Default for Convention {
+    fn default() -> Convention {
+        Convention::GL
+    }
+}
+
+impl Convention {
+    pub const GL: Convention = Convention {
+        handedness: Handedness::Right,
+        depth_range: DepthRange::NegativeOneToOne,
+        framebuffer_y: FramebufferY::Up,
+    };
+
+    pub const DIRECT3D: Convention = Convention {
+        handedness: Handedness::Left,
+        depth_range: DepthRange::ZeroToOne,
+        framebuffer_y: FramebufferY::Down,
+    };
+
+    pub const VULKAN: Convention = Convention {
+        handedness: Handedness::Right,
+        depth_range: DepthRange::ZeroToOne,
+        framebuffer_y: FramebufferY::Down,
+    };
+
+    /// Maps right handed view space, as produced by `Matrix4::look_at_dir`, to
+    /// view space of this convention.
+    pub fn view_from_right_handed(&self) -> Matrix4<f32> {
+        match self.handedness {
+            Handedness::Right => Matrix4::from_scale(1.0),
+            // Right becomes -X and forward +Z
+            Handedness::Left => Matrix4::from_nonuniform_scale(-1.0, 1.0, -1.0),
+        }
+    }
+
+    /// Wraps a projection built for GL conventions so it takes view space and
+    /// produces clip space of this convention.
+    pub fn adapt_gl_projection(&self, gl: Matrix4<f32>) -> Matrix4<f32> {
+        let view_to_gl = match self.handedness {
+            Handedness::Right => Matrix4::from_scale(1.0),
+            Handedness::Left => Matrix4::from_nonuniform_scale(1.0, 1.0, -1.0),
+        };
+
+        // With rows going down the top of the picture has to land on row 0
+        let flip_y = match self.framebuffer_y {
+            FramebufferY::Up => Matrix4::from_scale(1.0),
+            FramebufferY::Down => Matrix4::from_nonuniform_scale(1.0, -1.0, 1.0),
+        };
+
+        // z' = (z + w) / 2
+        #[rustfmt::skip]
+        let remap_depth = match self.depth_range {
+            DepthRange::NegativeOneToOne => Matrix4::from_scale(1.0),
+            DepthRange::ZeroToOne => Matrix4::new(
+                1.0, 0.0, 0.0, 0.0,
+                0.0, 1.0, 0.0, 0.0,
+                0.0, 0.0, 0.5, 0.0,
+                0.0, 0.0, 0.5, 1.0,
+            ),
+        };
+
+        remap_depth * flip_y * gl * view_to_gl
+    }
+
+    /// Normalized device z of the near and far plane.
+    pub fn ndc_depth(&self) -> (f32, f32) {
+        match self.depth_range {
+            DepthRange::NegativeOneToOne => (-1.0, 1.0),
+            DepthRange::ZeroToOne => (0.0, 1.0),
+        }
+    }
+
+    /// Maps normalized device z to window space depth in [0, 1].
+    pub fn window_depth(&self, z_ndc: f32) -> f32 {
+        match self.depth_range {
+            DepthRange::NegativeOneToOne => z_ndc * 0.5 + 0.5,
+            DepthRange::ZeroToOne => z_ndc,
+        }
+    }
+
+    /// Whether front faces wind counter clockwise in framebuffer coordinates, with
+    /// x going right and y going in the direction rows are stored.
+    pub fn front_face_ccw(&self) -> bool {
+        (self.handedness == Handedness::Right) == (self.framebuffer_y == FramebufferY::Up)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::camera::{Camera, Projection};
+    use crate::opengl;
+    use crate::raster::{self, Tri3, Viewport};
+    use crate::transform::Transform;
+    use cgmath::{Deg, Matrix4, One, Point3, Quaternion, Vector3, Vector4};
+
+    const FRAMEBUFFER_HEIGHT: u32 = 90;
+
+    /// Where GL puts a clip space position after `opengl::apply_convention` and a
+    /// `glViewport` covering `viewport`, following the window transformation of
+    /// the GL 4.5 spec. The result has GL's lower left origin.
+    fn gl_window(viewport: &Viewport, clip: Vector4<f32>) -> Vector3<f32> {
+        let convention = &viewport.convention;
+        let ndc = clip.truncate() / clip.w;
+
+        // glViewport takes its origin from the bottom, rows of Viewport are counted
+        // in the direction they are stored
+        let o_y = match convention.framebuffer_y {
+            FramebufferY::Up => viewport.y,
+            FramebufferY::Down => FRAMEBUFFER_HEIGHT as i32 - viewport.y - viewport.height as i32,
+        };
+        // GL_UPPER_LEFT negates y_d, GL_ZERO_TO_ONE skips the depth remap
+        let y_d = match convention.framebuffer_y {
+            FramebufferY::Up => ndc.y,
+            FramebufferY::Down => -ndc.y,
+        };
+        let z_w = match convention.depth_range {
+            DepthRange::NegativeOneToOne => 0.5 * ndc.z + 0.5,
+            DepthRange::ZeroToOne => ndc.z,
+        };
+
+        let (p_x, p_y) = (viewport.width as f32, viewport.height as f32);
+        Vector3::new(
+            p_x * 0.5 * ndc.x + viewport.x as f32 + p_x * 0.5,
+            p_y * 0.5 * y_d + o_y as f32 + p_y * 0.5,
+            z_w,
+        )
+    }
+
+    /// Pixel column and row counted from the top of the framebuffer, for window
+    /// coordinates with rows going up or down.
+    fn top_down_pixel(framebuffer_y: FramebufferY, window: Vector3<f32>) -> (i32, i32) {
+        let row = match framebuffer_y {
+            FramebufferY::Up => FRAMEBUFFER_HEIGHT as f32 - window.y,
+            FramebufferY::Down => window.y,
+        };
+        (window.x.floor() as i32, row.floor() as i32)
+    }
+
+    fn camera(projection: Projection, convention: Convention) -> Camera {
+        let transform = Transform::new(
+            Point3::new(0.0, 0.0, 5.0),
+            Quaternion::one(),
+            Vector3::new(1.0, 1.0, 1.0),
+        );
+        let mut camera = Camera::new(transform, projection).with_convention(convention);
+        camera.look_at(Point3::new(0.0, 0.0, 0.0), Vector3::unit_y());
+        camera
+    }
+
+    #[test]
+    fn projection_matches_gl_window_transform() {
+        let projections = [
+            Projection::Perspective {
+                fovy: Deg(60.0).into(),
+                near: 0.1,
+                far: 100.0,
+            },
+            Projection::Orthographic {
+                height: 4.0,
+                near: 0.1,
+                far: 100.0,
+            },
+        ];
+        // Off pixel boundaries, so rounding can't move them to the next pixel
+        let points = [
+            Point3::new(0.013, 0.021, 0.0),
+            Point3::new(0.93, 0.41, 0.0),
+            Point3::new(-1.27, 0.73, -2.0),
+            Point3::new(0.38, -1.06, 1.5),
+        ];
+
+        for convention in [Convention::GL, Convention::DIRECT3D, Convention::VULKAN].iter() {
+            let viewport = Viewport {
+                x: 7,
+                y: 5,
+                width: 120,
+                height: 80,
+                convention: *convention,
+            };
+
+            for projection in projections.iter() {
+                let camera = camera(*projection, *convention);
+                let view_proj =
+                    camera.get_projection_matrix(viewport.aspect()) * camera.get_view_matrix();
+
+                for point in points.iter() {
+                    let window = camera.project(&viewport, *point).unwrap();
+                    let gl = gl_window(&viewport, view_proj * point.to_homogeneous());
+
+                    assert_eq!(
+                        top_down_pixel(convention.framebuffer_y, window),
+                        top_down_pixel(FramebufferY::Up, gl),
+                        "{:?} {:?} {:?}",
+                        convention,
+                        projection,
+                        point
+                    );
+                    assert!((window.z - gl.z).abs() < 1e-6);
+                    assert!(window.z > 0.0 && window.z < 1.0);
+                }
+
+                // Up and to the right of the camera's view shows up in the top right,
+                // whatever the convention
+                let up_right = points[0] + camera.screen_right() + Vector3::unit_y() * 0.5;
+                let (x, row) = top_down_pixel(
+                    convention.framebuffer_y,
+                    camera.project(&viewport, up_right).unwrap(),
+                );
+                let (center_x, center_row) = top_down_pixel(
+                    convention.framebuffer_y,
+                    camera.project(&viewport, points[0]).unwrap(),
+                );
+                assert!(x > center_x && row < center_row, "{:?}", convention);
+            }
+        }
+    }
+
+    #[test]
+    fn front_faces_match_gl_front_face() {
+        // Counter clockwise seen from the camera in object space
+        let tri = [
+            Point3::new(-1.0, -1.0, 0.0),
+            Point3::new(1.0, -1.0, 0.0),
+            Point3::new(0.0, 1.0, 0.0),
+        ];
+        let projection = Projection::Perspective {
+            fovy: Deg(60.0).into(),
+            near: 0.1,
+            far: 100.0,
+        };
+
+        for convention in [Convention::GL, Convention::DIRECT3D, Convention::VULKAN].iter() {
+            let viewport = Viewport {
+                x: 7,
+                y: 5,
+                width: 120,
+                height: 80,
+                convention: *convention,
+            };
+            let camera = camera(projection, *convention);
+            let view_proj =
+                camera.get_projection_matrix(viewport.aspect()) * camera.get_view_matrix();
+
+            for &mirrored in [false, true].iter() {
+                let model = if mirrored {
+                    Matrix4::from_nonuniform_scale(-1.0, 1.0, 1.0)
+                } else {
+                    Matrix4::from_scale(1.0)
+                };
+
+                // Both windings, corners left in their order like a draw call
+                for &reversed in [false, true].iter() {
+                    let mut corners = tri
+                        .iter()
+                        .map(|p| Point3::from_homogeneous(model * p.to_homogeneous()));
+                    let mut corners = [
+                        corners.next().unwrap(),
+                        corners.next().unwrap(),
+                        corners.next().unwrap(),
+                    ];
+                    if reversed {
+                        corners.swap(1, 2);
+                    }
+
+                    let window = |p: Point3<f32>| camera.project(&viewport, p).unwrap();
+                    let software = raster::is_front_facing(
+                        Tri3(window(corners[0]), window(corners[1]), window(corners[2])),
+                        &viewport,
+                        mirrored,
+                    );
+
+                    // Polygon area in GL window coordinates, positive for counter
+                    // clockwise, 14.6.1 of the GL 4.5 spec
+                    let w: Vec<_> = corners
+                        .iter()
+                        .map(|p| gl_window(&viewport, view_proj * p.to_homogeneous()))
+                        .collect();
+                    let area = (0..3)
+                        .map(|i| w[i].x * w[(i + 1) % 3].y - w[(i + 1) % 3].x * w[i].y)
+                        .sum::<f32>();
+                    let gl = match opengl::front_face(convention, mirrored) {
+                        gl::CCW => area > 0.0,
+                        _ => area < 0.0,
+                    };
+
+                    assert_eq!(
+                        software, !reversed,
+                        "{:?} mirrored {} reversed {}",
+                        convention, mirrored, reversed
+                    );
+                    assert_eq!(
+                        gl, software,
+                        "{:?} mirrored {} reversed {}",
+                        convention, mirrored, reversed
+                    );
+                }
+            }
+        }
+    }
+}
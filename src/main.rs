@@ -1,10 +1,18 @@
 #![feature(debug_non_exhaustive)]
 #![feature(vec_drain_as_slice)]
 
+pub mod animation;
+pub mod bounds;
 pub mod camera;
+pub mod controller;
+pub mod convention;
 pub mod mesh;
 pub mod opengl;
+pub mod raster;
+pub mod scene;
 pub mod shader;
+pub mod skinning;
+pub mod texture;
 pub mod transform;
 
 use motokigo::{
@@ -12,14 +20,16 @@ use motokigo::{
     vm::{self, *},
 };
 
-use camera::Camera;
-use cgmath::{Deg, Matrix4, PerspectiveFov, Rad, Vector2, Vector3, Vector4};
-use transform::Transform;
+use cgmath::prelude::*;
+use cgmath::{Matrix3, Matrix4, Point3, Vector2, Vector3, Vector4};
 
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 
 use gl::types::*;
+use opengl::VertexFormat;
+use raster::{interpolate, RenderGraph, Vector2f};
 use shader::Uniform;
+use std::collections::HashMap;
 use std::ffi::CString;
 use std::time::*;
 
@@ -39,6 +49,48 @@ impl Uniform for Matrix4<f32> {
     }
 }
 
+impl Uniform for Matrix3<f32> {
+    fn set(&self, id: &str, handle: GLuint) {
+        unsafe {
+            let name = CString::new(id.as_bytes()).unwrap();
+            let location = gl::GetUniformLocation(handle, name.as_ptr());
+            gl::ProgramUniformMatrix3fv(
+                handle,
+                location,
+                1,
+                gl::FALSE,
+                ::std::mem::transmute(self),
+            );
+        }
+    }
+}
+
+impl Uniform for &[Matrix4<f32>] {
+    fn set(&self, id: &str, handle: GLuint) {
+        unsafe {
+            let name = CString::new(id.as_bytes()).unwrap();
+            let location = gl::GetUniformLocation(handle, name.as_ptr());
+            gl::ProgramUniformMatrix4fv(
+                handle,
+                location,
+                self.len() as GLsizei,
+                gl::FALSE,
+                self.as_ptr() as *const f32,
+            );
+        }
+    }
+}
+
+impl Uniform for &[f32] {
+    fn set(&self, id: &str, handle: GLuint) {
+        unsafe {
+            let name = CString::new(id.as_bytes()).unwrap();
+            let location = gl::GetUniformLocation(handle, name.as_ptr());
+            gl::ProgramUniform1fv(handle, location, self.len() as GLsizei, self.as_ptr());
+        }
+    }
+}
+
 impl Uniform for Vector3<f32> {
     fn set(&self, id: &str, handle: GLuint) {
         unsafe {
@@ -59,6 +111,16 @@ impl Uniform for Vector2<i32> {
     }
 }
 
+impl Uniform for &[i32] {
+    fn set(&self, id: &str, handle: GLuint) {
+        unsafe {
+            let name = CString::new(id.as_bytes()).unwrap();
+            let location = gl::GetUniformLocation(handle, name.as_ptr());
+            gl::ProgramUniform1iv(handle, location, self.len() as GLsizei, self.as_ptr());
+        }
+    }
+}
+
 impl Uniform for i32 {
     fn set(&self, id: &str, handle: GLuint) {
         unsafe {
@@ -79,99 +141,173 @@ impl Uniform for f32 {
     }
 }
 
-fn edge(p: Vector2<f32>, v0: Vector2<f32>, v1: Vector2<f32>) -> f32 {
-    (p.x - v0.x) * (v1.y - v0.y) - (p.y - v0.y) * (v1.x - v0.x)
+/// Textures of a material uploaded for the GL preview.
+struct GlMaterial {
+    /// Uniform name and texture of every texture binding.
+    textures: Vec<(String, GLuint)>,
+    /// Texture and green sign of the first normal map, the GL shader applies it
+    /// itself.
+    normal_map: Option<(GLuint, f32)>,
 }
 
-type Vector2f = Vector2<f32>;
-type Vector3f = Vector3<f32>;
-
-#[derive(Copy, Clone, Debug)]
-struct Tri2(Vector2f, Vector2f, Vector2f);
-#[derive(Copy, Clone, Debug)]
-struct Tri3(Vector3f, Vector3f, Vector3f);
-
-impl Tri3 {
-    pub fn truncate(self) -> Tri2 {
-        Tri2(self.0.truncate(), self.1.truncate(), self.2.truncate())
-    }
+/// Types and names of the `in` globals a shadelang program declares, e.g.
+/// `in Vec3 normal`.
+fn shadelang_inputs(src: &str) -> Vec<(String, String)> {
+    src.lines()
+        .filter_map(|line| {
+            let mut tokens = line.split_whitespace();
+            match (tokens.next(), tokens.next(), tokens.next()) {
+                (Some("in"), Some(ty), Some(name)) => Some((ty.to_owned(), name.to_owned())),
+                _ => None,
+            }
+        })
+        .collect()
 }
 
-#[derive(Copy, Clone)]
-struct Bounds2 {
-    min_x: f32,
-    min_y: f32,
-    width: f32,
-    height: f32,
+/// A `sample(texture, uv)` call in a shadelang program, lowered to the `Vec3`
+/// input `input` that gets the RGB of the texture at the pixel's `uv` or `uv1`.
+#[derive(Debug)]
+struct ShadelangSample {
+    texture: String,
+    uv1: bool,
+    input: String,
 }
 
-fn calculate_triangle_bounds(tri: Tri2) -> Bounds2 {
-    let points = [tri.0, tri.1, tri.2];
+/// Replaces every `sample(texture, uv)` call with an input the renderer fills in
+/// per pixel, since the VM can't access textures itself, and declares those
+/// inputs at the top of the program. The texture has to be one of `textures`,
+/// the names bound by the scene's materials, and the UVs `uv` or `uv1` since
+/// those are the only coordinates the renderer interpolates. Calls in `//`
+/// comments are left alone.
+fn lower_shadelang_samples(
+    src: &str,
+    textures: &[&str],
+) -> Result<(String, Vec<ShadelangSample>), String> {
+    const CALL: &str = "sample(";
+    let is_ident = |c: char| c.is_alphanumeric() || c == '_';
+
+    let mut samples = Vec::<ShadelangSample>::new();
+    let mut body = String::new();
+    for (i, line) in src.lines().enumerate() {
+        let error = |what: String| format!("shadelang line {}: {}", i + 1, what);
+        let (mut rest, comment) = line.split_at(line.find("//").unwrap_or(line.len()));
+
+        while let Some(start) = rest.find(CALL) {
+            // Part of a longer name like `resample(`
+            if rest[..start].ends_with(is_ident) {
+                body.push_str(&rest[..start + CALL.len()]);
+                rest = &rest[start + CALL.len()..];
+                continue;
+            }
 
-    let mut min_x = 42000.0;
-    let mut max_x = 0.0;
-    let mut min_y = 42000.0;
-    let mut max_y = 0.0;
+            let args = &rest[start + CALL.len()..];
+            let end = args
+                .find(')')
+                .ok_or_else(|| error("sample( is not closed on the same line".into()))?;
+            let (texture, uv) = match args[..end].split(',').map(str::trim).collect::<Vec<_>>()[..]
+            {
+                [texture, uv] => (texture, uv),
+                _ => {
+                    return Err(error(format!(
+                        "sample({}) must take a texture and UVs",
+                        &args[..end]
+                    )))
+                }
+            };
+            if !textures.contains(&texture) {
+                return Err(error(format!(
+                    "sample({}, {}), no material binds a texture named '{}'",
+                    texture, uv, texture
+                )));
+            }
+            let uv1 = match uv {
+                "uv" => false,
+                "uv1" => true,
+                _ => {
+                    return Err(error(format!(
+                        "sample({}, {}) must use uv or uv1, other coordinates are not interpolated",
+                        texture, uv
+                    )))
+                }
+            };
 
-    for p in points.iter() {
-        if p.x < min_x {
-            min_x = p.x;
-        }
-        if p.x > max_x {
-            max_x = p.x;
-        }
-        if p.y < min_y {
-            min_y = p.y;
-        }
-        if p.y > max_y {
-            max_y = p.y;
+            let input = format!("sample_{}_{}", texture, uv);
+            if !samples.iter().any(|s| s.input == input) {
+                samples.push(ShadelangSample {
+                    texture: texture.to_owned(),
+                    uv1,
+                    input: input.clone(),
+                });
+            }
+
+            body.push_str(&rest[..start]);
+            body.push_str(&input);
+            rest = &args[end + 1..];
         }
+        body.push_str(rest);
+        body.push_str(comment);
+        body.push('\n');
     }
 
-    Bounds2 {
-        min_x,
-        min_y,
-        width: max_x - min_x,
-        height: max_y - min_y,
+    let mut lowered = String::new();
+    for sample in samples.iter() {
+        lowered.push_str(&format!("in Vec3 {}\n", sample.input));
     }
+    lowered.push_str(&body);
+    Ok((lowered, samples))
 }
 
-///
-///
-///
-fn rasterize_window_space<F>(tri: Tri3, mut cb: F)
-where
-    F: FnMut((u32, u32), (f32, f32, f32)) -> (),
-{
-    let bounds = calculate_triangle_bounds(tri.truncate());
-    let rast_min_x = bounds.min_x as u32;
-    let rast_max_x = (bounds.min_x + bounds.width + 1.0) as u32;
-    let rast_min_y = bounds.min_y as u32;
-    let rast_max_y = (bounds.min_y + bounds.height + 1.0) as u32;
-
-    for x in rast_min_x..rast_max_x {
-        for y in rast_min_y..rast_max_y {
-            let p = Vector2::new(x as f32 + 0.5, y as f32 + 0.5);
-
-            let v0 = tri.0.truncate();
-            let v1 = tri.1.truncate();
-            let v2 = tri.2.truncate();
-
-            let area = edge(v0, v1, v2);
-
-            let w0 = edge(p, v1, v2);
-            let w1 = edge(p, v2, v0);
-            let w2 = edge(p, v0, v1);
-
-            if (w0 <= 0.0) && (w1 <= 0.0) && (w2 <= 0.0) {
-                cb((x, y), (w0 / area, w1 / area, w2 / area))
-            }
+/// Hands a texture sample or vertex color to a shadelang input declared as `Vec3`
+/// or `Vec4`, a `Vec3` gets RGB.
+fn set_vector_input(vm: &mut vm::VirtualMachine, name: &str, ty: &str, s: Vector4<f32>) {
+    match ty {
+        "Vec3" => vm.set_global(name, [s.x, s.y, s.z]),
+        "Vec4" => vm.set_global(name, [s.x, s.y, s.z, s.w]),
+        _ => panic!("input {} must be a Vec3 or Vec4", name),
+    }
+}
+
+/// Picks the coarsest level of every LOD chain that stays within a pixel of the
+/// full mesh at its nearest instance, one level serves every instance of a mesh.
+/// Levels that changed since the last call replace the mesh in the scene.
+fn select_lods(
+    scene: &mut scene::Scene,
+    camera: &camera::Camera,
+    lods: &[(scene::MeshId, mesh::LodChain)],
+    viewport_height: u32,
+    selected: &mut HashMap<scene::MeshId, usize>,
+) {
+    let instances = scene.instances();
+    for (mesh, chain) in lods.iter() {
+        let nearest = instances
+            .iter()
+            .filter(|i| i.mesh == *mesh)
+            .map(|i| camera.transform.position.distance(i.bounds.sphere.center))
+            .fold(f32::INFINITY, f32::min);
+        let ppu = camera.projection.pixels_per_unit(nearest, viewport_height);
+        let level = chain.select_level(ppu, 1.0);
+        if selected.insert(*mesh, level) != Some(level) {
+            scene.set_mesh(*mesh, chain.levels[level].mesh.clone());
         }
     }
 }
 
 fn main() {
-    let im_dims = (800, 600);
+    let scene_path = std::env::args()
+        .nth(1)
+        .unwrap_or_else(|| "res/scenes/monkeys.ron".to_owned());
+    let scene::SceneSetup {
+        mut scene,
+        mut camera,
+        output,
+        shaders,
+        environment,
+        lods,
+        animation,
+        timeline,
+    } = scene::load_scene(PathBuf::from(scene_path)).unwrap_or_else(|e| panic!("{}", e));
+    let im_dims = (output.width, output.height);
+    let convention = camera.convention;
 
     let events_loop = glutin::event_loop::EventLoop::new();
 
@@ -182,87 +318,72 @@ fn main() {
         ))
         .with_title("Hello world");
 
+    // Shading happens in linear space, GL encodes sRGB on the way out like
+    // `Framebuffer::to_image` does
     let context = glutin::ContextBuilder::new()
+        .with_srgb(true)
         .build_windowed(wb, &events_loop)
         .unwrap();
     let context = unsafe { context.make_current().unwrap() };
 
     gl::load_with(|s| context.get_proc_address(s));
 
-    let mut imgbuf = image::ImageBuffer::new(im_dims.0, im_dims.1);
-
-    let mut camera = Camera::new(
-        Transform::default(),
-        PerspectiveFov {
-            fovy: Rad::from(Deg(75.0)),
-            aspect: 1280.0 / 720.0,
-            near: 0.1,
-            far: 1000.0,
-        },
-    );
-    camera.transform.position.z = -3.0;
-
-    let view = camera.get_view_matrix();
-    let proj = camera.get_projection_matrix();
+    opengl::apply_convention(&convention);
+    unsafe { gl::Enable(gl::FRAMEBUFFER_SRGB) };
 
-    let mesh = mesh::load_ply(PathBuf::from("res/mesh/monkey.ply"));
+    // Level of detail currently in the scene for every mesh with a LOD chain,
+    // picked again whenever the camera or the instances move
+    let mut lod_levels = HashMap::new();
 
-    let mut depth = vec![1.0; im_dims.0 as usize * im_dims.1 as usize];
-
-    struct Viewport {
-        x: i32,
-        y: i32,
-        width: u32,
-        height: u32,
-    }
+    let light = scene
+        .lights
+        .first()
+        .map_or_else(Vector3::zero, |l| l.shader_input());
 
-    let mut vao = 0;
-    let mut vbo = 0;
+    let vertex_layout = mesh::Vertex::layout();
 
-    unsafe {
-        gl::CreateVertexArrays(1, &mut vao);
-        gl::CreateBuffers(1, &mut vbo);
-        gl::BindVertexArray(vao);
+    // Vertex array, vertex count and morph target texture of every level of detail
+    // of every mesh, meshes without a LOD chain have just the one
+    let upload_mesh = |mesh: &mesh::Mesh| {
+        let mut vao = 0;
+        let mut vbo = 0;
 
-        gl::BindBuffer(gl::ARRAY_BUFFER, vbo);
-        gl::NamedBufferData(
-            vbo,
-            (std::mem::size_of::<mesh::Triangle>() * mesh.triangles.len()) as isize,
-            mesh.triangles.as_ptr() as *const GLvoid,
-            gl::STATIC_DRAW,
-        );
+        unsafe {
+            gl::CreateVertexArrays(1, &mut vao);
+            gl::CreateBuffers(1, &mut vbo);
+            gl::BindVertexArray(vao);
+
+            gl::BindBuffer(gl::ARRAY_BUFFER, vbo);
+            let vertices = mesh.vertices();
+            gl::NamedBufferData(
+                vbo,
+                std::mem::size_of_val(vertices) as isize,
+                vertices.as_ptr() as *const GLvoid,
+                gl::STATIC_DRAW,
+            );
 
-        gl::VertexAttribPointer(
-            0,
-            3,
-            gl::FLOAT,
-            0,
-            std::mem::size_of::<mesh::Vertex>() as i32,
-            std::ptr::null(),
-        );
-        gl::EnableVertexAttribArray(0);
+            vertex_layout.configure();
 
-        macro_rules! offset_of {
-            ($ty:ty, $field:ident) => {
-                &(*(0 as *const $ty)).$field as *const _ as usize
-            };
+            gl::BindVertexArray(0);
         }
 
-        gl::VertexAttribPointer(
-            1,
-            3,
-            gl::FLOAT,
-            0,
-            std::mem::size_of::<mesh::Vertex>() as i32,
-            offset_of!(mesh::Vertex, normal) as *const GLvoid,
-        );
-        gl::EnableVertexAttribArray(1);
+        let morph_targets = opengl::upload_morph_targets(mesh);
 
-        gl::BindVertexArray(0);
-    }
+        (vao, mesh.vertices().len() as GLsizei, morph_targets)
+    };
+    let gl_meshes: HashMap<_, Vec<(GLuint, GLsizei, Option<GLuint>)>> = scene
+        .meshes()
+        .map(|(id, mesh)| {
+            let levels = match lods.iter().find(|(m, _)| *m == id) {
+                Some((_, chain)) => chain.levels.iter().map(|l| upload_mesh(&l.mesh)).collect(),
+                None => vec![upload_mesh(mesh)],
+            };
+            (id, levels)
+        })
+        .collect();
 
     use std::io::Read;
-    pub fn read_file_contents(filename: &str) -> String {
+    pub fn read_file_contents(filename: &Path) -> String {
         let mut f = std::fs::File::open(filename).unwrap();
         let mut buffer = String::new();
         f.read_to_string(&mut buffer).unwrap();
@@ -271,25 +392,34 @@ fn main() {
 
     let shader = shader::Shader::new();
     shader
-        .attach(
-            &read_file_contents("res/shaders/glsl/basic.vs"),
-            gl::VERTEX_SHADER,
-        )
+        .attach(&read_file_contents(&shaders.vertex), gl::VERTEX_SHADER)
         .unwrap();
     shader
-        .attach(
-            &read_file_contents("res/shaders/glsl/basic.fs"),
-            gl::FRAGMENT_SHADER,
-        )
+        .attach(&read_file_contents(&shaders.fragment), gl::FRAGMENT_SHADER)
         .unwrap();
+    vertex_layout.bind_locations(&shader);
     shader.compile().unwrap();
+    if let Err(missing) = vertex_layout.check(&shader) {
+        panic!(
+            "Shader inputs not provided by the vertex layout: {:?}",
+            missing
+        );
+    }
     shader.bind();
 
+    let texture_names = scene
+        .materials()
+        .flat_map(|(_, material)| material.textures.iter().map(|b| b.name.as_str()))
+        .collect::<Vec<_>>();
+    let (shadelang_src, shadelang_samples) =
+        lower_shadelang_samples(&read_file_contents(&shaders.shadelang), &texture_names)
+            .unwrap_or_else(|e| panic!("{}", e));
+    let shadelang_inputs = shadelang_inputs(&shadelang_src);
     let shadelang_shader = {
-        let src = read_file_contents("res/shaders/shadelang/basic.sl");
+        let src = &shadelang_src;
         std::fs::create_dir_all("debug/shaders/basic/").ok();
 
-        let program = parser::parse(&src);
+        let program = parser::parse(src);
         std::fs::write("debug/shaders/basic/ast.rson", format!("{:#?}", program)).ok();
         {
             let mut program = program.clone();
@@ -307,141 +437,421 @@ fn main() {
     };
     let mut shadelang_vm = vm::VirtualMachine::new(&shadelang_shader);
 
-    let viewport = Viewport {
-        x: 0,
-        y: 0,
-        width: im_dims.0,
-        height: im_dims.1,
+    // Same texture and sampler state for the GL preview, to compare against
+    let gl_materials: HashMap<_, GlMaterial> = scene
+        .materials()
+        .map(|(id, material)| {
+            let textures = material
+                .textures
+                .iter()
+                .map(|b| {
+                    (
+                        b.name.clone(),
+                        opengl::upload_texture(&b.texture, &b.sampler),
+                    )
+                })
+                .collect();
+            let normal_map = material.normal_maps.first().map(|b| {
+                (
+                    opengl::upload_texture(&b.texture, &b.sampler),
+                    b.convention.green_sign(),
+                )
+            });
+            (
+                id,
+                GlMaterial {
+                    textures,
+                    normal_map,
+                },
+            )
+        })
+        .collect();
+
+    // The still image is the first frame, the rest of the timeline is only
+    // rendered when asked for
+    let frame_count = match &output.frames {
+        Some(frames) => {
+            if let Some(dir) = frames.parent() {
+                std::fs::create_dir_all(dir).unwrap();
+            }
+            timeline.frame_count()
+        }
+        None => 1,
     };
-
-    // clear
-    for (x, y, pixel) in imgbuf.enumerate_pixels_mut() {
-        let r = (0.3 * x as f32) as u8;
-        let b = (0.3 * y as f32) as u8;
-        *pixel = image::Rgb([r, 0, b]);
-    }
-
-    let begin = std::time::Instant::now();
-    for tri in mesh.triangles.iter() {
-        let mut t1_ndc = [
-            (proj * view * tri.0.position.extend(1.0)),
-            (proj * view * tri.1.position.extend(1.0)),
-            (proj * view * tri.2.position.extend(1.0)),
-        ];
-
-        for p in t1_ndc.iter_mut() {
-            p.x = p.x / p.w;
-            p.y = p.y / p.w;
-            p.z = p.z / p.w;
+    for frame in 0..frame_count {
+        let time = timeline.frame_time(frame);
+        if let Some(animation) = &animation {
+            animation.pose_scene(time, &mut scene);
+            animation.pose_camera(time, &mut camera);
         }
+        select_lods(&mut scene, &camera, &lods, im_dims.1, &mut lod_levels);
+        let view = camera.get_view_matrix();
+        let aspect = im_dims.0 as f32 / im_dims.1.max(1) as f32;
+        let (instances, stats) = scene.cull(&scene.instances(), &camera.frustum(aspect));
+        let eye = camera.transform.position.to_vec();
+
+        let mut graph = RenderGraph::new().with_convention(convention);
+
+        // Skybox, every pixel looks up the environment along its view ray. The sky
+        // is smooth enough to render at half resolution and filter up, so the
+        // projection keeps the aspect ratio of the full image.
+        let sky_dims = ((im_dims.0 / 2).max(1), (im_dims.1 / 2).max(1));
+        graph.add_pass("sky", sky_dims.0, sky_dims.1, &[], |target, _| {
+            let viewport = target.viewport();
+            let proj = camera.get_projection_matrix(aspect);
+            let inverse_view_proj = (proj * view).invert().unwrap();
+
+            for y in 0..target.height() {
+                for x in 0..target.width() {
+                    let unproject = |depth: f32| {
+                        let ndc = viewport.window_to_ndc(x as f32 + 0.5, y as f32 + 0.5, depth);
+                        let p = inverse_view_proj * ndc.extend(1.0);
+                        p.truncate() / p.w
+                    };
+
+                    let i = target.index(x, y);
+                    target.color[i] = match &environment {
+                        Some(sky) => sky.environment.sample_lod(
+                            &sky.sampler,
+                            unproject(1.0) - unproject(0.0),
+                            0.0,
+                        ),
+                        None => Vector4::new(0.0, 0.0, 0.0, 1.0),
+                    };
+                }
+            }
+        });
+
+        graph.add_pass("scene", im_dims.0, im_dims.1, &["sky"], |target, inputs| {
+            let sky = inputs.color_binding(
+                "sky",
+                texture::Sampler {
+                    filter: texture::Filter::Bilinear,
+                    wrap_u: texture::Wrap::Clamp,
+                    wrap_v: texture::Wrap::Clamp,
+                    ..Default::default()
+                },
+            );
+            for y in 0..target.height() {
+                for x in 0..target.width() {
+                    let i = target.index(x, y);
+                    target.color[i] =
+                        sky.texture
+                            .sample_lod(&sky.sampler, target.pixel_uv(x, y), 0.0);
+                }
+            }
+            let viewport = target.viewport();
+            let proj = camera.get_projection_matrix(viewport.aspect());
+
+            for instance in instances.iter() {
+                let material = scene.material(instance.material);
+                let palette = scene.joint_palette(instance);
+                let triangles = scene
+                    .mesh(instance.mesh)
+                    .morphed(&scene.node(instance.node).morph_weights);
+                for tri in triangles.iter() {
+                    let tri = match &palette {
+                        Some(palette) => palette.skin_triangle(tri),
+                        None => *tri,
+                    };
+                    let tri = &tri.transformed(&instance.model, &instance.normal_matrix);
+                    let t1_wnd = raster::Tri3(
+                        viewport.clip_to_window(proj * view * tri.0.position.extend(1.0)),
+                        viewport.clip_to_window(proj * view * tri.1.position.extend(1.0)),
+                        viewport.clip_to_window(proj * view * tri.2.position.extend(1.0)),
+                    );
+                    // `transformed` already swapped the corners of mirrored instances
+                    if !raster::is_front_facing(t1_wnd, &viewport, false) {
+                        continue;
+                    }
 
-        let t1_ndc: Vec<_> = t1_ndc.iter().map(|v| Vector4::truncate(*v)).collect();
-
-        let near_val = 0.0;
-        let far_val = 1.0;
-
-        let ndc_to_wnd = |p: Vector3<f32>| {
-            let (x_ndc, y_ndc, z_ndc) = p.into();
-            Vector3::new(
-                (viewport.width / 2) as f32 * x_ndc
-                    + viewport.x as f32
-                    + (viewport.width / 2) as f32,
-                (viewport.height / 2) as f32 * y_ndc
-                    + viewport.y as f32
-                    + (viewport.height / 2) as f32,
-                ((far_val - near_val) / 2.0) * z_ndc + ((far_val + near_val) / 2.0),
-            )
-        };
-
-        let t1_wnd = [
-            ndc_to_wnd(t1_ndc[0]),
-            ndc_to_wnd(t1_ndc[1]),
-            ndc_to_wnd(t1_ndc[2]),
-        ];
-
-        let t1_wnd = Tri3(t1_wnd[0], t1_wnd[1], t1_wnd[2]);
+                    raster::rasterize_window_space(t1_wnd, &viewport, |(x, y), (w0, w1, w2)| {
+                        let mut vm = shadelang_vm.clone();
+
+                        let i = target.index(x, y);
+
+                        let z = (t1_wnd.0.z, t1_wnd.1.z, t1_wnd.2.z);
+                        let w = (w0, w1, w2);
+                        let d = raster::interpolate_depth(z, w);
+                        let n = interpolate((tri.0.normal, tri.1.normal, tri.2.normal), z, w, d);
+
+                        if d < target.depth[i] {
+                            vm.set_global("normal", [n.x, n.y, n.z]);
+
+                            // The remaining varyings are only set if the shader declares them
+                            let declared_type = |name: &str| {
+                                shadelang_inputs
+                                    .iter()
+                                    .find(|(_, n)| n == name)
+                                    .map(|(ty, _)| ty.as_str())
+                            };
+                            let declared = |name: &str| declared_type(name).is_some();
+                            if declared("uv") {
+                                let uv = interpolate((tri.0.uv, tri.1.uv, tri.2.uv), z, w, d);
+                                vm.set_global("uv", [uv.x, uv.y]);
+                            }
+                            if declared("uv1") {
+                                let uv1 = interpolate((tri.0.uv1, tri.1.uv1, tri.2.uv1), z, w, d);
+                                vm.set_global("uv1", [uv1.x, uv1.y]);
+                            }
+                            if declared("light") {
+                                vm.set_global("light", [light.x, light.y, light.z]);
+                            }
+                            if let Some(ty) = declared_type("color") {
+                                let c =
+                                    interpolate((tri.0.color, tri.1.color, tri.2.color), z, w, d);
+                                set_vector_input(&mut vm, "color", ty, c);
+                            }
+
+                            let frame = || {
+                                let t = interpolate(
+                                    (tri.0.tangent, tri.1.tangent, tri.2.tangent),
+                                    z,
+                                    w,
+                                    d,
+                                );
+                                raster::tangent_frame(n, t)
+                            };
+                            if declared("tangent") {
+                                let (t, _, _) = frame();
+                                vm.set_global("tangent", [t.x, t.y, t.z]);
+                            }
+                            if declared("bitangent") {
+                                let (_, b, _) = frame();
+                                vm.set_global("bitangent", [b.x, b.y, b.z]);
+                            }
+
+                            // UVs with derivatives by finite differences to the neighbouring pixels
+                            let uv_grad = |uv1: bool| {
+                                let uvs = if uv1 {
+                                    (tri.0.uv1, tri.1.uv1, tri.2.uv1)
+                                } else {
+                                    (tri.0.uv, tri.1.uv, tri.2.uv)
+                                };
+                                let uv_at = |p: Vector2f| {
+                                    let w = raster::barycentric(t1_wnd.truncate(), p);
+                                    let d = raster::interpolate_depth(z, w);
+                                    interpolate(uvs, z, w, d)
+                                };
+                                let p = Vector2::new(x as f32 + 0.5, y as f32 + 0.5);
+                                let uv = uv_at(p);
+                                (
+                                    uv,
+                                    uv_at(p + Vector2::new(1.0, 0.0)) - uv,
+                                    uv_at(p + Vector2::new(0.0, 1.0)) - uv,
+                                )
+                            };
+
+                            for binding in material.textures.iter() {
+                                let ty = match declared_type(&binding.name) {
+                                    Some(ty) => ty,
+                                    None => continue,
+                                };
+
+                                let (uv, duv_dx, duv_dy) = uv_grad(false);
+                                let s = binding.texture.sample_grad(
+                                    &binding.sampler,
+                                    uv,
+                                    duv_dx,
+                                    duv_dy,
+                                );
+                                set_vector_input(&mut vm, &binding.name, ty, s);
+                            }
+
+                            for sample in shadelang_samples.iter() {
+                                let binding = match material
+                                    .textures
+                                    .iter()
+                                    .find(|b| b.name == sample.texture)
+                                {
+                                    Some(binding) => binding,
+                                    None => continue,
+                                };
+
+                                let (uv, duv_dx, duv_dy) = uv_grad(sample.uv1);
+                                let s = binding.texture.sample_grad(
+                                    &binding.sampler,
+                                    uv,
+                                    duv_dx,
+                                    duv_dy,
+                                );
+                                vm.set_global(&sample.input, [s.x, s.y, s.z]);
+                            }
+
+                            for binding in material.normal_maps.iter() {
+                                if !declared(&binding.name) {
+                                    continue;
+                                }
 
-        rasterize_window_space(t1_wnd, |(x, y), (w0, w1, w2)| {
-            let mut vm = shadelang_vm.clone();
+                                let (uv, duv_dx, duv_dy) = uv_grad(false);
+                                let s = binding.texture.sample_grad(
+                                    &binding.sampler,
+                                    uv,
+                                    duv_dx,
+                                    duv_dy,
+                                );
+                                let m = binding.convention.decode(s);
+                                let (t, b, n) = frame();
+                                let mapped = (t * m.x + b * m.y + n * m.z).normalize();
+                                vm.set_global(&binding.name, [mapped.x, mapped.y, mapped.z]);
+                            }
+
+                            for binding in environment.iter() {
+                                let ty = match declared_type(&binding.name) {
+                                    Some(ty) => ty,
+                                    None => continue,
+                                };
+
+                                let direction = match binding.lookup {
+                                    texture::Lookup::Normal => n,
+                                    texture::Lookup::Reflection => {
+                                        let p = interpolate(
+                                            (tri.0.position, tri.1.position, tri.2.position),
+                                            z,
+                                            w,
+                                            d,
+                                        );
+                                        let v = (p - eye).normalize();
+                                        let n = n.normalize();
+                                        v - n * (2.0 * n.dot(v))
+                                    }
+                                };
+
+                                let s = binding.environment.sample_lod(
+                                    &binding.sampler,
+                                    direction,
+                                    0.0,
+                                );
+                                set_vector_input(&mut vm, &binding.name, ty, s);
+                            }
+
+                            let result = vm.run_fn("main", vec![]);
+
+                            let mut vm = match result {
+                                VMState::BreakpointEncountered(s) => {
+                                    dbg!(s.breakpoint());
+                                    let stack = s.generate_stack_view();
+                                    dbg!(stack.current_fn);
+                                    stack.symbols.iter().for_each(|(id, (tk, bytes))| {
+                                        println!(
+                                            "{} [{:?}]: {}",
+                                            id,
+                                            tk,
+                                            match tk {
+                                                motokigo::ast::TypeKind::F32 => format!(
+                                                    "{}",
+                                                    bytemuck::from_bytes::<f32>(&bytes)
+                                                ),
+                                                motokigo::ast::TypeKind::Vector(_, _) => panic!(),
+                                                _ => panic!(),
+                                            }
+                                        );
+                                    });
+                                    std::process::exit(0);
+                                    s.resume().unwrap_vm()
+                                }
+                                VMState::VMRunFinished(s) => s.reset(),
+                            };
 
-            let i = im_dims.0 * y + x;
+                            let color: [f32; 3] = unsafe { vm.pop_stack() };
 
-            let interpolate_inverse = |(a, b, c), (w0, w1, w2)| {
-                let i = (1.0 / a) * (w0) + (1.0 / b) * (w1) + (1.0 / c) * (w2);
+                            target.color[i] = Vector4::new(color[0], color[1], color[2], 1.0);
+                            target.depth[i] = d;
+                        }
+                    })
+                }
+            }
+        });
 
-                1.0 / i
-            };
+        let begin = std::time::Instant::now();
+        let targets = graph
+            .execute(&["scene"])
+            .unwrap_or_else(|e| panic!("{}", e));
+        println!("{:?}, {}", Instant::now().duration_since(begin), stats);
 
-            let d = interpolate_inverse((t1_wnd.0.z, t1_wnd.1.z, t1_wnd.2.z), (w0, w1, w2));
-
-            let n = (tri.0.normal / t1_wnd.0.z) * w0 * d
-                + (tri.1.normal / t1_wnd.1.z) * w1 * d
-                + (tri.2.normal / t1_wnd.2.z) * w2 * d;
-
-            if d < depth[i as usize] {
-                // shadelang_vm.set_in_float("nx", n.x);
-                // shadelang_vm.set_in_float("ny", n.y);
-                // shadelang_vm.set_in_float("nz", n.z);
-
-                vm.set_global("normal", [n.x, n.y, n.z]);
-
-                let result = vm.run_fn("main", vec![]);
-
-                let mut vm = match result {
-                    VMState::BreakpointEncountered(s) => {
-                        dbg!(s.breakpoint());
-                        let stack = s.generate_stack_view();
-                        dbg!(stack.current_fn);
-                        stack.symbols.iter().for_each(|(id, (tk, bytes))| {
-                            println!(
-                                "{} [{:?}]: {}",
-                                id,
-                                tk,
-                                match tk {
-                                    motokigo::ast::TypeKind::F32 =>
-                                        format!("{}", bytemuck::from_bytes::<f32>(&bytes)),
-                                    motokigo::ast::TypeKind::Vector(_, _) => panic!(),
-                                    _ => panic!(),
-                                }
-                            );
-                        });
-                        std::process::exit(0);
-                        s.resume().unwrap_vm()
-                    }
-                    VMState::VMRunFinished(s) => s.reset(),
-                };
-
-                let color: [f32; 3] = unsafe { vm.pop_stack() };
-                // let cr = shadelang_vm.get_out_float("cr");
-                // let cg = shadelang_vm.get_out_float("cg");
-                // let cb = shadelang_vm.get_out_float("cb");
-
-                // let color = Vector3::new(cr, cg, cb);
-                // let color = Vector3::new(1.0, 0.0, 0.0);
-
-                *(imgbuf.get_pixel_mut(x, im_dims.1 - (y + 1))) = image::Rgb([
-                    (color[0] * 255.0) as u8,
-                    (color[1] * 255.0) as u8,
-                    (color[2] * 255.0) as u8,
-                ]);
-                depth[i as usize] = d;
-            }
-        })
+        let image = targets["scene"].to_image();
+        if frame == 0 {
+            image.save(&output.path).unwrap();
+        }
+        if let Some(frames) = &output.frames {
+            image.save(animation::frame_path(frames, frame)).unwrap();
+        }
     }
 
-    println!("{:?}", Instant::now().duration_since(begin));
-    imgbuf.save("output.png").unwrap();
-
-    use glutin::event::{Event, WindowEvent};
+    let target = scene
+        .bounding_sphere()
+        .map_or_else(Point3::origin, |s| s.center);
+    let mut controller = controller::CameraController::orbit(&camera, target);
+    let mut last_frame = Instant::now();
+    // Seconds of animation played, space pauses and resumes
+    let mut clock = 0.0;
+    let mut playing = true;
+    // Frame stats go in the window title whenever they change
+    let mut shown_stats = None;
+
+    use glutin::event::{ElementState, Event, KeyboardInput, VirtualKeyCode, WindowEvent};
     use glutin::event_loop::ControlFlow;
     events_loop.run(move |event, _, control_flow| match event {
         Event::LoopDestroyed => return,
         Event::WindowEvent { event, .. } => match event {
-            WindowEvent::Resized(physical_size) => context.resize(physical_size),
+            WindowEvent::Resized(physical_size) => {
+                context.resize(physical_size);
+                unsafe {
+                    gl::Viewport(
+                        0,
+                        0,
+                        physical_size.width as GLsizei,
+                        physical_size.height as GLsizei,
+                    );
+                }
+            }
             WindowEvent::CloseRequested => *control_flow = ControlFlow::Exit,
-            _ => (),
+            WindowEvent::KeyboardInput {
+                input:
+                    KeyboardInput {
+                        virtual_keycode: Some(VirtualKeyCode::Space),
+                        state: ElementState::Pressed,
+                        ..
+                    },
+                ..
+            } => {
+                playing = !playing;
+                // Takes over the camera from wherever the animation left it
+                if !playing {
+                    controller = controller::CameraController::orbit(&camera, controller.target);
+                }
+            }
+            event => controller.handle_event(&event),
         },
         Event::RedrawRequested(_) => {
+            let now = Instant::now();
+            let dt = now.duration_since(last_frame).as_secs_f32();
+            last_frame = now;
+
+            if playing {
+                clock += dt;
+            }
+            let time = timeline.time_at(clock);
+            if let Some(animation) = &animation {
+                animation.pose_scene(time, &mut scene);
+            }
+            match &animation {
+                Some(animation) if playing && animation.camera.is_some() => {
+                    animation.pose_camera(time, &mut camera)
+                }
+                _ => controller.update(&mut camera, dt),
+            }
+            let size = context.window().inner_size();
+            select_lods(&mut scene, &camera, &lods, size.height, &mut lod_levels);
+            let aspect = size.width as f32 / size.height.max(1) as f32;
+            let (instances, stats) = scene.cull(&scene.instances(), &camera.frustum(aspect));
+            if shown_stats != Some(stats) {
+                context
+                    .window()
+                    .set_title(&format!("Hello world - {}", stats));
+                shown_stats = Some(stats);
+            }
+
             unsafe {
                 gl::ClearColor(0.3, 0.0, 0.3, 1.0);
                 gl::Clear(gl::COLOR_BUFFER_BIT | gl::DEPTH_BUFFER_BIT);
@@ -449,14 +859,73 @@ fn main() {
 
             shader.bind();
             shader.set_uniform("view", camera.get_view_matrix());
-            shader.set_uniform("proj", camera.get_projection_matrix());
+            shader.set_uniform("light", light);
+            shader.set_uniform("proj", camera.get_projection_matrix(aspect));
 
             unsafe {
                 gl::Enable(gl::DEPTH_TEST);
                 gl::DepthFunc(gl::LESS);
+            }
 
-                gl::BindVertexArray(vao);
-                gl::DrawArrays(gl::TRIANGLES, 0, (mesh.triangles.len() * 3) as i32);
+            for instance in instances.iter() {
+                let material = &gl_materials[&instance.material];
+                for (unit, (name, texture)) in material.textures.iter().enumerate() {
+                    shader.set_uniform(name, unit as i32);
+                    unsafe { gl::BindTextureUnit(unit as GLuint, *texture) };
+                }
+                if let Some((texture, green)) = material.normal_map {
+                    let unit = material.textures.len();
+                    shader.set_uniform("normal_map", unit as i32);
+                    shader.set_uniform("normal_map_green", green);
+                    unsafe { gl::BindTextureUnit(unit as GLuint, texture) };
+                }
+
+                shader.set_uniform("model", instance.model);
+                shader.set_uniform("normal_matrix", instance.normal_matrix);
+                match scene.joint_palette(instance) {
+                    Some(palette) => {
+                        if palette.len() > opengl::MAX_JOINTS {
+                            panic!(
+                                "{} joints, the vertex shader takes at most {}",
+                                palette.len(),
+                                opengl::MAX_JOINTS
+                            );
+                        }
+                        let method = match palette {
+                            skinning::JointPalette::Linear(_) => 1,
+                            skinning::JointPalette::DualQuaternion(_) => 2,
+                        };
+                        shader.set_uniform("skinning", method);
+                        shader.set_uniform("joint_matrices", &palette.shader_matrices()[..]);
+                    }
+                    None => shader.set_uniform("skinning", 0),
+                }
+
+                // Always on a unit of its own, sampler types must not share units
+                let level = lod_levels.get(&instance.mesh).copied().unwrap_or(0);
+                let (vao, count, morph_targets) = gl_meshes[&instance.mesh][level];
+                let morph_unit = material.textures.len() + 1;
+                shader.set_uniform("morph_deltas", morph_unit as i32);
+                match morph_targets {
+                    Some(texture) => {
+                        let targets = scene.mesh(instance.mesh).morph_targets.len();
+                        let weights = &scene.node(instance.node).morph_weights;
+                        let (indices, weights) =
+                            opengl::active_morph_targets(&weights[..weights.len().min(targets)]);
+                        shader.set_uniform("morph_targets", targets as i32);
+                        shader.set_uniform("morph_count", indices.len() as i32);
+                        shader.set_uniform("morph_indices", &indices[..]);
+                        shader.set_uniform("morph_weights", &weights[..]);
+                        unsafe { gl::BindTextureUnit(morph_unit as GLuint, texture) };
+                    }
+                    None => shader.set_uniform("morph_count", 0),
+                }
+                opengl::set_front_face(&convention, instance.is_mirrored());
+
+                unsafe {
+                    gl::BindVertexArray(vao);
+                    gl::DrawArrays(gl::TRIANGLES, 0, count);
+                }
             }
 
             context.swap_buffers().unwrap();
@@ -465,3 +934,36 @@ fn main() {
         _ => (),
     });
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn lowers_samples_outside_comments_and_longer_names() {
+        let src = "Vec3 main() {\n    a = sample(albedo, uv) + sample( albedo ,uv )\n    b = resample(x) // sample(albedo, uv1)\n    return a * sample(detail, uv1)\n}\n";
+        let (lowered, samples) = lower_shadelang_samples(src, &["albedo", "detail"]).unwrap();
+        assert_eq!(
+            lowered,
+            "in Vec3 sample_albedo_uv\nin Vec3 sample_detail_uv1\nVec3 main() {\n    a = sample_albedo_uv + sample_albedo_uv\n    b = resample(x) // sample(albedo, uv1)\n    return a * sample_detail_uv1\n}\n"
+        );
+        assert_eq!(samples.len(), 2);
+        assert_eq!(
+            (samples[0].texture.as_str(), samples[0].uv1),
+            ("albedo", false)
+        );
+        assert_eq!(
+            (samples[1].texture.as_str(), samples[1].uv1),
+            ("detail", true)
+        );
+    }
+
+    #[test]
+    fn reports_calls_it_cannot_lower() {
+        let error = |src: &str| lower_shadelang_samples(src, &["albedo"]).unwrap_err();
+        assert!(error("a = 1\nb = sample(albedo, uv").starts_with("shadelang line 2:"));
+        assert!(error("b = sample(albedo)").contains("texture and UVs"));
+        assert!(error("b = sample(normals, uv)").contains("'normals'"));
+        assert!(error("b = sample(albedo, uv * 2.0)").contains("uv or uv1"));
+    }
+}
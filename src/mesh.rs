@@ -1,119 +1,813 @@
+use crate::bounds::{Aabb, BoundingSphere, Bounds};
 use cgmath::prelude::*;
-use cgmath::{Vector2, Vector3};
+use cgmath::{Matrix3, Matrix4, Point3, Rad, Vector2, Vector3, Vector4};
 
-use std::fs;
-use std::path::PathBuf;
+use std::borrow::Cow;
+use std::collections::HashMap;
 
-#[derive(Clone, Debug)]
+pub mod error;
+pub mod obj;
+pub mod ply;
+pub mod simplify;
+pub mod stl;
+
+pub use error::{MeshError, MeshErrorKind};
+pub use obj::{load_obj, parse_obj, read_obj, save_obj, write_obj};
+pub use ply::{load_ply, parse_ply, read_ply, save_ply, write_ply, PlyFormat};
+pub use simplify::{Lod, LodChain};
+pub use stl::{load_stl, parse_stl, read_stl, save_stl, write_stl, StlFormat};
+
+#[derive(Copy, Clone, Debug)]
+#[repr(C)]
 pub struct Vertex {
     pub position: Vector3<f32>,
     pub normal: Vector3<f32>,
     pub uv: Vector2<f32>,
-    pub tangent: Vector3<f32>,
+    /// Tangent along increasing u, w is the handedness of the bitangent so that
+    /// `bitangent = w * cross(normal, tangent)`.
+    pub tangent: Vector4<f32>,
+    /// Linear RGBA, white for meshes without vertex colors.
+    pub color: Vector4<f32>,
+    /// Second UV set, usually a lightmap layout.
+    pub uv1: Vector2<f32>,
+    /// Indices into the joints of the skeleton the mesh is skinned to.
+    pub joints: Vector4<u32>,
+    /// Influence of each of `joints`, all zero for vertices that are not skinned.
+    pub weights: Vector4<f32>,
 }
 
 impl Vertex {
-    pub fn calculate_tangent(x: Vertex, y: Vertex, z: Vertex) -> Vector3<f32> {
-        let edge1 = y.position - x.position;
-        let edge2 = z.position - x.position;
-
-        let uv1 = y.uv - x.uv;
-        let uv2 = z.uv - x.uv;
+    /// A vertex at `position` with every other attribute at its default.
+    pub fn new(position: Vector3<f32>) -> Vertex {
+        Vertex {
+            position,
+            normal: Vector3::new(0.0, 0.0, 0.0),
+            uv: Vector2::new(0.0, 0.0),
+            tangent: Vector4::new(0.0, 0.0, 0.0, 0.0),
+            color: Vector4::new(1.0, 1.0, 1.0, 1.0),
+            uv1: Vector2::new(0.0, 0.0),
+            joints: Vector4::new(0, 0, 0, 0),
+            weights: Vector4::new(0.0, 0.0, 0.0, 0.0),
+        }
+    }
 
-        let f = 1.0 / (uv1.x * uv2.y - uv2.x * uv1.y);
-        let mut tangent = Vector3::new(0.0, 0.0, 0.0);
-        tangent.x = f * (uv2.y * edge1.x - uv1.y * edge2.x);
-        tangent.y = f * (uv2.y * edge1.y - uv1.y * edge2.y);
-        tangent.z = f * (uv2.y * edge1.z - uv1.y * edge2.z);
+    /// The vertex moved by a model matrix, `normal_matrix` is the inverse
+    /// transpose of its upper 3x3. Normal and tangent come out unit length
+    /// again, or zero if they were degenerate.
+    pub fn transformed(&self, model: &Matrix4<f32>, normal_matrix: &Matrix3<f32>) -> Vertex {
+        let linear = Matrix3::from_cols(model.x.truncate(), model.y.truncate(), model.z.truncate());
+        // A mirroring flips the bitangent relative to normal and tangent
+        let handedness = linear.determinant().signum();
 
-        tangent.normalize()
+        Vertex {
+            position: (model * self.position.extend(1.0)).truncate(),
+            normal: safe_normalize(normal_matrix * self.normal),
+            tangent: safe_normalize(linear * self.tangent.truncate())
+                .extend(self.tangent.w * handedness),
+            ..*self
+        }
     }
 }
 
-#[derive(Debug)]
+// Safe because every field is a repr(C) cgmath vector of f32 or u32, so there is
+// no padding and any bit pattern is valid
+unsafe impl bytemuck::Zeroable for Vertex {}
+unsafe impl bytemuck::Pod for Vertex {}
+
+/// Laid out as three consecutive vertices, so a slice of triangles can be cast to
+/// a slice of vertices and drawn with `GL_TRIANGLES`.
+#[derive(Copy, Clone, Debug)]
+#[repr(C)]
 pub struct Triangle(pub Vertex, pub Vertex, pub Vertex);
 
-#[derive(Debug)]
+unsafe impl bytemuck::Zeroable for Triangle {}
+unsafe impl bytemuck::Pod for Triangle {}
+
+impl Triangle {
+    /// Unnormalized facet normal, its length is twice the triangle's area.
+    pub fn face_normal(&self) -> Vector3<f32> {
+        (self.1.position - self.0.position).cross(self.2.position - self.0.position)
+    }
+
+    /// The triangle moved by a model matrix, see `Vertex::transformed`. Mirroring
+    /// matrices also swap two corners, so the same side stays in front.
+    pub fn transformed(&self, model: &Matrix4<f32>, normal_matrix: &Matrix3<f32>) -> Triangle {
+        let (a, b, c) = (
+            self.0.transformed(model, normal_matrix),
+            self.1.transformed(model, normal_matrix),
+            self.2.transformed(model, normal_matrix),
+        );
+        if normal_matrix.determinant() < 0.0 {
+            Triangle(a, c, b)
+        } else {
+            Triangle(a, b, c)
+        }
+    }
+}
+
+/// How facet normals are weighted when averaged into a smooth vertex normal.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum NormalWeighting {
+    /// Large triangles dominate.
+    Area,
+    /// Weighted by the triangle's angle at the vertex, independent of tessellation.
+    Angle,
+}
+
+/// How vertex normals are derived from the triangle geometry.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum NormalMode {
+    /// Every corner of a triangle gets the facet normal.
+    Flat,
+    /// Corners sharing a position get the weighted average of their facet normals.
+    /// Facets meeting at more than `crease_angle` are not averaged, which splits
+    /// the vertex along hard edges.
+    Smooth {
+        weighting: NormalWeighting,
+        crease_angle: Option<Rad<f32>>,
+    },
+}
+
+impl Default for NormalMode {
+    fn default() -> NormalMode {
+        NormalMode::Smooth {
+            weighting: NormalWeighting::Angle,
+            crease_angle: None,
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
 pub struct Mesh {
     pub triangles: Vec<Triangle>,
+    /// Shapes the mesh can blend towards, see `morphed`.
+    pub morph_targets: Vec<MorphTarget>,
+}
+
+/// Offsets from a mesh to another shape of it, blended in by a weight. There is a
+/// delta for every triangle corner, in the order of `Mesh::vertices`.
+#[derive(Clone, Debug, Default)]
+pub struct MorphTarget {
+    pub name: String,
+    pub position_deltas: Vec<Vector3<f32>>,
+    /// Empty if the shape keeps the normals of the mesh.
+    pub normal_deltas: Vec<Vector3<f32>>,
+}
+
+/// A mesh with shared vertices, the representation used by indexed file formats.
+#[derive(Clone, Debug, Default)]
+pub struct IndexedMesh {
+    pub vertices: Vec<Vertex>,
+    pub faces: Vec<[u32; 3]>,
+}
+
+impl IndexedMesh {
+    /// Generates per-vertex tangents following the MikkTSpace conventions.
+    ///
+    /// Face tangents are accumulated with the corner angle as weight, then
+    /// orthogonalized against the vertex normal. Vertices without usable UVs get an
+    /// arbitrary tangent perpendicular to their normal instead of NaNs.
+    pub fn generate_tangents(&mut self) {
+        let zero = Vector3::new(0.0, 0.0, 0.0);
+        let mut tangents = vec![zero; self.vertices.len()];
+        let mut bitangents = vec![zero; self.vertices.len()];
+
+        for face in self.faces.iter() {
+            let v = [
+                &self.vertices[face[0] as usize],
+                &self.vertices[face[1] as usize],
+                &self.vertices[face[2] as usize],
+            ];
+
+            let edge1 = v[1].position - v[0].position;
+            let edge2 = v[2].position - v[0].position;
+            let uv1 = v[1].uv - v[0].uv;
+            let uv2 = v[2].uv - v[0].uv;
+
+            let det = uv1.x * uv2.y - uv2.x * uv1.y;
+            if det.abs() <= f32::EPSILON * (uv1.magnitude2() + uv2.magnitude2()) {
+                // Degenerate UV mapping, this face has no defined tangent
+                continue;
+            }
+
+            // The determinant's sign is kept in the bitangent, which is what
+            // mirrored UVs flip
+            let tangent = safe_normalize((edge1 * uv2.y - edge2 * uv1.y) / det);
+            let bitangent = safe_normalize((edge2 * uv1.x - edge1 * uv2.x) / det);
+
+            for corner in 0..3 {
+                let p = v[corner].position;
+                let a = v[(corner + 1) % 3].position - p;
+                let b = v[(corner + 2) % 3].position - p;
+                let weight = corner_angle(a, b);
+
+                let i = face[corner] as usize;
+                tangents[i] += tangent * weight;
+                bitangents[i] += bitangent * weight;
+            }
+        }
+
+        for (i, v) in self.vertices.iter_mut().enumerate() {
+            let n = v.normal;
+            let t = safe_normalize(tangents[i] - n * n.dot(tangents[i]));
+            let t = if t == zero { perpendicular(n) } else { t };
+
+            let w = if n.cross(t).dot(bitangents[i]) < 0.0 {
+                -1.0
+            } else {
+                1.0
+            };
+
+            v.tangent = t.extend(w);
+        }
+    }
+
+    pub fn to_mesh(&self) -> Mesh {
+        let v = |i: u32| self.vertices[i as usize];
+        Mesh::new(
+            self.faces
+                .iter()
+                .map(|f| Triangle(v(f[0]), v(f[1]), v(f[2])))
+                .collect(),
+        )
+    }
 }
 
 impl Mesh {
     pub fn new(triangles: Vec<Triangle>) -> Self {
-        Mesh { triangles }
+        Mesh {
+            triangles,
+            morph_targets: Vec::new(),
+        }
+    }
+
+    /// All triangle corners in order, ready to be drawn with `GL_TRIANGLES`.
+    pub fn vertices(&self) -> &[Vertex] {
+        bytemuck::cast_slice(&self.triangles)
+    }
+
+    /// Merges vertices whose attributes are bit for bit identical. Tangents are
+    /// derived data and do not prevent merging.
+    pub fn to_indexed(&self) -> IndexedMesh {
+        let mut indexed = IndexedMesh::default();
+        let mut lookup = HashMap::<[u32; 22], u32>::new();
+
+        for tri in self.triangles.iter() {
+            let mut face = [0; 3];
+            for (i, v) in [&tri.0, &tri.1, &tri.2].iter().enumerate() {
+                let vertices = &mut indexed.vertices;
+                face[i] = *lookup.entry(vertex_key(v)).or_insert_with(|| {
+                    vertices.push(**v);
+                    (vertices.len() - 1) as u32
+                });
+            }
+            indexed.faces.push(face);
+        }
+
+        indexed
+    }
+
+    /// See `IndexedMesh::generate_tangents`, tangents are accumulated over
+    /// vertices that are otherwise identical.
+    pub fn generate_tangents(&mut self) {
+        let mut indexed = self.to_indexed();
+        indexed.generate_tangents();
+        // Corners come back in the same order, so the morph targets still fit
+        self.triangles = indexed.to_mesh().triangles;
+    }
+
+    /// Replaces all vertex normals with ones computed from the triangle geometry.
+    pub fn generate_normals(&mut self, mode: NormalMode) {
+        let (weighting, crease_angle) = match mode {
+            NormalMode::Flat => {
+                for tri in self.triangles.iter_mut() {
+                    let n = safe_normalize(tri.face_normal());
+                    tri.0.normal = n;
+                    tri.1.normal = n;
+                    tri.2.normal = n;
+                }
+                return;
+            }
+            NormalMode::Smooth {
+                weighting,
+                crease_angle,
+            } => (weighting, crease_angle),
+        };
+
+        let face_normals = self
+            .triangles
+            .iter()
+            .map(|tri| safe_normalize(tri.face_normal()))
+            .collect::<Vec<_>>();
+
+        // Welds corners by their exact position, which is what STL and
+        // other unindexed formats produce for shared vertices
+        let mut corners = HashMap::<[u32; 3], Vec<(usize, f32)>>::new();
+        for (i, tri) in self.triangles.iter().enumerate() {
+            let p = [tri.0.position, tri.1.position, tri.2.position];
+            for corner in 0..3 {
+                let weight = match weighting {
+                    NormalWeighting::Area => tri.face_normal().magnitude() * 0.5,
+                    NormalWeighting::Angle => corner_angle(
+                        p[(corner + 1) % 3] - p[corner],
+                        p[(corner + 2) % 3] - p[corner],
+                    ),
+                };
+                corners
+                    .entry(position_key(p[corner]))
+                    .or_default()
+                    .push((i, weight));
+            }
+        }
+
+        let min_cos = crease_angle.map(Rad::cos);
+        for (i, tri) in self.triangles.iter_mut().enumerate() {
+            let flat = face_normals[i];
+            for v in [&mut tri.0, &mut tri.1, &mut tri.2].iter_mut() {
+                let mut sum = Vector3::new(0.0, 0.0, 0.0);
+                for &(j, weight) in corners[&position_key(v.position)].iter() {
+                    let smooth = match min_cos {
+                        Some(min_cos) => flat.dot(face_normals[j]) >= min_cos,
+                        None => true,
+                    };
+                    if smooth {
+                        sum += face_normals[j] * weight;
+                    }
+                }
+
+                let n = safe_normalize(sum);
+                v.normal = if n == Vector3::new(0.0, 0.0, 0.0) {
+                    flat
+                } else {
+                    n
+                };
+            }
+        }
     }
 }
 
-pub fn load_ply(path: PathBuf) -> Mesh {
-    let buffer = fs::read_to_string(path).unwrap();
+// Morph targets
+impl Mesh {
+    /// Target turning this mesh into `shape`, which has to have the same triangles
+    /// in the same order, like the meshes of a PLY set exported from one base.
+    /// `None` if the triangle counts differ.
+    pub fn morph_target_to(&self, name: &str, shape: &Mesh) -> Option<MorphTarget> {
+        if shape.triangles.len() != self.triangles.len() {
+            return None;
+        }
+
+        let pairs = || self.vertices().iter().zip(shape.vertices());
+        Some(MorphTarget {
+            name: name.to_owned(),
+            position_deltas: pairs().map(|(a, b)| b.position - a.position).collect(),
+            normal_deltas: pairs().map(|(a, b)| b.normal - a.normal).collect(),
+        })
+    }
 
-    let mut vertices = Vec::<Vertex>::new();
-    let mut faces = Vec::<Triangle>::new();
-    let mut lines = buffer.lines();
+    pub fn find_morph_target(&self, name: &str) -> Option<usize> {
+        self.morph_targets.iter().position(|t| t.name == name)
+    }
+
+    /// Triangles with every morph target added in by its weight, indexed like
+    /// `morph_targets` with missing weights counting as zero. Normals are
+    /// renormalized, tangents are left as they are.
+    pub fn morphed(&self, weights: &[f32]) -> Cow<'_, [Triangle]> {
+        let active = || {
+            self.morph_targets
+                .iter()
+                .zip(weights.iter().copied())
+                .filter(|(_, weight)| *weight != 0.0)
+        };
+        if active().next().is_none() {
+            return Cow::Borrowed(&self.triangles);
+        }
 
-    // Parse header
-    'header: while let Some(line) = lines.next() {
-        let mut tokens = line.split_whitespace();
+        let mut triangles = self.triangles.clone();
+        let vertices: &mut [Vertex] = bytemuck::cast_slice_mut(&mut triangles);
+        for (target, weight) in active() {
+            for (v, d) in vertices.iter_mut().zip(target.position_deltas.iter()) {
+                v.position += d * weight;
+            }
+            for (v, d) in vertices.iter_mut().zip(target.normal_deltas.iter()) {
+                v.normal += d * weight;
+            }
+        }
+        for v in vertices.iter_mut() {
+            v.normal = safe_normalize(v.normal);
+        }
+        Cow::Owned(triangles)
+    }
 
-        match tokens.next().unwrap() {
-            "element" => match tokens.next().unwrap() {
-                "vertex" => {
-                    vertices.reserve_exact(tokens.next().unwrap().parse::<usize>().unwrap())
+    /// Keeps the triangles `keep` returns true for, along with their deltas.
+    fn retain_triangles(&mut self, keep: impl FnMut(&Triangle) -> bool) {
+        let kept: Vec<bool> = self.triangles.iter().map(keep).collect();
+        let retain = |deltas: &mut Vec<Vector3<f32>>| {
+            let mut corner = 0;
+            deltas.retain(|_| {
+                corner += 1;
+                kept[(corner - 1) / 3]
+            });
+        };
+        for target in self.morph_targets.iter_mut() {
+            retain(&mut target.position_deltas);
+            retain(&mut target.normal_deltas);
+        }
+
+        let mut i = 0;
+        self.triangles.retain(|_| {
+            i += 1;
+            kept[i - 1]
+        });
+    }
+}
+
+// Mesh clean up and placement
+impl Mesh {
+    /// Merges vertices whose positions are within `epsilon` of each other and whose
+    /// normals, UVs, colors and skin weights match within the same tolerance, so
+    /// UV seams, hard edges and color borders survive. Joints have to be the same.
+    /// Merged corners take on all attributes of the first vertex seen, tangents
+    /// are not compared since they get regenerated.
+    pub fn weld(&mut self, epsilon: f32) {
+        let epsilon = epsilon.max(f32::EPSILON);
+        let cell_of = |p: Vector3<f32>| {
+            [
+                (p.x / epsilon).floor() as i64,
+                (p.y / epsilon).floor() as i64,
+                (p.z / epsilon).floor() as i64,
+            ]
+        };
+        let matches = |a: &Vertex, b: &Vertex| {
+            (a.position - b.position).magnitude2() <= epsilon * epsilon
+                && (a.normal - b.normal).magnitude2() <= epsilon * epsilon
+                && (a.uv - b.uv).magnitude2() <= epsilon * epsilon
+                && (a.uv1 - b.uv1).magnitude2() <= epsilon * epsilon
+                && (a.color - b.color).magnitude2() <= epsilon * epsilon
+                && (a.weights - b.weights).magnitude2() <= epsilon * epsilon
+                && a.joints == b.joints
+        };
+
+        let mut welded = Vec::<Vertex>::new();
+        let mut grid = HashMap::<[i64; 3], Vec<usize>>::new();
+
+        for tri in self.triangles.iter_mut() {
+            for v in [&mut tri.0, &mut tri.1, &mut tri.2].iter_mut() {
+                let cell = cell_of(v.position);
+
+                // Anything within epsilon lies in one of the 27 surrounding cells
+                let mut found = None;
+                'search: for dx in -1..=1 {
+                    for dy in -1..=1 {
+                        for dz in -1..=1 {
+                            let neighbour = [cell[0] + dx, cell[1] + dy, cell[2] + dz];
+                            for &i in grid.get(&neighbour).into_iter().flatten() {
+                                if matches(&welded[i], v) {
+                                    found = Some(i);
+                                    break 'search;
+                                }
+                            }
+                        }
+                    }
                 }
-                _ => {}
-            },
-            "end_header" => break 'header,
-            _ => {}
+
+                match found {
+                    Some(i) => **v = welded[i],
+                    None => {
+                        grid.entry(cell).or_default().push(welded.len());
+                        welded.push(**v);
+                    }
+                }
+            }
         }
     }
 
-    // Parse vertices
-    for _ in 0..vertices.capacity() {
-        let line = lines.next().unwrap();
-        let tokens = line.split_whitespace();
-        let values = tokens
-            .map(|t| t.parse::<f32>().unwrap())
-            .collect::<Vec<_>>();
-        vertices.push(Vertex {
-            position: Vector3::new(values[0], values[1], values[2]),
-            normal: Vector3::new(values[3], values[4], values[5]),
-            uv: Vector2::new(
-                *values.get(6).unwrap_or(&0.0),
-                *values.get(7).unwrap_or(&0.0),
-            ),
-            tangent: Vector3::new(0.0, 0.0, 0.0),
+    /// Removes triangles with (numerically) zero area, returns how many were removed.
+    pub fn remove_degenerate_triangles(&mut self) -> usize {
+        let count = self.triangles.len();
+        self.retain_triangles(|tri| {
+            let n = tri.face_normal();
+            let scale = (tri.1.position - tri.0.position)
+                .magnitude2()
+                .max((tri.2.position - tri.0.position).magnitude2());
+            n.magnitude() > scale * f32::EPSILON
+        });
+        count - self.triangles.len()
+    }
+
+    /// Removes triangles using the same three positions with the same winding as an
+    /// earlier triangle, returns how many were removed. Back to back triangles with
+    /// opposite winding are kept.
+    pub fn remove_duplicate_triangles(&mut self) -> usize {
+        let count = self.triangles.len();
+        let mut seen = std::collections::HashSet::new();
+        self.retain_triangles(|tri| {
+            let mut key = [
+                position_key(tri.0.position),
+                position_key(tri.1.position),
+                position_key(tri.2.position),
+            ];
+            // Rotate the smallest corner to the front, which keeps the winding
+            let first = (0..3).min_by_key(|i| key[*i]).unwrap();
+            key.rotate_left(first);
+            seen.insert(key)
         });
+        count - self.triangles.len()
     }
 
-    // Parse faces
-    while let Some(line) = lines.next() {
-        let tokens = line.split_whitespace();
-        let values = tokens
-            .map(|t| t.parse::<u32>().unwrap())
-            .collect::<Vec<u32>>();
+    pub fn bounds(&self) -> Aabb {
+        Aabb::from_points(self.positions())
+    }
 
-        match values[0] {
-            3 => {
-                let face = [values[1], values[2], values[3]];
+    pub fn bounding_sphere(&self) -> BoundingSphere {
+        BoundingSphere::from_points(&self.positions().collect::<Vec<_>>())
+    }
+
+    /// Box and sphere around every shape the morph targets can blend to with
+    /// weights between 0 and 1, `bounds` and `bounding_sphere` without targets.
+    pub fn morph_bounds(&self) -> Bounds {
+        let mut bounds = Bounds::from_points(&self.positions().collect::<Vec<_>>());
+        if self.morph_targets.is_empty() {
+            return bounds;
+        }
 
-                let tangent = Vertex::calculate_tangent(
-                    vertices[face[0] as usize].clone(),
-                    vertices[face[1] as usize].clone(),
-                    vertices[face[2] as usize].clone(),
-                );
-                vertices[face[0] as usize].tangent = tangent;
-                vertices[face[1] as usize].tangent = tangent;
-                vertices[face[2] as usize].tangent = tangent;
+        // Per axis the corner moves at most by the sum of the deltas pointing one
+        // way, and by no more than the sum of their lengths overall
+        let zero = Vector3::new(0.0, 0.0, 0.0);
+        let mut furthest = 0.0f32;
+        for (i, v) in self.vertices().iter().enumerate() {
+            let (mut low, mut high, mut length) = (zero, zero, 0.0);
+            for target in self.morph_targets.iter() {
+                if let Some(d) = target.position_deltas.get(i) {
+                    low += Vector3::new(d.x.min(0.0), d.y.min(0.0), d.z.min(0.0));
+                    high += Vector3::new(d.x.max(0.0), d.y.max(0.0), d.z.max(0.0));
+                    length += d.magnitude();
+                }
+            }
+            bounds.aabb.include(Point3::from_vec(v.position + low));
+            bounds.aabb.include(Point3::from_vec(v.position + high));
+            furthest = furthest.max(length);
+        }
+        bounds.sphere.radius += furthest;
+        bounds
+    }
+
+    pub fn translate(&mut self, offset: Vector3<f32>) {
+        for tri in self.triangles.iter_mut() {
+            for v in [&mut tri.0, &mut tri.1, &mut tri.2].iter_mut() {
+                v.position += offset;
+            }
+        }
+    }
+
+    /// Uniform scale about the origin. Normals and tangents are unaffected.
+    pub fn scale(&mut self, factor: f32) {
+        for tri in self.triangles.iter_mut() {
+            for v in [&mut tri.0, &mut tri.1, &mut tri.2].iter_mut() {
+                v.position *= factor;
+            }
+        }
+        for target in self.morph_targets.iter_mut() {
+            for d in target.position_deltas.iter_mut() {
+                *d *= factor;
+            }
+        }
+    }
+
+    /// Moves the center of the bounding box to the origin.
+    pub fn recenter(&mut self) {
+        let bounds = self.bounds();
+        if !bounds.is_empty() {
+            self.translate(-bounds.center().to_vec());
+        }
+    }
+
+    /// Recenters the mesh and scales it uniformly so it fits the box from
+    /// -0.5 to 0.5 with its largest side touching the faces of the box.
+    pub fn normalize(&mut self) {
+        self.recenter();
+
+        let size = self.bounds().size();
+        let largest = size.x.max(size.y).max(size.z);
+        if largest > 0.0 && largest.is_finite() {
+            self.scale(1.0 / largest);
+        }
+    }
 
-                faces.push(Triangle(
-                    vertices[values[1] as usize].clone(),
-                    vertices[values[2] as usize].clone(),
-                    vertices[values[3] as usize].clone(),
-                ));
+    /// Reverses the winding of every triangle and turns the normals around to
+    /// match. Tangent handedness is flipped so the bitangents stay the same.
+    pub fn flip_winding(&mut self) {
+        for tri in self.triangles.iter_mut() {
+            std::mem::swap(&mut tri.1, &mut tri.2);
+            for v in [&mut tri.0, &mut tri.1, &mut tri.2].iter_mut() {
+                v.normal = -v.normal;
+                v.tangent.w = -v.tangent.w;
+            }
+        }
+        for target in self.morph_targets.iter_mut() {
+            for corners in target.position_deltas.chunks_exact_mut(3) {
+                corners.swap(1, 2);
+            }
+            for corners in target.normal_deltas.chunks_exact_mut(3) {
+                corners.swap(1, 2);
+                corners.iter_mut().for_each(|d| *d = -*d);
             }
-            _ => {}
         }
     }
 
-    Mesh::new(faces)
+    fn positions(&self) -> impl Iterator<Item = Point3<f32>> + '_ {
+        self.triangles.iter().flat_map(|tri| {
+            vec![tri.0.position, tri.1.position, tri.2.position]
+                .into_iter()
+                .map(Point3::from_vec)
+        })
+    }
+}
+
+/// Shared tail of the loaders, generates normals with the default mode if the
+/// file did not provide any and then generates tangents.
+fn finish_loading(mut mesh: IndexedMesh, has_normals: bool) -> Mesh {
+    if has_normals {
+        mesh.generate_tangents();
+        mesh.to_mesh()
+    } else {
+        let mut mesh = mesh.to_mesh();
+        mesh.generate_normals(NormalMode::default());
+        mesh.generate_tangents();
+        mesh
+    }
+}
+
+fn vertex_key(v: &Vertex) -> [u32; 22] {
+    let p = position_key(v.position);
+    let bits = |f: f32| f.to_bits();
+    [
+        p[0],
+        p[1],
+        p[2],
+        bits(v.normal.x),
+        bits(v.normal.y),
+        bits(v.normal.z),
+        bits(v.uv.x),
+        bits(v.uv.y),
+        bits(v.color.x),
+        bits(v.color.y),
+        bits(v.color.z),
+        bits(v.color.w),
+        bits(v.uv1.x),
+        bits(v.uv1.y),
+        bits(v.weights.x),
+        bits(v.weights.y),
+        bits(v.weights.z),
+        bits(v.weights.w),
+        v.joints.x,
+        v.joints.y,
+        v.joints.z,
+        v.joints.w,
+    ]
+}
+
+fn position_key(p: Vector3<f32>) -> [u32; 3] {
+    // +0.0 and -0.0 must weld together
+    [
+        (p.x + 0.0).to_bits(),
+        (p.y + 0.0).to_bits(),
+        (p.z + 0.0).to_bits(),
+    ]
+}
+
+/// Angle between two edges leaving a corner, zero for degenerate edges.
+fn corner_angle(a: Vector3<f32>, b: Vector3<f32>) -> f32 {
+    let cos = safe_normalize(a).dot(safe_normalize(b));
+    cos.clamp(-1.0, 1.0).acos()
+}
+
+/// Some unit vector perpendicular to `n`, or the x axis if `n` is zero.
+fn perpendicular(n: Vector3<f32>) -> Vector3<f32> {
+    // Crossing with the axis least aligned with n keeps the result well conditioned
+    let axis = if n.x.abs() <= n.y.abs() && n.x.abs() <= n.z.abs() {
+        Vector3::new(1.0, 0.0, 0.0)
+    } else if n.y.abs() <= n.z.abs() {
+        Vector3::new(0.0, 1.0, 0.0)
+    } else {
+        Vector3::new(0.0, 0.0, 1.0)
+    };
+
+    let t = safe_normalize(n.cross(axis));
+    if t == Vector3::new(0.0, 0.0, 0.0) {
+        Vector3::new(1.0, 0.0, 0.0)
+    } else {
+        t
+    }
+}
+
+/// Normalizes `v`, returning the zero vector instead of NaNs for degenerate input.
+pub(crate) fn safe_normalize(v: Vector3<f32>) -> Vector3<f32> {
+    let len2 = v.magnitude2();
+    if len2 > f32::MIN_POSITIVE && len2.is_finite() {
+        v / len2.sqrt()
+    } else {
+        Vector3::new(0.0, 0.0, 0.0)
+    }
+}
+
+/// Meshes and comparisons shared by the tests of the loaders and writers.
+#[cfg(test)]
+pub(crate) mod testing {
+    use super::*;
+
+    /// A unit square in the XY plane facing +Z, with UVs following the position
+    /// and a different color at every corner.
+    pub fn quad() -> Mesh {
+        let vertex = |x: f32, y: f32, color: Vector4<f32>| Vertex {
+            normal: Vector3::new(0.0, 0.0, 1.0),
+            uv: Vector2::new(x, y),
+            color,
+            ..Vertex::new(Vector3::new(x, y, 0.0))
+        };
+        let a = vertex(0.0, 0.0, Vector4::new(0.2, 0.4, 0.6, 1.0));
+        let b = vertex(1.0, 0.0, Vector4::new(0.9, 0.1, 0.3, 1.0));
+        let c = vertex(1.0, 1.0, Vector4::new(0.05, 0.7, 0.5, 1.0));
+        let d = vertex(0.0, 1.0, Vector4::new(1.0, 1.0, 0.0, 1.0));
+        Mesh::new(vec![Triangle(a, b, c), Triangle(a, c, d)])
+    }
+
+    /// Asserts that both meshes have the same triangles with positions, normals
+    /// and UVs within `epsilon` and colors within `color_epsilon`.
+    pub fn assert_same_triangles(a: &Mesh, b: &Mesh, epsilon: f32, color_epsilon: f32) {
+        assert_eq!(a.triangles.len(), b.triangles.len());
+        for (i, (va, vb)) in a.vertices().iter().zip(b.vertices()).enumerate() {
+            let close = |x: f32, y: f32, epsilon: f32| (x - y).abs() <= epsilon;
+            let same = (0..3).all(|c| close(va.position[c], vb.position[c], epsilon))
+                && (0..3).all(|c| close(va.normal[c], vb.normal[c], epsilon))
+                && (0..2).all(|c| close(va.uv[c], vb.uv[c], epsilon))
+                && (0..4).all(|c| close(va.color[c], vb.color[c], color_epsilon));
+            assert!(same, "corner {}: {:?} != {:?}", i, va, vb);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::transform;
+
+    #[test]
+    fn transformed_keeps_normals_and_tangents_unit_length() {
+        let v = Vertex {
+            normal: Vector3::new(0.0, 0.6, 0.8),
+            tangent: Vector4::new(1.0, 0.0, 0.0, 1.0),
+            ..Vertex::new(Vector3::new(1.0, 2.0, 3.0))
+        };
+        let model = Matrix4::from_nonuniform_scale(3.0, -2.0, 0.5);
+        let t = v.transformed(&model, &transform::normal_matrix(&model));
+
+        assert!((t.normal.magnitude() - 1.0).abs() < 1e-6);
+        assert!((t.tangent.truncate().magnitude() - 1.0).abs() < 1e-6);
+        // Mirrored, so the bitangent flips
+        assert_eq!(t.tangent.w, -1.0);
+    }
+
+    #[test]
+    fn morph_bounds_contain_every_blend() {
+        let mut mesh = testing::quad();
+        let bounds = mesh.bounds();
+        assert_eq!(mesh.morph_bounds().aabb, bounds);
+        assert_eq!(mesh.morph_bounds().sphere, mesh.bounding_sphere());
+
+        let count = mesh.vertices().len();
+        let target = |name: &str, delta: &dyn Fn(usize) -> Vector3<f32>| MorphTarget {
+            name: name.to_owned(),
+            position_deltas: (0..count).map(delta).collect(),
+            normal_deltas: Vec::new(),
+        };
+        mesh.morph_targets = vec![
+            target("lift", &|_| Vector3::new(0.0, 0.0, 1.0)),
+            target("shift", &|_| Vector3::new(-0.5, 0.0, 0.0)),
+            // Only the first corner, the other way
+            target("dent", &|i| {
+                Vector3::new(0.0, 0.0, if i == 0 { -2.0 } else { 0.0 })
+            }),
+        ];
+
+        let morph_bounds = mesh.morph_bounds();
+        assert_eq!(morph_bounds.aabb.min, Point3::new(-0.5, 0.0, -2.0));
+        assert_eq!(morph_bounds.aabb.max, Point3::new(1.0, 1.0, 1.0));
+
+        let steps = [0.0, 0.3, 1.0];
+        for &a in steps.iter() {
+            for &b in steps.iter() {
+                for &c in steps.iter() {
+                    for tri in mesh.morphed(&[a, b, c]).iter() {
+                        for v in [tri.0, tri.1, tri.2].iter() {
+                            let p = Point3::from_vec(v.position);
+                            let aabb = &morph_bounds.aabb;
+                            assert!((0..3).all(|i| aabb.min[i] <= p[i] && p[i] <= aabb.max[i]));
+                            let sphere = &morph_bounds.sphere;
+                            assert!((p - sphere.center).magnitude() <= sphere.radius + 1e-5);
+                        }
+                    }
+                }
+            }
+        }
+    }
 }
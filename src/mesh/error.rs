@@ -0,0 +1,105 @@
+use std::error::Error;
+use std::fmt;
+use std::io;
+use std::path::PathBuf;
+
+#[derive(Debug)]
+pub enum MeshErrorKind {
+    Io(io::Error),
+    /// The file ended before all declared data was read.
+    UnexpectedEof,
+    InvalidUtf8,
+    InvalidNumber(String),
+    /// A statement or header line that does not follow the format.
+    Malformed(String),
+    Unsupported(String),
+    IndexOutOfRange {
+        index: i64,
+        count: usize,
+    },
+}
+
+/// Error returned by the mesh loaders, with as much location context as the
+/// format allows. Binary data has no line numbers.
+#[derive(Debug)]
+pub struct MeshError {
+    pub file: Option<PathBuf>,
+    pub line: Option<usize>,
+    pub kind: MeshErrorKind,
+}
+
+impl MeshError {
+    pub fn new(kind: MeshErrorKind) -> MeshError {
+        MeshError {
+            file: None,
+            line: None,
+            kind,
+        }
+    }
+
+    /// Attaches a one based line number.
+    pub fn at_line(mut self, line: usize) -> MeshError {
+        self.line = Some(line);
+        self
+    }
+
+    pub fn in_file(mut self, file: PathBuf) -> MeshError {
+        self.file = Some(file);
+        self
+    }
+}
+
+impl From<io::Error> for MeshError {
+    fn from(e: io::Error) -> MeshError {
+        MeshError::new(MeshErrorKind::Io(e))
+    }
+}
+
+impl fmt::Display for MeshErrorKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            MeshErrorKind::Io(e) => write!(f, "{}", e),
+            MeshErrorKind::UnexpectedEof => write!(f, "unexpected end of file"),
+            MeshErrorKind::InvalidUtf8 => write!(f, "text data is not valid utf8"),
+            MeshErrorKind::InvalidNumber(token) => write!(f, "invalid number '{}'", token),
+            MeshErrorKind::Malformed(what) => write!(f, "malformed {}", what),
+            MeshErrorKind::Unsupported(what) => write!(f, "unsupported {}", what),
+            MeshErrorKind::IndexOutOfRange { index, count } => {
+                write!(f, "index {} out of range for {} vertices", index, count)
+            }
+        }
+    }
+}
+
+impl fmt::Display for MeshError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if let Some(file) = &self.file {
+            write!(f, "{}:", file.display())?;
+        }
+        if let Some(line) = self.line {
+            write!(f, "{}:", line)?;
+        }
+        if self.file.is_some() || self.line.is_some() {
+            write!(f, " ")?;
+        }
+        write!(f, "{}", self.kind)
+    }
+}
+
+impl Error for MeshError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match &self.kind {
+            MeshErrorKind::Io(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+/// Parses a single token, reporting it on failure.
+pub(crate) fn parse_number<T: std::str::FromStr>(token: Option<&str>) -> Result<T, MeshError> {
+    let token = token
+        .ok_or_else(|| MeshError::new(MeshErrorKind::Malformed("line, missing value".into())))?;
+    token
+        .parse::<T>()
+        .map_err(|_| MeshError::new(MeshErrorKind::InvalidNumber(token.to_owned())))
+}
@@ -0,0 +1,258 @@
+use super::error::{parse_number, MeshError, MeshErrorKind};
+use super::{IndexedMesh, Mesh, Vertex};
+
+use cgmath::{Vector2, Vector3, Vector4};
+
+use std::collections::HashMap;
+use std::fs;
+use std::io::{self, BufWriter, Read, Write};
+use std::path::PathBuf;
+
+/// Loads the geometry of a Wavefront OBJ file.
+///
+/// Only `v`, `vt`, `vn` and `f` statements are used, groups, objects and
+/// materials are ignored. Polygonal faces are triangulated as a fan. Colors
+/// following the vertex position are read as vertex colors. OBJ has no second
+/// UV set, so `uv1` stays zero. Normals are generated with
+/// `NormalMode::default()` if any face corner lacks one.
+pub fn load_obj(path: PathBuf) -> Result<Mesh, MeshError> {
+    let buffer = fs::read(&path).map_err(|e| MeshError::from(e).in_file(path.clone()))?;
+    parse_obj(&buffer).map_err(|e| e.in_file(path))
+}
+
+pub fn read_obj<R: Read>(mut reader: R) -> Result<Mesh, MeshError> {
+    let mut buffer = Vec::new();
+    reader.read_to_end(&mut buffer)?;
+    parse_obj(&buffer)
+}
+
+pub fn parse_obj(buffer: &[u8]) -> Result<Mesh, MeshError> {
+    let buffer =
+        std::str::from_utf8(buffer).map_err(|_| MeshError::new(MeshErrorKind::InvalidUtf8))?;
+
+    let mut positions = Vec::<Vector3<f32>>::new();
+    let mut colors = Vec::<Vector4<f32>>::new();
+    let mut uvs = Vec::<Vector2<f32>>::new();
+    let mut normals = Vec::<Vector3<f32>>::new();
+
+    let mut mesh = IndexedMesh::default();
+    let mut has_normals = true;
+    let mut lookup = HashMap::<(usize, Option<usize>, Option<usize>), u32>::new();
+
+    for (line_index, line) in buffer.lines().enumerate() {
+        let mut tokens = line.split_whitespace();
+
+        let statement = match tokens.next() {
+            Some(s) => s,
+            None => continue,
+        };
+
+        let parse_statement = || -> Result<(), MeshError> {
+            match statement {
+                "v" => {
+                    let values = tokens
+                        .map(|t| parse_number(Some(t)))
+                        .collect::<Result<Vec<f32>, _>>()?;
+                    if values.len() < 3 {
+                        return Err(MeshError::new(MeshErrorKind::Malformed("vertex".into())));
+                    }
+
+                    positions.push(Vector3::new(values[0], values[1], values[2]));
+                    // Vertex colors are a common extension, "v x y z r g b"
+                    colors.push(if values.len() >= 6 {
+                        Vector4::new(values[3], values[4], values[5], 1.0)
+                    } else {
+                        Vector4::new(1.0, 1.0, 1.0, 1.0)
+                    });
+                }
+                "vt" => uvs.push(Vector2::new(
+                    parse_number(tokens.next())?,
+                    parse_number(tokens.next().or(Some("0")))?,
+                )),
+                "vn" => normals.push(Vector3::new(
+                    parse_number(tokens.next())?,
+                    parse_number(tokens.next())?,
+                    parse_number(tokens.next())?,
+                )),
+                "f" => {
+                    let mut face = Vec::new();
+
+                    for corner in tokens {
+                        let mut refs = corner.split('/');
+                        let position =
+                            resolve_index(refs.next(), positions.len())?.ok_or_else(|| {
+                                MeshError::new(MeshErrorKind::Malformed("face corner".into()))
+                            })?;
+                        let uv = resolve_index(refs.next(), uvs.len())?;
+                        let normal = resolve_index(refs.next(), normals.len())?;
+                        has_normals &= normal.is_some();
+
+                        let vertices = &mut mesh.vertices;
+                        let index = *lookup.entry((position, uv, normal)).or_insert_with(|| {
+                            vertices.push(Vertex {
+                                normal: normal
+                                    .map(|i| normals[i])
+                                    .unwrap_or(Vector3::new(0.0, 0.0, 0.0)),
+                                uv: uv.map(|i| uvs[i]).unwrap_or(Vector2::new(0.0, 0.0)),
+                                color: colors[position],
+                                ..Vertex::new(positions[position])
+                            });
+                            (vertices.len() - 1) as u32
+                        });
+                        face.push(index);
+                    }
+
+                    for i in 1..face.len().saturating_sub(1) {
+                        mesh.faces.push([face[0], face[i], face[i + 1]]);
+                    }
+                }
+                _ => {}
+            }
+            Ok(())
+        };
+
+        parse_statement().map_err(|e| e.at_line(line_index + 1))?;
+    }
+
+    Ok(super::finish_loading(mesh, has_normals))
+}
+
+pub fn save_obj(mesh: &Mesh, path: PathBuf) -> io::Result<()> {
+    let file = fs::File::create(path)?;
+    write_obj(mesh, BufWriter::new(file))
+}
+
+/// Writes the mesh with shared vertices welded, every vertex gets a `v`, `vt` and
+/// `vn` statement with the same index. Vertex colors are written without alpha,
+/// and only if some vertex is not white.
+pub fn write_obj<W: Write>(mesh: &Mesh, mut w: W) -> io::Result<()> {
+    let mesh = mesh.to_indexed();
+    let has_colors = mesh
+        .vertices
+        .iter()
+        .any(|v| v.color != Vector4::new(1.0, 1.0, 1.0, 1.0));
+
+    for v in mesh.vertices.iter() {
+        let p = v.position;
+        if has_colors {
+            let c = v.color;
+            writeln!(w, "v {} {} {} {} {} {}", p.x, p.y, p.z, c.x, c.y, c.z)?;
+        } else {
+            writeln!(w, "v {} {} {}", p.x, p.y, p.z)?;
+        }
+    }
+    for v in mesh.vertices.iter() {
+        writeln!(w, "vt {} {}", v.uv.x, v.uv.y)?;
+    }
+    for v in mesh.vertices.iter() {
+        writeln!(w, "vn {} {} {}", v.normal.x, v.normal.y, v.normal.z)?;
+    }
+    for f in mesh.faces.iter() {
+        // OBJ indices are one based
+        let (a, b, c) = (f[0] + 1, f[1] + 1, f[2] + 1);
+        writeln!(w, "f {}/{}/{} {}/{}/{} {}/{}/{}", a, a, a, b, b, b, c, c, c)?;
+    }
+
+    w.flush()
+}
+
+/// Turns a one based, possibly negative (relative to the end) OBJ index into a
+/// zero based one. Empty references like the uv in `1//1` yield `None`.
+fn resolve_index(reference: Option<&str>, count: usize) -> Result<Option<usize>, MeshError> {
+    let reference = match reference.filter(|r| !r.is_empty()) {
+        Some(r) => r,
+        None => return Ok(None),
+    };
+    let index = parse_number::<i64>(Some(reference))?;
+
+    let resolved = if index < 0 {
+        count as i64 + index
+    } else {
+        index - 1
+    };
+
+    if resolved < 0 || resolved >= count as i64 {
+        return Err(MeshError::new(MeshErrorKind::IndexOutOfRange {
+            index,
+            count,
+        }));
+    }
+    Ok(Some(resolved as usize))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mesh::testing::{assert_same_triangles, quad};
+
+    #[test]
+    fn round_trips() {
+        let mesh = quad();
+        let mut buffer = Vec::new();
+        write_obj(&mesh, &mut buffer).unwrap();
+        assert_same_triangles(&mesh, &parse_obj(&buffer).unwrap(), 1e-6, 1e-6);
+    }
+
+    #[test]
+    fn reports_malformed_statements_with_their_line() {
+        let e = parse_obj(b"# triangle\nv 0 0 0\nv 1 0 0.5.1\n").unwrap_err();
+        assert!(matches!(e.kind, MeshErrorKind::InvalidNumber(ref t) if t == "0.5.1"));
+        assert_eq!(e.line, Some(3));
+        assert!(e.to_string().starts_with("3: "));
+
+        let e = parse_obj(b"v 0 0 0\nv 1 0\n").unwrap_err();
+        assert!(matches!(e.kind, MeshErrorKind::Malformed(_)));
+        assert_eq!(e.line, Some(2));
+
+        let e = parse_obj(b"v 0 0 0\nvn 0 1\n").unwrap_err();
+        assert!(matches!(e.kind, MeshErrorKind::Malformed(_)));
+        assert_eq!(e.line, Some(2));
+
+        let e = parse_obj(b"v 0 0 0\n\nf /1 1 1\n").unwrap_err();
+        assert!(matches!(e.kind, MeshErrorKind::Malformed(_)));
+        assert_eq!(e.line, Some(3));
+
+        let e = parse_obj(b"v 0 0 0\nf 1 a 1\n").unwrap_err();
+        assert!(matches!(e.kind, MeshErrorKind::InvalidNumber(_)));
+        assert_eq!(e.line, Some(2));
+    }
+
+    #[test]
+    fn reports_out_of_range_indices() {
+        let src = b"v 0 0 0\nv 1 0 0\nv 0 1 0\nvt 0 0\n";
+        for (face, index, count) in [
+            ("f 1 2 4", 4, 3),
+            ("f 1 2 0", 0, 3),
+            ("f 1 2 -4", -4, 3),
+            ("f 1/2 2/1 3/1", 2, 1),
+            ("f 1//1 2//1 3//1", 1, 0),
+        ]
+        .iter()
+        {
+            let mut buffer = src.to_vec();
+            buffer.extend_from_slice(face.as_bytes());
+            let e = parse_obj(&buffer).unwrap_err();
+            match e.kind {
+                MeshErrorKind::IndexOutOfRange { index: i, count: c } => {
+                    assert_eq!((i, c), (*index, *count), "{}", face)
+                }
+                ref kind => panic!("{}: unexpected error {}", face, kind),
+            }
+            assert_eq!(e.line, Some(5));
+        }
+    }
+
+    #[test]
+    fn accepts_unusual_but_valid_files() {
+        let mesh = parse_obj(b"").unwrap();
+        assert!(mesh.triangles.is_empty());
+
+        // Comments, unknown statements, blank lines, relative indices, a
+        // vt without v, a quad and faces too small to make a triangle
+        let src = b"# comment\nmtllib a.mtl\no thing\n\n  \nv 0 0 0\nv 1 0 0\nv 1 1 0\nv 0 1 0\n\
+vt 0.5\nvn 0 0 1\nusemtl a\ns off\nf -4/1/1 -3/1/1 -2/1/1 -1/1/1\nf 1 2\nf 1\nf\n";
+        let mesh = parse_obj(src).unwrap();
+        assert_eq!(mesh.triangles.len(), 2);
+        assert_eq!(mesh.triangles[0].0.uv, Vector2::new(0.5, 0.0));
+    }
+}
@@ -0,0 +1,686 @@
+use super::error::{parse_number, MeshError, MeshErrorKind};
+use super::{IndexedMesh, Mesh, Vertex};
+use crate::texture::{linear_to_srgb, srgb_to_linear};
+
+use cgmath::{Vector2, Vector3, Vector4};
+
+use std::fs;
+use std::io::{self, BufWriter, Read, Write};
+use std::path::PathBuf;
+use std::str::SplitWhitespace;
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum PlyFormat {
+    Ascii,
+    BinaryLittleEndian,
+    BinaryBigEndian,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+enum ScalarType {
+    I8,
+    U8,
+    I16,
+    U16,
+    I32,
+    U32,
+    F32,
+    F64,
+}
+
+impl ScalarType {
+    fn parse(name: &str) -> Result<ScalarType, MeshError> {
+        Ok(match name {
+            "char" | "int8" => ScalarType::I8,
+            "uchar" | "uint8" => ScalarType::U8,
+            "short" | "int16" => ScalarType::I16,
+            "ushort" | "uint16" => ScalarType::U16,
+            "int" | "int32" => ScalarType::I32,
+            "uint" | "uint32" => ScalarType::U32,
+            "float" | "float32" => ScalarType::F32,
+            "double" | "float64" => ScalarType::F64,
+            _ => {
+                return Err(MeshError::new(MeshErrorKind::Unsupported(format!(
+                    "property type '{}'",
+                    name
+                ))))
+            }
+        })
+    }
+
+    fn size(self) -> usize {
+        match self {
+            ScalarType::I8 | ScalarType::U8 => 1,
+            ScalarType::I16 | ScalarType::U16 => 2,
+            ScalarType::I32 | ScalarType::U32 | ScalarType::F32 => 4,
+            ScalarType::F64 => 8,
+        }
+    }
+}
+
+#[derive(Debug)]
+enum Property {
+    Scalar(String, ScalarType),
+    List(String, ScalarType, ScalarType),
+}
+
+impl Property {
+    fn name(&self) -> &str {
+        match self {
+            Property::Scalar(name, _) | Property::List(name, _, _) => name,
+        }
+    }
+}
+
+#[derive(Debug)]
+struct Element {
+    name: String,
+    count: usize,
+    properties: Vec<Property>,
+    /// Header line declaring the element.
+    line: usize,
+}
+
+/// Source of property values for the body of a PLY file.
+///
+/// Every value is widened to f64, which represents all PLY scalar types exactly.
+trait ValueReader {
+    /// Called before each element instance, ascii files store one per line.
+    fn next_record(&mut self) -> Result<(), MeshError>;
+    fn read(&mut self, ty: ScalarType) -> Result<f64, MeshError>;
+    /// Line number of the current record, if the encoding has lines.
+    fn line(&self) -> Option<usize>;
+}
+
+struct AsciiReader<'a> {
+    lines: std::str::Lines<'a>,
+    tokens: SplitWhitespace<'a>,
+    line: usize,
+}
+
+impl<'a> ValueReader for AsciiReader<'a> {
+    fn next_record(&mut self) -> Result<(), MeshError> {
+        let line = self
+            .lines
+            .next()
+            .ok_or_else(|| MeshError::new(MeshErrorKind::UnexpectedEof))?;
+        self.tokens = line.split_whitespace();
+        self.line += 1;
+        Ok(())
+    }
+
+    fn read(&mut self, _ty: ScalarType) -> Result<f64, MeshError> {
+        parse_number(self.tokens.next())
+    }
+
+    fn line(&self) -> Option<usize> {
+        Some(self.line)
+    }
+}
+
+struct BinaryReader<'a> {
+    buffer: &'a [u8],
+    big_endian: bool,
+}
+
+impl<'a> ValueReader for BinaryReader<'a> {
+    fn next_record(&mut self) -> Result<(), MeshError> {
+        Ok(())
+    }
+
+    fn read(&mut self, ty: ScalarType) -> Result<f64, MeshError> {
+        if self.buffer.len() < ty.size() {
+            return Err(MeshError::new(MeshErrorKind::UnexpectedEof));
+        }
+        let (bytes, rest) = self.buffer.split_at(ty.size());
+        self.buffer = rest;
+
+        let mut raw = [0; 8];
+        raw[..bytes.len()].copy_from_slice(bytes);
+        if self.big_endian {
+            raw[..bytes.len()].reverse();
+        }
+
+        Ok(match ty {
+            ScalarType::I8 => raw[0] as i8 as f64,
+            ScalarType::U8 => raw[0] as f64,
+            ScalarType::I16 => i16::from_le_bytes([raw[0], raw[1]]) as f64,
+            ScalarType::U16 => u16::from_le_bytes([raw[0], raw[1]]) as f64,
+            ScalarType::I32 => i32::from_le_bytes([raw[0], raw[1], raw[2], raw[3]]) as f64,
+            ScalarType::U32 => u32::from_le_bytes([raw[0], raw[1], raw[2], raw[3]]) as f64,
+            ScalarType::F32 => f32::from_le_bytes([raw[0], raw[1], raw[2], raw[3]]) as f64,
+            ScalarType::F64 => f64::from_le_bytes(raw),
+        })
+    }
+
+    fn line(&self) -> Option<usize> {
+        None
+    }
+}
+
+/// Loads an ascii or binary PLY file containing a `vertex` and a `face` element.
+///
+/// Recognized vertex properties are x/y/z, nx/ny/nz, s/t (or u/v), red/green/
+/// blue/alpha and s1/t1 for the second UV set, anything else is skipped.
+/// Integer colors are taken as sRGB and decoded to linear, alpha and float
+/// colors are used as they are. Polygonal faces are triangulated as a fan.
+/// Normals are generated with `NormalMode::default()` if the file has none.
+pub fn load_ply(path: PathBuf) -> Result<Mesh, MeshError> {
+    let buffer = fs::read(&path).map_err(|e| MeshError::from(e).in_file(path.clone()))?;
+    parse_ply(&buffer).map_err(|e| e.in_file(path))
+}
+
+pub fn read_ply<R: Read>(mut reader: R) -> Result<Mesh, MeshError> {
+    let mut buffer = Vec::new();
+    reader.read_to_end(&mut buffer)?;
+    parse_ply(&buffer)
+}
+
+pub fn parse_ply(buffer: &[u8]) -> Result<Mesh, MeshError> {
+    let header_end = find_header_end(buffer)?;
+    let header = std::str::from_utf8(&buffer[..header_end])
+        .map_err(|_| MeshError::new(MeshErrorKind::InvalidUtf8))?;
+    let body = &buffer[header_end..];
+
+    let mut format = PlyFormat::Ascii;
+    let mut elements = Vec::<Element>::new();
+
+    // Parse header
+    for (i, line) in header.lines().enumerate() {
+        parse_header_line(line, i + 1, &mut format, &mut elements).map_err(|e| e.at_line(i + 1))?;
+    }
+    // Instances without properties take up no bytes of a binary body, so nothing
+    // would stop a bogus count from being read forever
+    if let Some(e) = elements
+        .iter()
+        .find(|e| e.properties.is_empty() && e.count > 0)
+    {
+        return Err(MeshError::new(MeshErrorKind::Malformed(format!(
+            "header, element '{}' has instances but no properties",
+            e.name
+        )))
+        .at_line(e.line));
+    }
+
+    let mut reader: Box<dyn ValueReader> = match format {
+        PlyFormat::Ascii => Box::new(AsciiReader {
+            lines: std::str::from_utf8(body)
+                .map_err(|_| MeshError::new(MeshErrorKind::InvalidUtf8))?
+                .lines(),
+            tokens: "".split_whitespace(),
+            line: header.lines().count(),
+        }),
+        _ => Box::new(BinaryReader {
+            buffer: body,
+            big_endian: format == PlyFormat::BinaryBigEndian,
+        }),
+    };
+
+    let vertex_count = elements
+        .iter()
+        .find(|e| e.name == "vertex")
+        .map(|e| e.count)
+        .unwrap_or(0);
+    let mut mesh = IndexedMesh::default();
+    let mut has_normals = true;
+
+    // Parse body
+    for element in elements.iter() {
+        let index_of = |names: &[&str]| {
+            element
+                .properties
+                .iter()
+                .position(|p| names.contains(&p.name()))
+        };
+        let position = [index_of(&["x"]), index_of(&["y"]), index_of(&["z"])];
+        let normal = [index_of(&["nx"]), index_of(&["ny"]), index_of(&["nz"])];
+        if element.name == "vertex" && normal.iter().any(Option::is_none) {
+            has_normals = false;
+        }
+        let uv = [
+            index_of(&["s", "u", "texture_u"]),
+            index_of(&["t", "v", "texture_v"]),
+        ];
+        let color = [
+            index_of(&["red", "r"]),
+            index_of(&["green", "g"]),
+            index_of(&["blue", "b"]),
+            index_of(&["alpha", "a"]),
+        ];
+        let uv1 = [
+            index_of(&["s1", "u1", "texture_u1"]),
+            index_of(&["t1", "v1", "texture_v1"]),
+        ];
+        let indices = index_of(&["vertex_indices", "vertex_index"]);
+
+        for _ in 0..element.count {
+            let mut parse_record = || -> Result<(), MeshError> {
+                reader.next_record()?;
+
+                let values = element
+                    .properties
+                    .iter()
+                    .map(|property| match *property {
+                        Property::Scalar(_, ty) => Ok(vec![reader.read(ty)?]),
+                        Property::List(_, count, item) => {
+                            let count = reader.read(count)? as usize;
+                            (0..count).map(|_| reader.read(item)).collect()
+                        }
+                    })
+                    .collect::<Result<Vec<_>, _>>()?;
+
+                let get_or = |i: Option<usize>, default: f32| {
+                    i.and_then(|i| values[i].first())
+                        .map(|v| *v as f32)
+                        .unwrap_or(default)
+                };
+                let get = |i: Option<usize>| get_or(i, 0.0);
+                // Integer colors use the full range of their type and are sRGB
+                // encoded, except for alpha
+                let get_color = |i: Option<usize>, srgb: bool| {
+                    let c = match i.map(|i| &element.properties[i]) {
+                        Some(Property::Scalar(_, ScalarType::U8)) => get(i) / 255.0,
+                        Some(Property::Scalar(_, ScalarType::U16)) => get(i) / 65535.0,
+                        _ => return get_or(i, 1.0),
+                    };
+                    if srgb {
+                        srgb_to_linear(c)
+                    } else {
+                        c
+                    }
+                };
+
+                match element.name.as_str() {
+                    "vertex" => mesh.vertices.push(Vertex {
+                        normal: Vector3::new(get(normal[0]), get(normal[1]), get(normal[2])),
+                        uv: Vector2::new(get(uv[0]), get(uv[1])),
+                        color: Vector4::new(
+                            get_color(color[0], true),
+                            get_color(color[1], true),
+                            get_color(color[2], true),
+                            get_color(color[3], false),
+                        ),
+                        uv1: Vector2::new(get(uv1[0]), get(uv1[1])),
+                        ..Vertex::new(Vector3::new(
+                            get(position[0]),
+                            get(position[1]),
+                            get(position[2]),
+                        ))
+                    }),
+                    "face" => {
+                        let face = &values[indices.ok_or_else(|| {
+                            MeshError::new(MeshErrorKind::Malformed(
+                                "face element without vertex_indices".into(),
+                            ))
+                        })?];
+
+                        let mut corners = Vec::with_capacity(face.len());
+                        for index in face.iter() {
+                            if *index < 0.0 || *index >= vertex_count as f64 {
+                                return Err(MeshError::new(MeshErrorKind::IndexOutOfRange {
+                                    index: *index as i64,
+                                    count: vertex_count,
+                                }));
+                            }
+                            corners.push(*index as u32);
+                        }
+                        for i in 1..corners.len().saturating_sub(1) {
+                            mesh.faces.push([corners[0], corners[i], corners[i + 1]]);
+                        }
+                    }
+                    _ => {}
+                }
+                Ok(())
+            };
+
+            parse_record().map_err(|e| match reader.line() {
+                Some(line) => e.at_line(line),
+                None => e,
+            })?;
+        }
+    }
+
+    Ok(super::finish_loading(mesh, has_normals))
+}
+
+fn parse_header_line(
+    line: &str,
+    line_number: usize,
+    format: &mut PlyFormat,
+    elements: &mut Vec<Element>,
+) -> Result<(), MeshError> {
+    let mut tokens = line.split_whitespace();
+    let mut next = || {
+        tokens
+            .next()
+            .ok_or_else(|| MeshError::new(MeshErrorKind::Malformed("header line".into())))
+    };
+
+    match next() {
+        Ok("format") => {
+            *format = match next()? {
+                "ascii" => PlyFormat::Ascii,
+                "binary_little_endian" => PlyFormat::BinaryLittleEndian,
+                "binary_big_endian" => PlyFormat::BinaryBigEndian,
+                f => {
+                    return Err(MeshError::new(MeshErrorKind::Unsupported(format!(
+                        "format '{}'",
+                        f
+                    ))))
+                }
+            }
+        }
+        Ok("element") => elements.push(Element {
+            name: next()?.to_owned(),
+            count: parse_number(Some(next()?))?,
+            properties: Vec::new(),
+            line: line_number,
+        }),
+        Ok("property") => {
+            let property = match next()? {
+                "list" => {
+                    let count = ScalarType::parse(next()?)?;
+                    let item = ScalarType::parse(next()?)?;
+                    Property::List(next()?.to_owned(), count, item)
+                }
+                ty => {
+                    let ty = ScalarType::parse(ty)?;
+                    Property::Scalar(next()?.to_owned(), ty)
+                }
+            };
+            elements
+                .last_mut()
+                .ok_or_else(|| {
+                    MeshError::new(MeshErrorKind::Malformed(
+                        "header, property outside of an element".into(),
+                    ))
+                })?
+                .properties
+                .push(property);
+        }
+        _ => {}
+    }
+
+    Ok(())
+}
+
+pub fn save_ply(mesh: &Mesh, path: PathBuf, format: PlyFormat) -> io::Result<()> {
+    let file = fs::File::create(path)?;
+    write_ply(mesh, BufWriter::new(file), format)
+}
+
+/// Writes the mesh with shared vertices welded. Tangents are not stored, they are
+/// recalculated when the file is loaded again. Colors are written as 8 bit sRGB.
+pub fn write_ply<W: Write>(mesh: &Mesh, mut w: W, format: PlyFormat) -> io::Result<()> {
+    let mesh = mesh.to_indexed();
+
+    // Optional attributes are only written if some vertex uses them
+    let has_colors = mesh
+        .vertices
+        .iter()
+        .any(|v| v.color != Vector4::new(1.0, 1.0, 1.0, 1.0));
+    let has_uv1 = mesh
+        .vertices
+        .iter()
+        .any(|v| v.uv1 != Vector2::new(0.0, 0.0));
+
+    let mut properties = ["x", "y", "z", "nx", "ny", "nz", "s", "t"]
+        .iter()
+        .map(|name| (*name, ScalarType::F32))
+        .collect::<Vec<_>>();
+    if has_colors {
+        for name in ["red", "green", "blue", "alpha"].iter() {
+            properties.push((name, ScalarType::U8));
+        }
+    }
+    if has_uv1 {
+        properties.push(("s1", ScalarType::F32));
+        properties.push(("t1", ScalarType::F32));
+    }
+
+    let attributes = |v: &Vertex| {
+        let mut values = vec![
+            v.position.x,
+            v.position.y,
+            v.position.z,
+            v.normal.x,
+            v.normal.y,
+            v.normal.z,
+            v.uv.x,
+            v.uv.y,
+        ];
+        if has_colors {
+            let rgb = v
+                .color
+                .truncate()
+                .map(|c| linear_to_srgb(c.clamp(0.0, 1.0)));
+            for c in [rgb.x, rgb.y, rgb.z, v.color.w.clamp(0.0, 1.0)].iter() {
+                values.push((c * 255.0).round());
+            }
+        }
+        if has_uv1 {
+            values.push(v.uv1.x);
+            values.push(v.uv1.y);
+        }
+        values
+    };
+
+    writeln!(w, "ply")?;
+    writeln!(
+        w,
+        "format {} 1.0",
+        match format {
+            PlyFormat::Ascii => "ascii",
+            PlyFormat::BinaryLittleEndian => "binary_little_endian",
+            PlyFormat::BinaryBigEndian => "binary_big_endian",
+        }
+    )?;
+    writeln!(w, "element vertex {}", mesh.vertices.len())?;
+    for (name, ty) in properties.iter() {
+        let ty = if *ty == ScalarType::U8 {
+            "uchar"
+        } else {
+            "float"
+        };
+        writeln!(w, "property {} {}", ty, name)?;
+    }
+    writeln!(w, "element face {}", mesh.faces.len())?;
+    writeln!(w, "property list uchar uint vertex_indices")?;
+    writeln!(w, "end_header")?;
+
+    match format {
+        PlyFormat::Ascii => {
+            for v in mesh.vertices.iter() {
+                let values = attributes(v)
+                    .iter()
+                    .map(|f| f.to_string())
+                    .collect::<Vec<_>>();
+                writeln!(w, "{}", values.join(" "))?;
+            }
+            for f in mesh.faces.iter() {
+                writeln!(w, "3 {} {} {}", f[0], f[1], f[2])?;
+            }
+        }
+        _ => {
+            let big_endian = format == PlyFormat::BinaryBigEndian;
+            let u32_bytes = |i: u32| {
+                if big_endian {
+                    i.to_be_bytes()
+                } else {
+                    i.to_le_bytes()
+                }
+            };
+
+            for v in mesh.vertices.iter() {
+                for (f, (_, ty)) in attributes(v).iter().zip(properties.iter()) {
+                    match ty {
+                        ScalarType::U8 => w.write_all(&[*f as u8])?,
+                        _ => w.write_all(&u32_bytes(f.to_bits()))?,
+                    }
+                }
+            }
+            for f in mesh.faces.iter() {
+                w.write_all(&[3])?;
+                for i in f.iter() {
+                    w.write_all(&u32_bytes(*i))?;
+                }
+            }
+        }
+    }
+
+    w.flush()
+}
+
+/// Returns the offset of the first byte after the `end_header` line.
+fn find_header_end(buffer: &[u8]) -> Result<usize, MeshError> {
+    if !buffer.starts_with(b"ply") {
+        return Err(MeshError::new(MeshErrorKind::Malformed(
+            "header, missing 'ply' magic".into(),
+        ))
+        .at_line(1));
+    }
+
+    let marker = b"end_header";
+    let start = buffer
+        .windows(marker.len())
+        .position(|w| w == marker)
+        .ok_or_else(|| MeshError::new(MeshErrorKind::UnexpectedEof))?;
+
+    Ok(match buffer[start..].iter().position(|b| *b == b'\n') {
+        Some(newline) => start + newline + 1,
+        None => buffer.len(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mesh::testing::{assert_same_triangles, quad};
+
+    #[test]
+    fn round_trips_every_format() {
+        let mesh = quad();
+        for format in [
+            PlyFormat::Ascii,
+            PlyFormat::BinaryLittleEndian,
+            PlyFormat::BinaryBigEndian,
+        ]
+        .iter()
+        {
+            let mut buffer = Vec::new();
+            write_ply(&mesh, &mut buffer, *format).unwrap();
+            let loaded = parse_ply(&buffer).unwrap();
+            // Colors go through 8 bit sRGB
+            assert_same_triangles(&mesh, &loaded, 1e-6, 0.01);
+        }
+    }
+
+    const HEADER: &str = "ply\nformat ascii 1.0\nelement vertex 3\nproperty float x\nproperty float y\nproperty float z\nelement face 1\nproperty list uchar int vertex_indices\nend_header\n";
+
+    fn ascii(body: &str) -> Vec<u8> {
+        format!("{}{}", HEADER, body).into_bytes()
+    }
+
+    #[test]
+    fn reports_malformed_numbers_with_their_line() {
+        let e = parse_ply(&ascii("0 0 0\n1 x 0\n0 1 0\n3 0 1 2\n")).unwrap_err();
+        assert!(matches!(e.kind, MeshErrorKind::InvalidNumber(ref t) if t == "x"));
+        assert_eq!(e.line, Some(11));
+        assert!(e.to_string().starts_with("11: "));
+
+        let e = parse_ply(&ascii("0 0 0\n1 0\n0 1 0\n3 0 1 2\n")).unwrap_err();
+        assert!(matches!(e.kind, MeshErrorKind::Malformed(_)));
+        assert_eq!(e.line, Some(11));
+    }
+
+    #[test]
+    fn reports_out_of_range_face_indices() {
+        let e = parse_ply(&ascii("0 0 0\n1 0 0\n0 1 0\n3 0 1 3\n")).unwrap_err();
+        assert!(matches!(
+            e.kind,
+            MeshErrorKind::IndexOutOfRange { index: 3, count: 3 }
+        ));
+        assert_eq!(e.line, Some(13));
+
+        let e = parse_ply(&ascii("0 0 0\n1 0 0\n0 1 0\n3 0 -1 2\n")).unwrap_err();
+        assert!(matches!(
+            e.kind,
+            MeshErrorKind::IndexOutOfRange { index: -1, .. }
+        ));
+        assert_eq!(e.line, Some(13));
+    }
+
+    #[test]
+    fn reports_truncated_bodies() {
+        // Ascii ends on the last line that was read
+        let e = parse_ply(&ascii("0 0 0\n1 0 0\n")).unwrap_err();
+        assert!(matches!(e.kind, MeshErrorKind::UnexpectedEof));
+        assert_eq!(e.line, Some(11));
+
+        // Binary data has no lines
+        let mut buffer = Vec::new();
+        write_ply(&quad(), &mut buffer, PlyFormat::BinaryLittleEndian).unwrap();
+        for cut in [1, 7, 50].iter() {
+            let e = parse_ply(&buffer[..buffer.len() - cut]).unwrap_err();
+            assert!(matches!(e.kind, MeshErrorKind::UnexpectedEof));
+            assert_eq!(e.line, None);
+        }
+
+        let e = parse_ply(b"ply\nformat ascii 1.0\nelement vertex 3\n").unwrap_err();
+        assert!(matches!(e.kind, MeshErrorKind::UnexpectedEof));
+    }
+
+    #[test]
+    fn reports_header_errors_with_their_line() {
+        let e = parse_ply(b"obj\nend_header\n").unwrap_err();
+        assert!(matches!(e.kind, MeshErrorKind::Malformed(_)));
+        assert_eq!(e.line, Some(1));
+
+        let e = parse_ply(b"ply\nformat ascii 1.0\nproperty float x\nend_header\n").unwrap_err();
+        assert!(matches!(e.kind, MeshErrorKind::Malformed(_)));
+        assert_eq!(e.line, Some(3));
+
+        let e = parse_ply(b"ply\nformat text 1.0\nend_header\n").unwrap_err();
+        assert!(matches!(e.kind, MeshErrorKind::Unsupported(_)));
+        assert_eq!(e.line, Some(2));
+
+        let e =
+            parse_ply(b"ply\nformat ascii 1.0\nelement vertex 1\nproperty half x\nend_header\n")
+                .unwrap_err();
+        assert!(matches!(e.kind, MeshErrorKind::Unsupported(_)));
+        assert_eq!(e.line, Some(4));
+
+        let e = parse_ply(b"ply\nformat ascii 1.0\nelement vertex many\nend_header\n").unwrap_err();
+        assert!(matches!(e.kind, MeshErrorKind::InvalidNumber(_)));
+        assert_eq!(e.line, Some(3));
+    }
+
+    #[test]
+    fn rejects_elements_without_properties() {
+        let buffer = b"ply\nformat binary_little_endian 1.0\ncomment x\nelement empty 1000000000\nend_header\n";
+        let e = parse_ply(buffer).unwrap_err();
+        assert!(matches!(e.kind, MeshErrorKind::Malformed(_)));
+        assert_eq!(e.line, Some(4));
+    }
+
+    #[test]
+    fn accepts_unusual_but_valid_files() {
+        // Nothing at all
+        let mesh = parse_ply(b"ply\nformat ascii 1.0\nend_header\n").unwrap();
+        assert!(mesh.triangles.is_empty());
+
+        // Empty elements without properties, unknown elements and properties,
+        // comments, a quad and faces too small to make a triangle
+        let buffer = b"ply\nformat ascii 1.0\ncomment made by hand\nelement nothing 0\n\
+element vertex 4\nproperty float x\nproperty float y\nproperty float z\nproperty float weight\n\
+element face 3\nproperty list uchar int vertex_indices\nproperty uchar flags\n\
+element extra 1\nproperty int value\nend_header\n\
+0 0 0 1\n1 0 0 1\n1 1 0 1\n0 1 0 1\n4 0 1 2 3 7\n2 0 1 0\n0 0\n42\n";
+        let mesh = parse_ply(buffer).unwrap();
+        assert_eq!(mesh.triangles.len(), 2);
+
+        // Header without a trailing newline
+        let mesh = parse_ply(b"ply\nformat binary_big_endian 1.0\nend_header").unwrap();
+        assert!(mesh.triangles.is_empty());
+    }
+}
@@ -0,0 +1,409 @@
+use super::{position_key, Mesh};
+
+use cgmath::prelude::*;
+use cgmath::Vector3;
+
+use std::cmp::Ordering;
+use std::collections::{BinaryHeap, HashMap};
+
+/// Weight of the planes that keep boundaries and attribute seams in place,
+/// relative to the surface planes.
+const SEAM_WEIGHT: f64 = 100.0;
+
+/// Symmetric 4x4 error quadric of the Garland-Heckbert metric, stored as the ten
+/// unique coefficients along with the total weight of its planes. Kept in f64
+/// since sums over many planes lose precision.
+#[derive(Copy, Clone, Debug, Default)]
+struct Quadric {
+    coefficients: [f64; 10],
+    weight: f64,
+}
+
+impl Quadric {
+    /// Quadric of the squared distance to the plane through `p` with unit normal `n`.
+    fn from_plane(n: Vector3<f32>, p: Vector3<f32>, weight: f64) -> Quadric {
+        let (a, b, c) = (n.x as f64, n.y as f64, n.z as f64);
+        let d = -(n.dot(p) as f64);
+        let coefficients = [
+            a * a * weight,
+            a * b * weight,
+            a * c * weight,
+            a * d * weight,
+            b * b * weight,
+            b * c * weight,
+            b * d * weight,
+            c * c * weight,
+            c * d * weight,
+            d * d * weight,
+        ];
+        Quadric {
+            coefficients,
+            weight,
+        }
+    }
+
+    fn add(&mut self, other: &Quadric) {
+        for (a, b) in self.coefficients.iter_mut().zip(other.coefficients.iter()) {
+            *a += b;
+        }
+        self.weight += other.weight;
+    }
+
+    /// Mean squared distance of `p` to the planes, weighted the same way as the
+    /// planes themselves, so it is in squared object space units no matter how
+    /// many planes were summed up.
+    fn mean_distance2(&self, p: Vector3<f32>) -> f64 {
+        if self.weight <= 0.0 {
+            return 0.0;
+        }
+
+        let q = &self.coefficients;
+        let (x, y, z) = (p.x as f64, p.y as f64, p.z as f64);
+        let error = (q[0] * x * x + 2.0 * q[1] * x * y + 2.0 * q[2] * x * z + 2.0 * q[3] * x)
+            + (q[4] * y * y + 2.0 * q[5] * y * z + 2.0 * q[6] * y)
+            + (q[7] * z * z + 2.0 * q[8] * z)
+            + q[9];
+        (error / self.weight).max(0.0)
+    }
+}
+
+/// Candidate collapse of position `from` onto position `to`, ordered so the
+/// cheapest collapse is at the top of the heap.
+struct Collapse {
+    cost: f64,
+    from: usize,
+    to: usize,
+    from_version: u32,
+    to_version: u32,
+}
+
+impl PartialEq for Collapse {
+    fn eq(&self, other: &Collapse) -> bool {
+        self.cost == other.cost
+    }
+}
+
+impl Eq for Collapse {}
+
+impl PartialOrd for Collapse {
+    fn partial_cmp(&self, other: &Collapse) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Collapse {
+    fn cmp(&self, other: &Collapse) -> Ordering {
+        other
+            .cost
+            .partial_cmp(&self.cost)
+            .unwrap_or(Ordering::Equal)
+    }
+}
+
+impl Mesh {
+    /// Reduces the triangle count with quadric error metric edge collapses until
+    /// at most `target_triangles` remain or the next collapse would move the
+    /// surface by more than `max_error`, whichever comes first.
+    ///
+    /// Collapses move one vertex onto a neighbour, so every remaining vertex keeps
+    /// its original normal, UVs and other attributes. A vertex is never moved off
+    /// a UV seam, normal split or open boundary. Returns the simplified mesh and
+    /// the largest error actually introduced, in object space units. The error of
+    /// a vertex is its area weighted RMS distance to the planes of the original
+    /// faces merged into it, boundaries and seams weighing in more heavily. Morph
+    /// targets are dropped.
+    pub fn simplify(&self, target_triangles: usize, max_error: f32) -> (Mesh, f32) {
+        let indexed = self.to_indexed();
+        let wedges = &indexed.vertices;
+        let mut faces = indexed.faces.clone();
+        let mut face_alive = vec![true; faces.len()];
+        let mut alive_count = faces.len();
+
+        // Wedges are the attribute vertices, several of them share a position on seams
+        let mut position_of = HashMap::new();
+        let mut positions = Vec::new();
+        let wedge_position = wedges
+            .iter()
+            .map(|w| {
+                *position_of
+                    .entry(position_key(w.position))
+                    .or_insert_with(|| {
+                        positions.push(w.position);
+                        positions.len() - 1
+                    })
+            })
+            .collect::<Vec<_>>();
+
+        let corner_positions = |face: &[u32; 3]| {
+            [
+                wedge_position[face[0] as usize],
+                wedge_position[face[1] as usize],
+                wedge_position[face[2] as usize],
+            ]
+        };
+
+        let mut position_faces = vec![Vec::new(); positions.len()];
+        let mut quadrics = vec![Quadric::default(); positions.len()];
+        let mut edges = HashMap::<(usize, usize), Vec<(usize, (u32, u32))>>::new();
+
+        for (f, face) in faces.iter().enumerate() {
+            let p = corner_positions(face);
+            let normal =
+                (positions[p[1]] - positions[p[0]]).cross(positions[p[2]] - positions[p[0]]);
+            let area = normal.magnitude() as f64 * 0.5;
+            let normal = super::safe_normalize(normal);
+
+            let plane = Quadric::from_plane(normal, positions[p[0]], area);
+            for i in 0..3 {
+                position_faces[p[i]].push(f);
+                quadrics[p[i]].add(&plane);
+
+                let (a, b) = (p[i], p[(i + 1) % 3]);
+                let (wa, wb) = (face[i], face[(i + 1) % 3]);
+                let key = if a < b { (a, b) } else { (b, a) };
+                let wedge_pair = if a < b { (wa, wb) } else { (wb, wa) };
+                edges.entry(key).or_default().push((f, wedge_pair));
+            }
+        }
+
+        // Open boundaries and seams get planes perpendicular to their faces, which
+        // makes sliding along them cheap and moving away from them expensive
+        for (&(a, b), uses) in edges.iter() {
+            let seam = uses.len() != 2 || uses[0].1 != uses[1].1;
+            if !seam {
+                continue;
+            }
+
+            for &(f, _) in uses.iter() {
+                let p = corner_positions(&faces[f]);
+                let face_normal =
+                    (positions[p[1]] - positions[p[0]]).cross(positions[p[2]] - positions[p[0]]);
+                let edge = positions[b] - positions[a];
+                let normal = super::safe_normalize(edge.cross(face_normal));
+                let plane = Quadric::from_plane(
+                    normal,
+                    positions[a],
+                    edge.magnitude2() as f64 * SEAM_WEIGHT,
+                );
+                quadrics[a].add(&plane);
+                quadrics[b].add(&plane);
+            }
+        }
+
+        let mut versions = vec![0u32; positions.len()];
+        let mut position_alive = vec![true; positions.len()];
+        let mut heap = BinaryHeap::new();
+
+        let push_collapse = |heap: &mut BinaryHeap<Collapse>,
+                             quadrics: &[Quadric],
+                             versions: &[u32],
+                             from: usize,
+                             to: usize| {
+            let mut q = quadrics[from];
+            q.add(&quadrics[to]);
+            heap.push(Collapse {
+                cost: q.mean_distance2(positions[to]),
+                from,
+                to,
+                from_version: versions[from],
+                to_version: versions[to],
+            });
+        };
+
+        for &(a, b) in edges.keys() {
+            push_collapse(&mut heap, &quadrics, &versions, a, b);
+            push_collapse(&mut heap, &quadrics, &versions, b, a);
+        }
+
+        // Positions sharing a live face with `x`, sorted
+        let neighbours =
+            |x: usize, position_faces: &[Vec<usize>], faces: &[[u32; 3]], face_alive: &[bool]| {
+                let mut n = position_faces[x]
+                    .iter()
+                    .filter(|f| face_alive[**f])
+                    .flat_map(|f| corner_positions(&faces[*f]).to_vec())
+                    .filter(|p| *p != x)
+                    .collect::<Vec<_>>();
+                n.sort_unstable();
+                n.dedup();
+                n
+            };
+
+        let mut error = 0.0f32;
+        let max_cost = (max_error as f64) * (max_error as f64);
+
+        while alive_count > target_triangles {
+            let collapse = match heap.pop() {
+                Some(c) => c,
+                None => break,
+            };
+            let (u, v) = (collapse.from, collapse.to);
+
+            if !position_alive[u]
+                || !position_alive[v]
+                || versions[u] != collapse.from_version
+                || versions[v] != collapse.to_version
+            {
+                continue;
+            }
+            if collapse.cost > max_cost {
+                break;
+            }
+
+            let u_faces = position_faces[u]
+                .iter()
+                .cloned()
+                .filter(|f| face_alive[*f])
+                .collect::<Vec<_>>();
+
+            // Every wedge at u has to map to exactly one wedge at v, through the
+            // faces that contain the edge. Anything else would tear a seam.
+            let mut wedge_map = HashMap::<u32, u32>::new();
+            let mut consistent = true;
+            for &f in u_faces.iter() {
+                let p = corner_positions(&faces[f]);
+                if let (Some(iu), Some(iv)) = (
+                    p.iter().position(|x| *x == u),
+                    p.iter().position(|x| *x == v),
+                ) {
+                    let target = *wedge_map.entry(faces[f][iu]).or_insert(faces[f][iv]);
+                    consistent &= target == faces[f][iv];
+                }
+            }
+            let all_mapped = u_faces.iter().all(|&f| {
+                let p = corner_positions(&faces[f]);
+                let iu = p.iter().position(|x| *x == u).unwrap();
+                wedge_map.contains_key(&faces[f][iu])
+            });
+            if !consistent || !all_mapped || wedge_map.is_empty() {
+                continue;
+            }
+
+            // Link condition, more than two shared neighbours would pinch the surface
+            let v_neighbours = neighbours(v, &position_faces, &faces, &face_alive);
+            let shared = neighbours(u, &position_faces, &faces, &face_alive)
+                .iter()
+                .filter(|n| v_neighbours.binary_search(n).is_ok())
+                .count();
+            if shared > 2 {
+                continue;
+            }
+
+            // Reject collapses that flip or squash any remaining face
+            let flips = u_faces.iter().any(|&f| {
+                let p = corner_positions(&faces[f]);
+                if p.contains(&v) {
+                    return false;
+                }
+                let before = p.iter().map(|x| positions[*x]).collect::<Vec<_>>();
+                let after = p
+                    .iter()
+                    .map(|x| if *x == u { positions[v] } else { positions[*x] })
+                    .collect::<Vec<_>>();
+                let n0 = (before[1] - before[0]).cross(before[2] - before[0]);
+                let n1 = (after[1] - after[0]).cross(after[2] - after[0]);
+                n0.dot(n1) <= 0.0
+            });
+            if flips {
+                continue;
+            }
+
+            for &f in u_faces.iter() {
+                if corner_positions(&faces[f]).contains(&v) {
+                    face_alive[f] = false;
+                    alive_count -= 1;
+                } else {
+                    for w in faces[f].iter_mut() {
+                        if let Some(mapped) = wedge_map.get(w) {
+                            *w = *mapped;
+                        }
+                    }
+                    position_faces[v].push(f);
+                }
+            }
+
+            let q = quadrics[u];
+            quadrics[v].add(&q);
+            position_alive[u] = false;
+            versions[v] += 1;
+            error = error.max((collapse.cost.sqrt()) as f32);
+
+            for n in neighbours(v, &position_faces, &faces, &face_alive) {
+                push_collapse(&mut heap, &quadrics, &versions, v, n);
+                push_collapse(&mut heap, &quadrics, &versions, n, v);
+            }
+        }
+
+        faces = faces
+            .into_iter()
+            .zip(face_alive)
+            .filter(|(_, alive)| *alive)
+            .map(|(f, _)| f)
+            .collect();
+
+        let simplified = super::IndexedMesh {
+            vertices: wedges.clone(),
+            faces,
+        };
+        (simplified.to_mesh(), error)
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct Lod {
+    pub mesh: Mesh,
+    /// Largest geometric error relative to the full detail mesh, in object units.
+    pub error: f32,
+}
+
+/// Successively simplified versions of a mesh, finest first.
+#[derive(Clone, Debug)]
+pub struct LodChain {
+    pub levels: Vec<Lod>,
+}
+
+impl LodChain {
+    /// Builds up to `levels` levels, each with about half the triangles of the
+    /// previous one. Stops early once simplification makes no more progress.
+    /// Only the full detail level keeps morph targets.
+    pub fn generate(mesh: &Mesh, levels: usize) -> LodChain {
+        let mut chain = vec![Lod {
+            mesh: mesh.clone(),
+            error: 0.0,
+        }];
+
+        while chain.len() < levels {
+            let previous = chain.last().unwrap();
+            let target = previous.mesh.triangles.len() / 2;
+            let (simplified, error) = previous.mesh.simplify(target, f32::INFINITY);
+
+            if simplified.triangles.len() >= previous.mesh.triangles.len() {
+                break;
+            }
+
+            // Errors of consecutive levels add up in the worst case
+            let error = previous.error + error;
+            chain.push(Lod {
+                mesh: simplified,
+                error,
+            });
+        }
+
+        LodChain { levels: chain }
+    }
+
+    /// Picks the coarsest level whose error stays below `max_pixels` on screen,
+    /// where one object space unit covers `pixels_per_unit` pixels at the
+    /// distance the mesh is seen from, see `Projection::pixels_per_unit`.
+    pub fn select(&self, pixels_per_unit: f32, max_pixels: f32) -> &Mesh {
+        &self.levels[self.select_level(pixels_per_unit, max_pixels)].mesh
+    }
+
+    /// Index of the level `select` picks.
+    pub fn select_level(&self, pixels_per_unit: f32, max_pixels: f32) -> usize {
+        self.levels
+            .iter()
+            .rposition(|lod| lod.error * pixels_per_unit <= max_pixels)
+            .unwrap_or(0)
+    }
+}
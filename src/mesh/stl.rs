@@ -0,0 +1,317 @@
+use super::error::{parse_number, MeshError, MeshErrorKind};
+use super::{Mesh, NormalMode, Triangle, Vertex};
+use crate::texture::{linear_to_srgb, srgb_to_linear};
+
+use cgmath::{InnerSpace, Vector3, Vector4};
+
+use std::fs;
+use std::io::{self, BufWriter, Read, Write};
+use std::path::PathBuf;
+
+/// Corners closer than this fraction of the mesh's size are welded on import.
+const WELD_TOLERANCE: f32 = 1e-6;
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum StlFormat {
+    Ascii,
+    Binary,
+}
+
+/// Loads an ascii or binary STL file.
+///
+/// STL stores every facet with its own three corners, so corners at (nearly) the
+/// same position are welded, the stored facet normals are discarded and vertex
+/// normals are regenerated according to `normals`. `Mesh::to_indexed` then
+/// shares the welded corners. Binary facet colors in the VisCAM/SolidView
+/// convention become vertex colors.
+pub fn load_stl(path: PathBuf, normals: NormalMode) -> Result<Mesh, MeshError> {
+    let buffer = fs::read(&path).map_err(|e| MeshError::from(e).in_file(path.clone()))?;
+    parse_stl(&buffer, normals).map_err(|e| e.in_file(path))
+}
+
+pub fn read_stl<R: Read>(mut reader: R, normals: NormalMode) -> Result<Mesh, MeshError> {
+    let mut buffer = Vec::new();
+    reader.read_to_end(&mut buffer)?;
+    parse_stl(&buffer, normals)
+}
+
+pub fn parse_stl(buffer: &[u8], normals: NormalMode) -> Result<Mesh, MeshError> {
+    let facets = if is_binary(buffer) {
+        parse_binary(buffer)?
+    } else {
+        parse_ascii(
+            std::str::from_utf8(buffer).map_err(|_| MeshError::new(MeshErrorKind::InvalidUtf8))?,
+        )?
+    };
+
+    let mut mesh = Mesh::new(
+        facets
+            .iter()
+            .map(|(corners, _)| {
+                Triangle(
+                    Vertex::new(corners[0]),
+                    Vertex::new(corners[1]),
+                    Vertex::new(corners[2]),
+                )
+            })
+            .collect(),
+    );
+
+    // Exporters write shared corners once per facet, not always bit for bit
+    // the same. Welding only looks at positions while nothing else is set, the
+    // facet colors are put back afterwards since they are not shared.
+    let size = mesh.bounds().size().magnitude();
+    mesh.weld(size * WELD_TOLERANCE);
+    for (tri, (_, color)) in mesh.triangles.iter_mut().zip(facets.iter()) {
+        let color = color.unwrap_or(Vector4::new(1.0, 1.0, 1.0, 1.0));
+        tri.0.color = color;
+        tri.1.color = color;
+        tri.2.color = color;
+    }
+
+    mesh.generate_normals(normals);
+    mesh.generate_tangents();
+    Ok(mesh)
+}
+
+pub fn save_stl(mesh: &Mesh, path: PathBuf, format: StlFormat) -> io::Result<()> {
+    let file = fs::File::create(path)?;
+    write_stl(mesh, BufWriter::new(file), format)
+}
+
+pub fn write_stl<W: Write>(mesh: &Mesh, mut w: W, format: StlFormat) -> io::Result<()> {
+    match format {
+        StlFormat::Ascii => {
+            writeln!(w, "solid mesh")?;
+            for tri in mesh.triangles.iter() {
+                let n = super::safe_normalize(tri.face_normal());
+                writeln!(w, "  facet normal {:e} {:e} {:e}", n.x, n.y, n.z)?;
+                writeln!(w, "    outer loop")?;
+                for v in [&tri.0, &tri.1, &tri.2].iter() {
+                    let p = v.position;
+                    writeln!(w, "      vertex {:e} {:e} {:e}", p.x, p.y, p.z)?;
+                }
+                writeln!(w, "    endloop")?;
+                writeln!(w, "  endfacet")?;
+            }
+            writeln!(w, "endsolid mesh")?;
+        }
+        StlFormat::Binary => {
+            w.write_all(&[0; 80])?;
+            w.write_all(&(mesh.triangles.len() as u32).to_le_bytes())?;
+            for tri in mesh.triangles.iter() {
+                let n = super::safe_normalize(tri.face_normal());
+                for v in [n, tri.0.position, tri.1.position, tri.2.position].iter() {
+                    for c in [v.x, v.y, v.z].iter() {
+                        w.write_all(&c.to_le_bytes())?;
+                    }
+                }
+                w.write_all(&encode_color(tri.0.color).to_le_bytes())?;
+            }
+        }
+    }
+
+    w.flush()
+}
+
+/// Binary files may also start with "solid", so the size implied by the facet
+/// count decides first. Anything else not starting with "solid" is treated as
+/// (possibly truncated) binary data.
+fn is_binary(buffer: &[u8]) -> bool {
+    if buffer.len() >= 84 {
+        let count = u32::from_le_bytes([buffer[80], buffer[81], buffer[82], buffer[83]]) as usize;
+        if 84 + count * 50 == buffer.len() {
+            return true;
+        }
+    }
+
+    !buffer.starts_with(b"solid")
+}
+
+/// Facet corners and the facet color, if any.
+type Facet = ([Vector3<f32>; 3], Option<Vector4<f32>>);
+
+/// The two attribute bytes of a binary facet hold a 15 bit color when the top
+/// bit is set, blue in the lowest five bits and red in bits 10 to 14. Channels
+/// are sRGB encoded like other 8 bit colors.
+fn decode_color(attribute: u16) -> Option<Vector4<f32>> {
+    if attribute & 0x8000 == 0 {
+        return None;
+    }
+
+    let channel = |shift: u16| srgb_to_linear(((attribute >> shift) & 0x1f) as f32 / 31.0);
+    Some(Vector4::new(channel(10), channel(5), channel(0), 1.0))
+}
+
+/// Inverse of `decode_color`, white is written as "no color".
+fn encode_color(color: Vector4<f32>) -> u16 {
+    if color == Vector4::new(1.0, 1.0, 1.0, 1.0) {
+        return 0;
+    }
+
+    let channel =
+        |c: f32, shift: u16| ((linear_to_srgb(c.clamp(0.0, 1.0)) * 31.0).round() as u16) << shift;
+    0x8000 | channel(color.x, 10) | channel(color.y, 5) | channel(color.z, 0)
+}
+
+fn parse_binary(buffer: &[u8]) -> Result<Vec<Facet>, MeshError> {
+    if buffer.len() < 84 {
+        return Err(MeshError::new(MeshErrorKind::UnexpectedEof));
+    }
+    let count = u32::from_le_bytes([buffer[80], buffer[81], buffer[82], buffer[83]]) as usize;
+    if (buffer.len() - 84) / 50 < count {
+        return Err(MeshError::new(MeshErrorKind::UnexpectedEof));
+    }
+
+    let read_f32 = |offset: usize| {
+        f32::from_le_bytes([
+            buffer[offset],
+            buffer[offset + 1],
+            buffer[offset + 2],
+            buffer[offset + 3],
+        ])
+    };
+    let read_vec3 =
+        |offset: usize| Vector3::new(read_f32(offset), read_f32(offset + 4), read_f32(offset + 8));
+
+    Ok((0..count)
+        .map(|i| {
+            // Skip the 12 byte facet normal
+            let facet = 84 + i * 50 + 12;
+            let attribute = u16::from_le_bytes([buffer[facet + 36], buffer[facet + 37]]);
+            (
+                [
+                    read_vec3(facet),
+                    read_vec3(facet + 12),
+                    read_vec3(facet + 24),
+                ],
+                decode_color(attribute),
+            )
+        })
+        .collect())
+}
+
+fn parse_ascii(buffer: &str) -> Result<Vec<Facet>, MeshError> {
+    let mut facets = Vec::new();
+    let mut corners = Vec::with_capacity(3);
+
+    for (i, line) in buffer.lines().enumerate() {
+        let mut tokens = line.split_whitespace();
+
+        match tokens.next() {
+            Some("vertex") => {
+                let mut parse = || parse_number(tokens.next()).map_err(|e| e.at_line(i + 1));
+                corners.push(Vector3::new(parse()?, parse()?, parse()?));
+            }
+            Some("endloop") => {
+                // Polygons with more than three corners are fanned into triangles
+                for i in 1..corners.len().saturating_sub(1) {
+                    facets.push(([corners[0], corners[i], corners[i + 1]], None));
+                }
+                corners.clear();
+            }
+            _ => {}
+        }
+    }
+
+    Ok(facets)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mesh::testing::{assert_same_triangles, quad};
+    use cgmath::Vector2;
+
+    #[test]
+    fn round_trips_both_formats() {
+        // STL has one color per facet and no UVs
+        let mut mesh = quad();
+        for tri in mesh.triangles.iter_mut() {
+            tri.1.color = tri.0.color;
+            tri.2.color = tri.0.color;
+            for v in [&mut tri.0, &mut tri.1, &mut tri.2].iter_mut() {
+                v.uv = Vector2::new(0.0, 0.0);
+            }
+        }
+
+        let mut buffer = Vec::new();
+        write_stl(&mesh, &mut buffer, StlFormat::Binary).unwrap();
+        let loaded = parse_stl(&buffer, NormalMode::Flat).unwrap();
+        // Colors go through 5 bit sRGB
+        assert_same_triangles(&mesh, &loaded, 1e-6, 0.03);
+
+        // ASCII files have no colors at all
+        for v in mesh.triangles.iter_mut() {
+            v.0.color = Vector4::new(1.0, 1.0, 1.0, 1.0);
+            v.1.color = v.0.color;
+            v.2.color = v.0.color;
+        }
+        let mut buffer = Vec::new();
+        write_stl(&mesh, &mut buffer, StlFormat::Ascii).unwrap();
+        let loaded = parse_stl(&buffer, NormalMode::Flat).unwrap();
+        assert_same_triangles(&mesh, &loaded, 1e-6, 0.0);
+    }
+
+    #[test]
+    fn reports_malformed_numbers_with_their_line() {
+        let src = b"solid x\nfacet normal 0 0 1\nouter loop\nvertex 0 0 0\nvertex 1 0 nan0\n";
+        let e = parse_stl(src, NormalMode::Flat).unwrap_err();
+        assert!(matches!(e.kind, MeshErrorKind::InvalidNumber(ref t) if t == "nan0"));
+        assert_eq!(e.line, Some(5));
+        assert!(e.to_string().starts_with("5: "));
+
+        let e = parse_stl(b"solid x\n\nvertex 0 0\n", NormalMode::Flat).unwrap_err();
+        assert!(matches!(e.kind, MeshErrorKind::Malformed(_)));
+        assert_eq!(e.line, Some(3));
+    }
+
+    #[test]
+    fn reports_truncated_binary_files() {
+        let mut buffer = Vec::new();
+        write_stl(&quad(), &mut buffer, StlFormat::Binary).unwrap();
+
+        // Binary data has no lines
+        for len in [0, 83, 84, buffer.len() - 1].iter() {
+            let e = parse_stl(&buffer[..*len], NormalMode::Flat).unwrap_err();
+            assert!(matches!(e.kind, MeshErrorKind::UnexpectedEof), "{}", len);
+            assert_eq!(e.line, None);
+        }
+    }
+
+    #[test]
+    fn accepts_unusual_but_valid_files() {
+        let mut buffer = vec![0; 84];
+        assert!(parse_stl(&buffer, NormalMode::Flat)
+            .unwrap()
+            .triangles
+            .is_empty());
+
+        // A binary header that starts with "solid", which is common
+        buffer[..5].copy_from_slice(b"solid");
+        assert!(parse_stl(&buffer, NormalMode::default())
+            .unwrap()
+            .triangles
+            .is_empty());
+
+        // Bytes after the declared facets
+        buffer[..5].copy_from_slice(b"model");
+        buffer.resize(200, 0);
+        assert!(parse_stl(&buffer, NormalMode::Flat)
+            .unwrap()
+            .triangles
+            .is_empty());
+
+        assert!(parse_stl(b"solid", NormalMode::Flat)
+            .unwrap()
+            .triangles
+            .is_empty());
+
+        // A quad facet and an unclosed loop with a single corner
+        let src = b"solid q\nfacet normal 0 0 1\nouter loop\nvertex 0 0 0\nvertex 1 0 0\n\
+vertex 1 1 0\nvertex 0 1 0\nendloop\nendfacet\nfacet normal 0 0 1\nouter loop\nvertex 0 0 0\n\
+endsolid q\n";
+        let mesh = parse_stl(src, NormalMode::Flat).unwrap();
+        assert_eq!(mesh.triangles.len(), 2);
+    }
+}
@@ -1 +1,315 @@
+extern crate gl;
 
+use crate::convention::{Convention, DepthRange, FramebufferY, Handedness};
+use crate::mesh;
+use crate::shader::Shader;
+use crate::texture::{Filter, Sampler, Texture2D, Wrap};
+
+use self::gl::types::*;
+
+/// From EXT_texture_filter_anisotropic, core since GL 4.6 and so missing from the
+/// generated bindings.
+pub const TEXTURE_MAX_ANISOTROPY: GLenum = 0x84FE;
+pub const MAX_TEXTURE_MAX_ANISOTROPY: GLenum = 0x84FF;
+
+/// Length of the `joint_matrices` array in the vertex shaders.
+pub const MAX_JOINTS: usize = 64;
+
+/// Length of the `morph_indices` and `morph_weights` arrays in the vertex
+/// shaders, the most morph targets blended in one draw.
+pub const MAX_MORPH_TARGETS: usize = 8;
+
+/// A single attribute in an interleaved vertex buffer.
+#[derive(Debug, Clone)]
+pub struct VertexAttribute {
+    pub name: &'static str,
+    pub location: GLuint,
+    pub components: GLint,
+    pub ty: GLenum,
+    pub normalized: bool,
+    /// Read as integers by the shader (`ivec` or `uvec` inputs) instead of being
+    /// converted to floats.
+    pub integer: bool,
+    /// Byte offset from the start of the vertex.
+    pub offset: usize,
+}
+
+/// Describes how a vertex type is laid out in a buffer.
+#[derive(Debug, Clone)]
+pub struct VertexLayout {
+    pub stride: usize,
+    pub attributes: Vec<VertexAttribute>,
+}
+
+/// Vertex types that can be uploaded to GL as they are in memory.
+pub trait VertexFormat: bytemuck::Pod {
+    fn layout() -> VertexLayout;
+}
+
+impl VertexLayout {
+    /// Sets up and enables every attribute for the currently bound vertex array and
+    /// `ARRAY_BUFFER`.
+    pub fn configure(&self) {
+        for attribute in self.attributes.iter() {
+            unsafe {
+                if attribute.integer {
+                    gl::VertexAttribIPointer(
+                        attribute.location,
+                        attribute.components,
+                        attribute.ty,
+                        self.stride as GLsizei,
+                        attribute.offset as *const GLvoid,
+                    );
+                } else {
+                    gl::VertexAttribPointer(
+                        attribute.location,
+                        attribute.components,
+                        attribute.ty,
+                        attribute.normalized as GLboolean,
+                        self.stride as GLsizei,
+                        attribute.offset as *const GLvoid,
+                    );
+                }
+                gl::EnableVertexAttribArray(attribute.location);
+            }
+        }
+    }
+
+    /// Assigns each attribute's location to the shader input of the same name.
+    /// Must be called before linking, explicit `layout(location = N)` qualifiers
+    /// in the shader take precedence.
+    pub fn bind_locations(&self, shader: &Shader) {
+        for attribute in self.attributes.iter() {
+            shader.bind_attribute_location(attribute.location, attribute.name);
+        }
+    }
+
+    /// Checks that every input of a linked shader is fed by an attribute of this
+    /// layout, returning the names of the ones that are not.
+    pub fn check(&self, shader: &Shader) -> Result<(), Vec<String>> {
+        let missing = shader
+            .active_attributes()
+            .into_iter()
+            // Built-ins like gl_VertexID have no location
+            .filter(|(name, location)| !name.starts_with("gl_") && *location >= 0)
+            .filter(|(_, location)| {
+                !self
+                    .attributes
+                    .iter()
+                    .any(|a| a.location as GLint == *location)
+            })
+            .map(|(name, _)| name)
+            .collect::<Vec<_>>();
+
+        if missing.is_empty() {
+            Ok(())
+        } else {
+            Err(missing)
+        }
+    }
+}
+
+/// Builds a `VertexAttribute` for a float vector field of a vertex instance.
+macro_rules! float_attribute {
+    ($vertex:expr, $field:ident, $location:expr, $components:expr) => {
+        VertexAttribute {
+            name: stringify!($field),
+            location: $location,
+            components: $components,
+            ty: gl::FLOAT,
+            normalized: false,
+            integer: false,
+            offset: &$vertex.$field as *const _ as usize - &$vertex as *const _ as usize,
+        }
+    };
+}
+
+/// Like `float_attribute`, for a `u32` vector field read as a `uvec`.
+macro_rules! uint_attribute {
+    ($vertex:expr, $field:ident, $location:expr, $components:expr) => {
+        VertexAttribute {
+            ty: gl::UNSIGNED_INT,
+            integer: true,
+            ..float_attribute!($vertex, $field, $location, $components)
+        }
+    };
+}
+
+impl VertexFormat for mesh::Vertex {
+    fn layout() -> VertexLayout {
+        // Offsets are measured on a real instance rather than a null pointer
+        let v = mesh::Vertex::new(cgmath::Vector3::new(0.0, 0.0, 0.0));
+
+        VertexLayout {
+            stride: std::mem::size_of::<mesh::Vertex>(),
+            attributes: vec![
+                float_attribute!(v, position, 0, 3),
+                float_attribute!(v, normal, 1, 3),
+                float_attribute!(v, uv, 2, 2),
+                float_attribute!(v, tangent, 3, 4),
+                float_attribute!(v, color, 4, 4),
+                float_attribute!(v, uv1, 5, 2),
+                uint_attribute!(v, joints, 6, 4),
+                float_attribute!(v, weights, 7, 4),
+            ],
+        }
+    }
+}
+
+/// Creates a buffer texture with the morph targets of `mesh` for the vertex
+/// shader to fetch by `gl_VertexID`, `None` for meshes without any. Texel
+/// `2 * (target * vertices + vertex)` is the position delta of a triangle corner
+/// and the one after it the normal delta.
+pub fn upload_morph_targets(mesh: &mesh::Mesh) -> Option<GLuint> {
+    if mesh.morph_targets.is_empty() {
+        return None;
+    }
+
+    let vertices = mesh.vertices().len();
+    let zero = cgmath::Vector3::new(0.0, 0.0, 0.0);
+    let mut texels: Vec<[f32; 3]> = Vec::with_capacity(2 * vertices * mesh.morph_targets.len());
+    for target in mesh.morph_targets.iter() {
+        for i in 0..vertices {
+            let position = target.position_deltas.get(i).copied().unwrap_or(zero);
+            let normal = target.normal_deltas.get(i).copied().unwrap_or(zero);
+            texels.push(position.into());
+            texels.push(normal.into());
+        }
+    }
+
+    let mut buffer = 0;
+    let mut handle = 0;
+    unsafe {
+        gl::CreateBuffers(1, &mut buffer);
+        gl::NamedBufferData(
+            buffer,
+            std::mem::size_of_val(&texels[..]) as isize,
+            texels.as_ptr() as *const GLvoid,
+            gl::STATIC_DRAW,
+        );
+        gl::CreateTextures(gl::TEXTURE_BUFFER, 1, &mut handle);
+        gl::TextureBuffer(handle, gl::RGB32F, buffer);
+    }
+
+    Some(handle)
+}
+
+/// Indices and weights of the up to `MAX_MORPH_TARGETS` targets with the largest
+/// non-zero weights, for the `morph_indices` and `morph_weights` uniforms. Any
+/// further targets have the least effect and are left out.
+pub fn active_morph_targets(weights: &[f32]) -> (Vec<i32>, Vec<f32>) {
+    let mut active = weights
+        .iter()
+        .copied()
+        .enumerate()
+        .filter(|(_, w)| *w != 0.0)
+        .collect::<Vec<_>>();
+    active.sort_by(|a, b| {
+        b.1.abs()
+            .partial_cmp(&a.1.abs())
+            .unwrap_or(std::cmp::Ordering::Equal)
+    });
+    active.truncate(MAX_MORPH_TARGETS);
+    active.into_iter().map(|(i, w)| (i as i32, w)).unzip()
+}
+
+/// Creates a GL texture holding every mip level of `texture` as linear RGBA32F,
+/// with filtering and wrapping set up to match `sampler` on the software side.
+pub fn upload_texture(texture: &Texture2D, sampler: &Sampler) -> GLuint {
+    let mut handle = 0;
+
+    unsafe {
+        gl::CreateTextures(gl::TEXTURE_2D, 1, &mut handle);
+        gl::TextureStorage2D(
+            handle,
+            texture.mip_levels() as GLsizei,
+            gl::RGBA32F,
+            texture.width() as GLsizei,
+            texture.height() as GLsizei,
+        );
+
+        // Both store the bottom row first
+        for level in 0..texture.mip_levels() {
+            let (width, height) = texture.level_size(level);
+            gl::TextureSubImage2D(
+                handle,
+                level as GLint,
+                0,
+                0,
+                width as GLsizei,
+                height as GLsizei,
+                gl::RGBA,
+                gl::FLOAT,
+                texture.level_texels(level).as_ptr() as *const GLvoid,
+            );
+        }
+
+        let (min_filter, mag_filter) = match sampler.filter {
+            Filter::Nearest => (gl::NEAREST_MIPMAP_NEAREST, gl::NEAREST),
+            Filter::Bilinear => (gl::LINEAR_MIPMAP_NEAREST, gl::LINEAR),
+            Filter::Trilinear => (gl::LINEAR_MIPMAP_LINEAR, gl::LINEAR),
+        };
+        gl::TextureParameteri(handle, gl::TEXTURE_MIN_FILTER, min_filter as GLint);
+        gl::TextureParameteri(handle, gl::TEXTURE_MAG_FILTER, mag_filter as GLint);
+
+        let wrap = |wrap| match wrap {
+            Wrap::Repeat => gl::REPEAT,
+            Wrap::Clamp => gl::CLAMP_TO_EDGE,
+            Wrap::Mirror => gl::MIRRORED_REPEAT,
+        } as GLint;
+        gl::TextureParameteri(handle, gl::TEXTURE_WRAP_S, wrap(sampler.wrap_u));
+        gl::TextureParameteri(handle, gl::TEXTURE_WRAP_T, wrap(sampler.wrap_v));
+
+        // Drivers without anisotropic filtering leave the limit untouched at 0
+        let mut max_anisotropy = 0.0;
+        gl::GetFloatv(MAX_TEXTURE_MAX_ANISOTROPY, &mut max_anisotropy);
+        if max_anisotropy >= 1.0 {
+            gl::TextureParameterf(
+                handle,
+                TEXTURE_MAX_ANISOTROPY,
+                sampler.max_anisotropy.clamp(1.0, max_anisotropy),
+            );
+        }
+    }
+
+    handle
+}
+
+/// Sets up clipping, window space and face culling of the current context to
+/// follow `convention`, so it draws the same picture as the software rasterizer.
+pub fn apply_convention(convention: &Convention) {
+    let origin = match convention.framebuffer_y {
+        FramebufferY::Up => gl::LOWER_LEFT,
+        FramebufferY::Down => gl::UPPER_LEFT,
+    };
+    let depth = match convention.depth_range {
+        DepthRange::NegativeOneToOne => gl::NEGATIVE_ONE_TO_ONE,
+        DepthRange::ZeroToOne => gl::ZERO_TO_ONE,
+    };
+
+    unsafe {
+        gl::ClipControl(origin, depth);
+        gl::CullFace(gl::BACK);
+        gl::Enable(gl::CULL_FACE);
+    }
+    set_front_face(convention, false);
+}
+
+/// Sets which winding is in front for the following draws, `mirrored` for model
+/// matrices with a negative determinant.
+pub fn set_front_face(convention: &Convention, mirrored: bool) {
+    unsafe {
+        gl::FrontFace(front_face(convention, mirrored));
+    }
+}
+
+/// `glFrontFace` mode `set_front_face` picks.
+pub fn front_face(convention: &Convention, mirrored: bool) -> GLenum {
+    // An upper left origin already flips the winding GL computes, so only the
+    // handedness decides which faces are in front
+    if (convention.handedness == Handedness::Right) != mirrored {
+        gl::CCW
+    } else {
+        gl::CW
+    }
+}
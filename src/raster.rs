@@ -0,0 +1,592 @@
+use crate::convention::{Convention, FramebufferY};
+use crate::texture::{linear_to_srgb, Sampler, Texture2D, TextureBinding};
+
+use cgmath::prelude::*;
+use cgmath::{Vector2, Vector3, Vector4};
+
+use std::collections::HashMap;
+use std::error::Error;
+use std::fmt;
+
+pub type Vector2f = Vector2<f32>;
+pub type Vector3f = Vector3<f32>;
+
+#[derive(Copy, Clone, Debug)]
+pub struct Tri2(pub Vector2f, pub Vector2f, pub Vector2f);
+#[derive(Copy, Clone, Debug)]
+pub struct Tri3(pub Vector3f, pub Vector3f, pub Vector3f);
+
+impl Tri3 {
+    pub fn truncate(self) -> Tri2 {
+        Tri2(self.0.truncate(), self.1.truncate(), self.2.truncate())
+    }
+}
+
+#[derive(Copy, Clone, Debug)]
+pub struct Viewport {
+    pub x: i32,
+    pub y: i32,
+    pub width: u32,
+    pub height: u32,
+    pub convention: Convention,
+}
+
+impl Viewport {
+    /// Width / height ratio, for the projection.
+    pub fn aspect(&self) -> f32 {
+        self.width as f32 / self.height.max(1) as f32
+    }
+
+    /// Maps a clip space position to window space, with depth in [0, 1]. Window
+    /// y grows in the direction framebuffer rows are stored.
+    pub fn clip_to_window(&self, p: Vector4<f32>) -> Vector3f {
+        let (x_ndc, y_ndc, z_ndc) = (p.truncate() / p.w).into();
+        Vector3::new(
+            self.width as f32 * 0.5 * (x_ndc + 1.0) + self.x as f32,
+            self.height as f32 * 0.5 * (y_ndc + 1.0) + self.y as f32,
+            self.convention.window_depth(z_ndc),
+        )
+    }
+
+    /// Inverse of `clip_to_window` for a pixel center, giving normalized device
+    /// coordinates at the given window depth.
+    pub fn window_to_ndc(&self, x: f32, y: f32, depth: f32) -> Vector3f {
+        let (near, far) = self.convention.ndc_depth();
+        Vector3::new(
+            (x - self.x as f32) / self.width as f32 * 2.0 - 1.0,
+            (y - self.y as f32) / self.height as f32 * 2.0 - 1.0,
+            near + (far - near) * depth,
+        )
+    }
+}
+
+fn edge(p: Vector2f, v0: Vector2f, v1: Vector2f) -> f32 {
+    (p.x - v0.x) * (v1.y - v0.y) - (p.y - v0.y) * (v1.x - v0.x)
+}
+
+#[derive(Copy, Clone)]
+struct Bounds2 {
+    min_x: f32,
+    min_y: f32,
+    width: f32,
+    height: f32,
+}
+
+fn calculate_triangle_bounds(tri: Tri2) -> Bounds2 {
+    let points = [tri.0, tri.1, tri.2];
+
+    let mut min_x = 42000.0;
+    let mut max_x = 0.0;
+    let mut min_y = 42000.0;
+    let mut max_y = 0.0;
+
+    for p in points.iter() {
+        if p.x < min_x {
+            min_x = p.x;
+        }
+        if p.x > max_x {
+            max_x = p.x;
+        }
+        if p.y < min_y {
+            min_y = p.y;
+        }
+        if p.y > max_y {
+            max_y = p.y;
+        }
+    }
+
+    Bounds2 {
+        min_x,
+        min_y,
+        width: max_x - min_x,
+        height: max_y - min_y,
+    }
+}
+
+/// Whether a window space triangle of `viewport` faces the camera. `mirrored` is
+/// for corners moved by a model matrix with a negative determinant and left in
+/// their order, `Triangle::transformed` already reorders them. Degenerate
+/// triangles face neither way.
+pub fn is_front_facing(tri: Tri3, viewport: &Viewport, mirrored: bool) -> bool {
+    // `edge` is negative for counter clockwise triangles
+    let t = tri.truncate();
+    let area = edge(t.0, t.1, t.2);
+    if viewport.convention.front_face_ccw() != mirrored {
+        area < 0.0
+    } else {
+        area > 0.0
+    }
+}
+
+/// Calls `cb` with the pixel and barycentric coordinates of every pixel center
+/// inside `viewport` covered by a window space triangle of either winding.
+pub fn rasterize_window_space<F>(tri: Tri3, viewport: &Viewport, mut cb: F)
+where
+    F: FnMut((u32, u32), (f32, f32, f32)),
+{
+    let t = tri.truncate();
+    if edge(t.0, t.1, t.2) == 0.0 {
+        return;
+    }
+
+    let bounds = calculate_triangle_bounds(t);
+    let clamp_x = |x: f32| {
+        x.max(viewport.x as f32)
+            .min((viewport.x + viewport.width as i32) as f32)
+    };
+    let clamp_y = |y: f32| {
+        y.max(viewport.y as f32)
+            .min((viewport.y + viewport.height as i32) as f32)
+    };
+    let rast_min_x = clamp_x(bounds.min_x) as u32;
+    let rast_max_x = clamp_x(bounds.min_x + bounds.width + 1.0) as u32;
+    let rast_min_y = clamp_y(bounds.min_y) as u32;
+    let rast_max_y = clamp_y(bounds.min_y + bounds.height + 1.0) as u32;
+
+    for x in rast_min_x..rast_max_x {
+        for y in rast_min_y..rast_max_y {
+            let p = Vector2::new(x as f32 + 0.5, y as f32 + 0.5);
+
+            let (w0, w1, w2) = barycentric(t, p);
+
+            if (w0 >= 0.0) && (w1 >= 0.0) && (w2 >= 0.0) {
+                cb((x, y), (w0, w1, w2))
+            }
+        }
+    }
+}
+
+/// Barycentric coordinates of `p`, which are all positive inside the triangle.
+/// Also valid outside of it, e.g. to find attribute derivatives next to an edge.
+pub fn barycentric(tri: Tri2, p: Vector2f) -> (f32, f32, f32) {
+    let area = edge(tri.0, tri.1, tri.2);
+
+    (
+        edge(p, tri.1, tri.2) / area,
+        edge(p, tri.2, tri.0) / area,
+        edge(p, tri.0, tri.1) / area,
+    )
+}
+
+/// Interpolated window space depth at a pixel with the given barycentric
+/// coordinates.
+pub fn interpolate_depth(z: (f32, f32, f32), w: (f32, f32, f32)) -> f32 {
+    1.0 / ((1.0 / z.0) * w.0 + (1.0 / z.1) * w.1 + (1.0 / z.2) * w.2)
+}
+
+/// Perspective correct interpolation of a vertex attribute, `z` holds the window
+/// space depth of each corner and `d` the interpolated depth at the pixel.
+pub fn interpolate<T>(attribute: (T, T, T), z: (f32, f32, f32), w: (f32, f32, f32), d: f32) -> T
+where
+    T: VectorSpace<Scalar = f32>,
+{
+    (attribute.0 / z.0) * w.0 * d + (attribute.1 / z.1) * w.1 * d + (attribute.2 / z.2) * w.2 * d
+}
+
+/// Orthonormal tangent, bitangent and normal from the interpolated normal and
+/// tangent, the sign of `tangent.w` gives the handedness of the UV mapping.
+pub fn tangent_frame(normal: Vector3f, tangent: Vector4<f32>) -> (Vector3f, Vector3f, Vector3f) {
+    let n = normal.normalize();
+    let t = tangent.truncate();
+    let t = (t - n * n.dot(t)).normalize();
+    let handedness = if tangent.w < 0.0 { -1.0 } else { 1.0 };
+    (t, n.cross(t) * handedness, n)
+}
+
+/// Color and depth target of the software rasterizer.
+///
+/// Rows are stored in window space order, bottom up or top down as the
+/// convention says.
+#[derive(Clone, Debug)]
+pub struct Framebuffer {
+    width: u32,
+    height: u32,
+    convention: Convention,
+    /// Linear RGBA.
+    pub color: Vec<Vector4<f32>>,
+    /// Window space depth, 1 is the far plane.
+    pub depth: Vec<f32>,
+}
+
+impl Framebuffer {
+    /// A framebuffer cleared to transparent black at the far plane, in GL
+    /// conventions.
+    pub fn new(width: u32, height: u32) -> Framebuffer {
+        Framebuffer::with_convention(width, height, Convention::default())
+    }
+
+    pub fn with_convention(width: u32, height: u32, convention: Convention) -> Framebuffer {
+        let size = width as usize * height as usize;
+        Framebuffer {
+            width,
+            height,
+            convention,
+            color: vec![Vector4::zero(); size],
+            depth: vec![1.0; size],
+        }
+    }
+
+    pub fn width(&self) -> u32 {
+        self.width
+    }
+
+    pub fn height(&self) -> u32 {
+        self.height
+    }
+
+    pub fn convention(&self) -> Convention {
+        self.convention
+    }
+
+    /// Viewport covering the whole framebuffer.
+    pub fn viewport(&self) -> Viewport {
+        Viewport {
+            x: 0,
+            y: 0,
+            width: self.width,
+            height: self.height,
+            convention: self.convention,
+        }
+    }
+
+    pub fn index(&self, x: u32, y: u32) -> usize {
+        y as usize * self.width as usize + x as usize
+    }
+
+    pub fn clear(&mut self, color: Vector4<f32>, depth: f32) {
+        self.color.iter_mut().for_each(|c| *c = color);
+        self.depth.iter_mut().for_each(|d| *d = depth);
+    }
+
+    /// Samples the color buffer, e.g. for post processing or reflections.
+    pub fn color_texture(&self) -> Texture2D {
+        let texels = self.bottom_up().map(|i| self.color[i]).collect();
+        Texture2D::from_texels(self.width, self.height, texels)
+    }
+
+    /// Samples the depth buffer, e.g. as a shadow map. Depth is in every channel
+    /// and there are no mip levels, since averaged depths are not depths.
+    pub fn depth_texture(&self) -> Texture2D {
+        let texels = self
+            .bottom_up()
+            .map(|i| Vector4::new(self.depth[i], self.depth[i], self.depth[i], 1.0))
+            .collect();
+        Texture2D::single_level(self.width, self.height, texels)
+    }
+
+    /// UV of the center of a pixel in the textures made from this framebuffer.
+    pub fn pixel_uv(&self, x: u32, y: u32) -> Vector2f {
+        let row = match self.convention.framebuffer_y {
+            FramebufferY::Up => y,
+            FramebufferY::Down => self.height - (y + 1),
+        };
+        Vector2::new(
+            (x as f32 + 0.5) / self.width as f32,
+            (row as f32 + 0.5) / self.height as f32,
+        )
+    }
+
+    /// Texel indices with the bottom row first, the order `Texture2D` stores.
+    fn bottom_up(&self) -> impl Iterator<Item = usize> + '_ {
+        (0..self.height).flat_map(move |y| {
+            let row = match self.convention.framebuffer_y {
+                FramebufferY::Up => y,
+                FramebufferY::Down => self.height - (y + 1),
+            };
+            (0..self.width).map(move |x| self.index(x, row))
+        })
+    }
+
+    /// Converts the linear color buffer to an 8 bit sRGB image, top row first.
+    pub fn to_image(&self) -> image::RgbImage {
+        let encode = |c: f32| (linear_to_srgb(c.clamp(0.0, 1.0)) * 255.0).round() as u8;
+        image::ImageBuffer::from_fn(self.width, self.height, |x, y| {
+            let row = match self.convention.framebuffer_y {
+                FramebufferY::Up => self.height - (y + 1),
+                FramebufferY::Down => y,
+            };
+            let c = self.color[self.index(x, row)];
+            image::Rgb([encode(c.x), encode(c.y), encode(c.z)])
+        })
+    }
+}
+
+#[derive(Debug)]
+pub enum RenderGraphError {
+    /// A pass reads a target that no pass writes.
+    MissingInput { pass: String, input: String },
+    /// Passes depend on each other, the names form the loop.
+    Cycle(Vec<String>),
+}
+
+impl fmt::Display for RenderGraphError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            RenderGraphError::MissingInput { pass, input } => {
+                write!(f, "pass {} reads {}, which no pass writes", pass, input)
+            }
+            RenderGraphError::Cycle(passes) => {
+                write!(f, "passes depend on each other: {}", passes.join(" -> "))
+            }
+        }
+    }
+}
+
+impl Error for RenderGraphError {}
+
+/// Finished targets of the passes a pass declared as inputs.
+pub struct PassInputs<'g> {
+    pass: &'g str,
+    targets: HashMap<&'g str, &'g Framebuffer>,
+}
+
+impl<'g> PassInputs<'g> {
+    /// Panics if `name` was not declared as an input of the pass, since it would
+    /// only be available by chance of the execution order.
+    pub fn get(&self, name: &str) -> &'g Framebuffer {
+        self.targets
+            .get(name)
+            .copied()
+            .unwrap_or_else(|| panic!("pass {} did not declare {} as an input", self.pass, name))
+    }
+
+    /// The color target of input `name` as a texture, bound under the same name.
+    pub fn color_binding(&self, name: &str, sampler: Sampler) -> TextureBinding {
+        TextureBinding {
+            name: name.to_owned(),
+            texture: self.get(name).color_texture(),
+            sampler,
+        }
+    }
+}
+
+type PassFn<'a> = Box<dyn FnMut(&mut Framebuffer, &PassInputs) + 'a>;
+
+struct Pass<'a> {
+    name: String,
+    width: u32,
+    height: u32,
+    reads: Vec<String>,
+    run: PassFn<'a>,
+}
+
+/// Software render passes, each drawing into its own framebuffer named after
+/// the pass. Passes are run in dependency order and only if an output needs them.
+#[derive(Default)]
+pub struct RenderGraph<'a> {
+    passes: Vec<Pass<'a>>,
+    convention: Convention,
+}
+
+impl<'a> RenderGraph<'a> {
+    pub fn new() -> RenderGraph<'a> {
+        RenderGraph::default()
+    }
+
+    /// Uses `convention` for every framebuffer the passes render into.
+    pub fn with_convention(mut self, convention: Convention) -> RenderGraph<'a> {
+        self.convention = convention;
+        self
+    }
+
+    /// Adds a pass rendering into a fresh `width` x `height` framebuffer, which
+    /// can read the finished targets of the passes named in `reads`.
+    pub fn add_pass<F>(&mut self, name: &str, width: u32, height: u32, reads: &[&str], run: F)
+    where
+        F: FnMut(&mut Framebuffer, &PassInputs) + 'a,
+    {
+        assert!(
+            self.passes.iter().all(|p| p.name != name),
+            "pass {} added twice",
+            name
+        );
+
+        self.passes.push(Pass {
+            name: name.to_owned(),
+            width,
+            height,
+            reads: reads.iter().map(|r| (*r).to_owned()).collect(),
+            run: Box::new(run),
+        });
+    }
+
+    /// Runs every pass `outputs` depend on and returns all targets rendered.
+    pub fn execute(
+        mut self,
+        outputs: &[&str],
+    ) -> Result<HashMap<String, Framebuffer>, RenderGraphError> {
+        let order = self.schedule(outputs)?;
+
+        let mut targets = HashMap::new();
+        for index in order {
+            let pass = &mut self.passes[index];
+            let mut target = Framebuffer::with_convention(pass.width, pass.height, self.convention);
+
+            let inputs = PassInputs {
+                pass: &pass.name,
+                targets: pass
+                    .reads
+                    .iter()
+                    .map(|r| (r.as_str(), &targets[r]))
+                    .collect(),
+            };
+            (pass.run)(&mut target, &inputs);
+
+            targets.insert(pass.name.clone(), target);
+        }
+
+        Ok(targets)
+    }
+
+    /// Pass indices in execution order, depth first from the outputs.
+    fn schedule(&self, outputs: &[&str]) -> Result<Vec<usize>, RenderGraphError> {
+        #[derive(Copy, Clone, PartialEq)]
+        enum State {
+            New,
+            Visiting,
+            Done,
+        }
+
+        fn visit(
+            graph: &RenderGraph,
+            index: usize,
+            states: &mut Vec<State>,
+            stack: &mut Vec<String>,
+            order: &mut Vec<usize>,
+        ) -> Result<(), RenderGraphError> {
+            let pass = &graph.passes[index];
+            match states[index] {
+                State::Done => return Ok(()),
+                State::Visiting => {
+                    let start = stack.iter().position(|n| *n == pass.name).unwrap();
+                    let mut cycle = stack[start..].to_vec();
+                    cycle.push(pass.name.clone());
+                    return Err(RenderGraphError::Cycle(cycle));
+                }
+                State::New => {}
+            }
+
+            states[index] = State::Visiting;
+            stack.push(pass.name.clone());
+            for input in pass.reads.iter() {
+                let dependency =
+                    graph
+                        .find(input)
+                        .ok_or_else(|| RenderGraphError::MissingInput {
+                            pass: pass.name.clone(),
+                            input: input.clone(),
+                        })?;
+                visit(graph, dependency, states, stack, order)?;
+            }
+            stack.pop();
+            states[index] = State::Done;
+            order.push(index);
+            Ok(())
+        }
+
+        let mut states = vec![State::New; self.passes.len()];
+        let mut order = Vec::new();
+        for output in outputs {
+            let index = self
+                .find(output)
+                .ok_or_else(|| RenderGraphError::MissingInput {
+                    pass: "<output>".to_owned(),
+                    input: (*output).to_owned(),
+                })?;
+            visit(self, index, &mut states, &mut Vec::new(), &mut order)?;
+        }
+
+        Ok(order)
+    }
+
+    fn find(&self, name: &str) -> Option<usize> {
+        self.passes.iter().position(|p| p.name == name)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::camera::{Camera, Projection};
+    use crate::mesh::{Triangle, Vertex};
+    use crate::transform::Transform;
+    use cgmath::{Deg, Matrix, Matrix3, Matrix4, One, Point3, Quaternion, SquareMatrix};
+
+    /// Window space corners of `tri` seen from +Z by a camera of `convention`.
+    fn project(tri: &Triangle, viewport: &Viewport) -> Tri3 {
+        let transform = Transform::new(
+            Point3::new(0.0, 0.0, 5.0),
+            Quaternion::one(),
+            Vector3::new(1.0, 1.0, 1.0),
+        );
+        let mut camera = Camera::new(
+            transform,
+            Projection::Perspective {
+                fovy: Deg(60.0).into(),
+                near: 0.1,
+                far: 100.0,
+            },
+        )
+        .with_convention(viewport.convention);
+        camera.look_at(Point3::new(0.0, 0.0, 0.0), Vector3::unit_y());
+
+        let corner = |v: &Vertex| {
+            camera
+                .project(viewport, Point3::from_vec(v.position))
+                .unwrap()
+        };
+        Tri3(corner(&tri.0), corner(&tri.1), corner(&tri.2))
+    }
+
+    #[test]
+    fn mirrored_instances_keep_their_front_faces() {
+        // Counter clockwise seen from +Z in a right handed world. Left handed
+        // conventions see the world mirrored and take clockwise triangles as
+        // front faces, so it is in front for every convention.
+        let tri = Triangle(
+            Vertex::new(Vector3::new(-1.0, -1.0, 0.0)),
+            Vertex::new(Vector3::new(1.0, -1.0, 0.0)),
+            Vertex::new(Vector3::new(0.0, 1.0, 0.0)),
+        );
+        let model = Matrix4::from_nonuniform_scale(-1.0, 1.0, 1.0);
+        let linear = Matrix3::from_cols(model.x.truncate(), model.y.truncate(), model.z.truncate());
+        let normal_matrix = linear.invert().unwrap().transpose();
+
+        for convention in [Convention::GL, Convention::DIRECT3D, Convention::VULKAN].iter() {
+            let viewport = Viewport {
+                x: 0,
+                y: 0,
+                width: 64,
+                height: 48,
+                convention: *convention,
+            };
+
+            assert!(is_front_facing(project(&tri, &viewport), &viewport, false));
+
+            // Moved corners in their original order need the mirrored flag, the
+            // reordered ones from `Triangle::transformed` must not get it
+            let mirrored = Tri3(
+                (model * tri.0.position.extend(1.0)).truncate(),
+                (model * tri.1.position.extend(1.0)).truncate(),
+                (model * tri.2.position.extend(1.0)).truncate(),
+            );
+            let mirrored = Triangle(
+                Vertex::new(mirrored.0),
+                Vertex::new(mirrored.1),
+                Vertex::new(mirrored.2),
+            );
+            assert!(is_front_facing(
+                project(&mirrored, &viewport),
+                &viewport,
+                true
+            ));
+
+            let transformed = tri.transformed(&model, &normal_matrix);
+            assert!(is_front_facing(
+                project(&transformed, &viewport),
+                &viewport,
+                false
+            ));
+        }
+    }
+}
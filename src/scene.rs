@@ -0,0 +1,378 @@
+use crate::bounds::{BoundingSphere, Bounds, Frustum};
+use crate::mesh::Mesh;
+use crate::skinning::{JointPalette, Skeleton};
+use crate::texture::{NormalMapBinding, TextureBinding};
+use crate::transform::{self, Transform};
+
+use cgmath::prelude::*;
+use cgmath::{Matrix3, Matrix4, Vector3};
+
+use std::fmt;
+
+pub mod file;
+pub mod gltf;
+
+pub use self::gltf::{import_gltf, GltfImport};
+pub use file::{load_scene, SceneError, SceneErrorKind, SceneSetup};
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub struct MeshId(usize);
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub struct MaterialId(usize);
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub struct NodeId(usize);
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub struct SkinId(usize);
+
+/// Textures a mesh is shaded with, bound to the shader inputs they are named
+/// after.
+#[derive(Clone, Debug, Default)]
+pub struct Material {
+    pub name: String,
+    pub textures: Vec<TextureBinding>,
+    pub normal_maps: Vec<NormalMapBinding>,
+}
+
+#[derive(Clone, Debug)]
+pub struct Node {
+    pub name: String,
+    /// Relative to the parent node.
+    pub transform: Transform,
+    /// Drawn with the node's world matrix if set.
+    pub mesh: Option<(MeshId, MaterialId)>,
+    /// Skeleton deforming the mesh.
+    pub skin: Option<SkinId>,
+    /// Weights of the morph targets of the mesh, indexed like them. Missing
+    /// weights are zero.
+    pub morph_weights: Vec<f32>,
+    parent: Option<NodeId>,
+    children: Vec<NodeId>,
+}
+
+impl Node {
+    pub fn new(name: &str, transform: Transform) -> Node {
+        Node {
+            name: name.to_owned(),
+            transform,
+            mesh: None,
+            skin: None,
+            morph_weights: Vec::new(),
+            parent: None,
+            children: Vec::new(),
+        }
+    }
+
+    pub fn with_mesh(mut self, mesh: MeshId, material: MaterialId) -> Node {
+        self.mesh = Some((mesh, material));
+        self
+    }
+
+    pub fn with_skin(mut self, skin: SkinId) -> Node {
+        self.skin = Some(skin);
+        self
+    }
+
+    pub fn parent(&self) -> Option<NodeId> {
+        self.parent
+    }
+
+    pub fn children(&self) -> &[NodeId] {
+        &self.children
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Light {
+    /// Infinitely far away light, `direction` points towards it.
+    Directional {
+        direction: Vector3<f32>,
+        intensity: f32,
+    },
+}
+
+impl Light {
+    /// Direction towards the light scaled by its intensity, which is how the
+    /// shaders take it as their `light` input.
+    pub fn shader_input(&self) -> Vector3<f32> {
+        match *self {
+            Light::Directional {
+                direction,
+                intensity,
+            } => direction.normalize() * intensity,
+        }
+    }
+}
+
+/// A mesh placed in the world, ready to be drawn.
+#[derive(Copy, Clone, Debug)]
+pub struct Instance {
+    pub node: NodeId,
+    pub mesh: MeshId,
+    pub material: MaterialId,
+    pub skin: Option<SkinId>,
+    pub model: Matrix4<f32>,
+    /// Inverse transpose of the upper 3x3 of `model`.
+    pub normal_matrix: Matrix3<f32>,
+    /// World space bounds of the mesh in its current pose.
+    pub bounds: Bounds,
+}
+
+impl Instance {
+    /// Whether `model` mirrors, which turns the winding of triangles around.
+    pub fn is_mirrored(&self) -> bool {
+        self.normal_matrix.determinant() < 0.0
+    }
+}
+
+/// Counts of what a frame drew, see `Scene::cull`.
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
+pub struct FrameStats {
+    pub instances: usize,
+    /// Instances outside the view frustum, which were skipped.
+    pub culled: usize,
+    /// Triangles of the instances that were drawn.
+    pub triangles: usize,
+}
+
+impl fmt::Display for FrameStats {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{} instances, {} culled, {} triangles",
+            self.instances, self.culled, self.triangles
+        )
+    }
+}
+
+/// Meshes and materials with a tree of nodes placing them. Meshes can be drawn
+/// by any number of nodes, each of which is an instance.
+#[derive(Clone, Debug, Default)]
+pub struct Scene {
+    pub lights: Vec<Light>,
+    meshes: Vec<Mesh>,
+    /// Object space bounds of every mesh, indexed like the meshes.
+    mesh_bounds: Vec<Bounds>,
+    materials: Vec<Material>,
+    skins: Vec<Skeleton>,
+    nodes: Vec<Node>,
+    roots: Vec<NodeId>,
+}
+
+impl Scene {
+    pub fn new() -> Scene {
+        Scene::default()
+    }
+
+    pub fn add_mesh(&mut self, mesh: Mesh) -> MeshId {
+        self.mesh_bounds.push(mesh.morph_bounds());
+        self.meshes.push(mesh);
+        MeshId(self.meshes.len() - 1)
+    }
+
+    pub fn add_material(&mut self, material: Material) -> MaterialId {
+        self.materials.push(material);
+        MaterialId(self.materials.len() - 1)
+    }
+
+    pub fn add_skin(&mut self, skin: Skeleton) -> SkinId {
+        self.skins.push(skin);
+        SkinId(self.skins.len() - 1)
+    }
+
+    /// Adds `node` as a child of `parent`, or as a root without one.
+    pub fn add_node(&mut self, parent: Option<NodeId>, mut node: Node) -> NodeId {
+        let id = NodeId(self.nodes.len());
+        node.parent = parent;
+        node.children.clear();
+        self.nodes.push(node);
+
+        match parent {
+            Some(parent) => self.nodes[parent.0].children.push(id),
+            None => self.roots.push(id),
+        }
+        id
+    }
+
+    pub fn mesh(&self, id: MeshId) -> &Mesh {
+        &self.meshes[id.0]
+    }
+
+    /// Replaces a mesh everywhere it is drawn, e.g. by one of its detail levels.
+    pub fn set_mesh(&mut self, id: MeshId, mesh: Mesh) {
+        self.mesh_bounds[id.0] = mesh.morph_bounds();
+        self.meshes[id.0] = mesh;
+    }
+
+    /// Bounds of a mesh in object space, covering every blend of its morph
+    /// targets, see `Mesh::morph_bounds`.
+    pub fn mesh_bounds(&self, id: MeshId) -> &Bounds {
+        &self.mesh_bounds[id.0]
+    }
+
+    pub fn meshes(&self) -> impl Iterator<Item = (MeshId, &Mesh)> {
+        self.meshes.iter().enumerate().map(|(i, m)| (MeshId(i), m))
+    }
+
+    pub fn material(&self, id: MaterialId) -> &Material {
+        &self.materials[id.0]
+    }
+
+    pub fn materials(&self) -> impl Iterator<Item = (MaterialId, &Material)> {
+        self.materials
+            .iter()
+            .enumerate()
+            .map(|(i, m)| (MaterialId(i), m))
+    }
+
+    pub fn skin(&self, id: SkinId) -> &Skeleton {
+        &self.skins[id.0]
+    }
+
+    pub fn skin_mut(&mut self, id: SkinId) -> &mut Skeleton {
+        &mut self.skins[id.0]
+    }
+
+    pub fn node(&self, id: NodeId) -> &Node {
+        &self.nodes[id.0]
+    }
+
+    pub fn node_mut(&mut self, id: NodeId) -> &mut Node {
+        &mut self.nodes[id.0]
+    }
+
+    pub fn roots(&self) -> &[NodeId] {
+        &self.roots
+    }
+
+    pub fn find_node(&self, name: &str) -> Option<NodeId> {
+        self.nodes.iter().position(|n| n.name == name).map(NodeId)
+    }
+
+    /// World matrix of every node, indexed like the nodes. Parents are composed as
+    /// matrices, so non-uniform scale above rotated children shears correctly.
+    pub fn world_matrices(&self) -> Vec<Matrix4<f32>> {
+        let mut world = vec![Matrix4::identity(); self.nodes.len()];
+        let mut stack: Vec<(NodeId, Matrix4<f32>)> = self
+            .roots
+            .iter()
+            .map(|r| (*r, Matrix4::identity()))
+            .collect();
+
+        while let Some((id, parent)) = stack.pop() {
+            let node = &self.nodes[id.0];
+            world[id.0] = parent * node.transform.to_matrix();
+            stack.extend(node.children.iter().map(|c| (*c, world[id.0])));
+        }
+
+        world
+    }
+
+    pub fn world_matrix(&self, id: NodeId) -> Matrix4<f32> {
+        let local = self.nodes[id.0].transform.to_matrix();
+        match self.nodes[id.0].parent {
+            Some(parent) => self.world_matrix(parent) * local,
+            None => local,
+        }
+    }
+
+    /// Every node with a mesh, in tree order.
+    pub fn instances(&self) -> Vec<Instance> {
+        let world = self.world_matrices();
+        let mut instances = Vec::new();
+
+        let mut stack: Vec<NodeId> = self.roots.iter().rev().copied().collect();
+        while let Some(id) = stack.pop() {
+            let node = &self.nodes[id.0];
+            if let Some((mesh, material)) = node.mesh {
+                instances.push(Instance {
+                    node: id,
+                    mesh,
+                    material,
+                    skin: node.skin,
+                    model: world[id.0],
+                    normal_matrix: transform::normal_matrix(&world[id.0]),
+                    bounds: self.posed_bounds(mesh, node.skin, &world[id.0], &world),
+                });
+            }
+            stack.extend(node.children.iter().rev());
+        }
+
+        instances
+    }
+
+    /// World space bounds of a mesh drawn with `model`. A skinned vertex blends
+    /// its position under several joints, so the box around the mesh moved by
+    /// every joint holds it. That is exact for linear blend skinning, dual
+    /// quaternions can reach slightly further around strongly twisted joints.
+    fn posed_bounds(
+        &self,
+        mesh: MeshId,
+        skin: Option<SkinId>,
+        model: &Matrix4<f32>,
+        world: &[Matrix4<f32>],
+    ) -> Bounds {
+        let bounds = &self.mesh_bounds[mesh.0];
+        let skin = match skin {
+            Some(skin) => &self.skins[skin.0],
+            None => return bounds.transformed(model),
+        };
+
+        // Vertices without weights stay where `model` puts them
+        let aabb = skin
+            .joints
+            .iter()
+            .zip(skin.inverse_bind_matrices.iter())
+            .map(|(joint, inverse_bind)| bounds.aabb.transformed(&(world[joint.0] * inverse_bind)))
+            .fold(bounds.aabb.transformed(model), |acc, b| acc.union(&b));
+        Bounds {
+            aabb,
+            sphere: aabb.bounding_sphere(),
+        }
+    }
+
+    /// Splits off the instances outside `frustum`, returning the rest and how
+    /// many of each there were.
+    pub fn cull(&self, instances: &[Instance], frustum: &Frustum) -> (Vec<Instance>, FrameStats) {
+        let visible: Vec<Instance> = instances
+            .iter()
+            .filter(|i| frustum.intersects(&i.bounds))
+            .copied()
+            .collect();
+        let stats = FrameStats {
+            instances: instances.len(),
+            culled: instances.len() - visible.len(),
+            triangles: visible
+                .iter()
+                .map(|i| self.meshes[i.mesh.0].triangles.len())
+                .sum(),
+        };
+        (visible, stats)
+    }
+
+    /// Joints of a skinned instance in its current pose, mapping the mesh in bind
+    /// pose to where the joints have moved it. Like in glTF the joints place the
+    /// mesh relative to its node, which `model` then places in the world.
+    pub fn joint_palette(&self, instance: &Instance) -> Option<JointPalette> {
+        let skin = &self.skins[instance.skin?.0];
+        let world = self.world_matrices();
+        let mesh_from_world = instance.model.invert()?;
+
+        let matrices = skin
+            .joints
+            .iter()
+            .zip(skin.inverse_bind_matrices.iter())
+            .map(|(joint, inverse_bind)| mesh_from_world * world[joint.0] * inverse_bind)
+            .collect();
+        Some(JointPalette::new(matrices, skin.method))
+    }
+
+    /// Sphere around every instance in world space, `None` for an empty scene.
+    pub fn bounding_sphere(&self) -> Option<BoundingSphere> {
+        self.instances()
+            .iter()
+            .map(|i| i.bounds.sphere)
+            .fold(None, |acc: Option<BoundingSphere>, s| {
+                Some(acc.map_or(s, |acc| acc.union(&s)))
+            })
+    }
+}
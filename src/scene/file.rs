@@ -0,0 +1,828 @@
+use super::{import_gltf, Light, Material, MaterialId, MeshId, Node, NodeId, Scene};
+use crate::animation::{
+    Animatable, Animation, CameraTracks, Interpolation, MorphTracks, Timeline, Track,
+    TransformTracks,
+};
+use crate::camera::{Camera, Projection};
+use crate::convention::Convention;
+use crate::mesh::{self, LodChain, Mesh, MeshError, NormalMode, NormalWeighting};
+use crate::skinning::Skinning;
+use crate::texture::{
+    ColorSpace, Environment, EnvironmentBinding, Lookup, NormalMapBinding, NormalMapConvention,
+    Sampler, Texture2D, TextureBinding, TextureCube,
+};
+use crate::transform::Transform;
+
+use cgmath::prelude::*;
+use cgmath::{Deg, Euler, Point3, Quaternion, Rad, Vector3};
+use serde::Deserialize;
+
+use std::collections::BTreeMap;
+use std::error::Error;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+/// Where the software renderer writes its image, and the preview window size.
+#[derive(Clone, Debug, Deserialize)]
+pub struct Output {
+    pub width: u32,
+    pub height: u32,
+    pub path: PathBuf,
+    /// Renders every frame of the timeline as well, numbered like
+    /// `animation::frame_path`.
+    #[serde(default)]
+    pub frames: Option<PathBuf>,
+}
+
+impl Default for Output {
+    fn default() -> Output {
+        Output {
+            width: 800,
+            height: 600,
+            path: PathBuf::from("output.png"),
+            frames: None,
+        }
+    }
+}
+
+#[derive(Clone, Debug, Deserialize)]
+pub struct Shaders {
+    /// GLSL stages of the preview.
+    pub vertex: PathBuf,
+    pub fragment: PathBuf,
+    /// Shader the software renderer runs per pixel.
+    pub shadelang: PathBuf,
+}
+
+/// Everything a scene file sets up.
+#[derive(Clone, Debug)]
+pub struct SceneSetup {
+    pub scene: Scene,
+    pub camera: Camera,
+    pub output: Output,
+    pub shaders: Shaders,
+    pub environment: Option<EnvironmentBinding>,
+    /// Detail levels of meshes that asked for them, the scene holds the full mesh.
+    pub lods: Vec<(MeshId, LodChain)>,
+    pub animation: Option<Animation>,
+    /// Covers the whole animation unless the file says otherwise, a single frame
+    /// without one. The scene and camera are posed at its start.
+    pub timeline: Timeline,
+}
+
+#[derive(Debug)]
+pub enum SceneErrorKind {
+    Io(io::Error),
+    Parse(ron::Error),
+    Mesh(MeshError),
+    Texture {
+        path: PathBuf,
+        error: image::ImageError,
+    },
+    Gltf {
+        path: PathBuf,
+        error: gltf::Error,
+    },
+    UnknownMesh(String),
+    UnknownMaterial(String),
+    UnknownNode(String),
+    /// A node draws a mesh or imports a glTF file without saying which material.
+    MissingMaterial(String),
+    UnsupportedMesh(PathBuf),
+    /// A morph target file whose triangles do not line up with its mesh.
+    MorphTargetMismatch(PathBuf),
+    /// A mesh with both morph targets and simplified levels, which can't keep them.
+    MorphedLod(String),
+    UnknownMorphTarget {
+        node: String,
+        target: String,
+    },
+}
+
+/// Error returned by `load_scene`, the file is the scene file itself. Errors in
+/// files it refers to carry their own path.
+#[derive(Debug)]
+pub struct SceneError {
+    pub file: Option<PathBuf>,
+    pub kind: SceneErrorKind,
+}
+
+impl SceneError {
+    pub fn new(kind: SceneErrorKind) -> SceneError {
+        SceneError { file: None, kind }
+    }
+
+    pub fn in_file(mut self, file: PathBuf) -> SceneError {
+        self.file = Some(file);
+        self
+    }
+}
+
+impl From<io::Error> for SceneError {
+    fn from(e: io::Error) -> SceneError {
+        SceneError::new(SceneErrorKind::Io(e))
+    }
+}
+
+impl From<MeshError> for SceneError {
+    fn from(e: MeshError) -> SceneError {
+        SceneError::new(SceneErrorKind::Mesh(e))
+    }
+}
+
+impl fmt::Display for SceneErrorKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            SceneErrorKind::Io(e) => write!(f, "{}", e),
+            SceneErrorKind::Parse(e) => write!(f, "{}", e),
+            SceneErrorKind::Mesh(e) => write!(f, "{}", e),
+            SceneErrorKind::Texture { path, error } => write!(f, "{}: {}", path.display(), error),
+            SceneErrorKind::Gltf { path, error } => write!(f, "{}: {}", path.display(), error),
+            SceneErrorKind::UnknownMesh(name) => write!(f, "no mesh named {}", name),
+            SceneErrorKind::UnknownMaterial(name) => write!(f, "no material named {}", name),
+            SceneErrorKind::UnknownNode(name) => write!(f, "no node named {}", name),
+            SceneErrorKind::MissingMaterial(node) => {
+                write!(f, "node {} has a mesh but no material", node)
+            }
+            SceneErrorKind::UnsupportedMesh(path) => {
+                write!(f, "{}: not a .ply, .obj or .stl file", path.display())
+            }
+            SceneErrorKind::MorphTargetMismatch(path) => write!(
+                f,
+                "{}: triangles do not match the mesh it morphs",
+                path.display()
+            ),
+            SceneErrorKind::MorphedLod(name) => write!(
+                f,
+                "mesh {} has morph targets, it can't have lod_levels too",
+                name
+            ),
+            SceneErrorKind::UnknownMorphTarget { node, target } => {
+                write!(
+                    f,
+                    "the mesh of node {} has no morph target {}",
+                    node, target
+                )
+            }
+        }
+    }
+}
+
+impl fmt::Display for SceneError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if let Some(file) = &self.file {
+            write!(f, "{}: ", file.display())?;
+        }
+        write!(f, "{}", self.kind)
+    }
+}
+
+impl Error for SceneError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match &self.kind {
+            SceneErrorKind::Io(e) => Some(e),
+            SceneErrorKind::Parse(e) => Some(e),
+            SceneErrorKind::Mesh(e) => Some(e),
+            SceneErrorKind::Texture { error, .. } => Some(error),
+            SceneErrorKind::Gltf { error, .. } => Some(error),
+            _ => None,
+        }
+    }
+}
+
+type Vec3 = (f32, f32, f32);
+
+fn one() -> f32 {
+    1.0
+}
+
+fn linear() -> Interpolation {
+    Interpolation::Linear
+}
+
+fn default_fps() -> f32 {
+    24.0
+}
+
+fn unit_scale() -> Vec3 {
+    (1.0, 1.0, 1.0)
+}
+
+fn y_up() -> Vec3 {
+    (0.0, 1.0, 0.0)
+}
+
+fn vector(v: Vec3) -> Vector3<f32> {
+    Vector3::new(v.0, v.1, v.2)
+}
+
+/// Euler angles in degrees, applied X first.
+fn rotation(degrees: Vec3) -> Quaternion<f32> {
+    Quaternion::from(Euler::new(Deg(degrees.0), Deg(degrees.1), Deg(degrees.2)))
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct SceneFile {
+    #[serde(default)]
+    output: Output,
+    #[serde(default)]
+    convention: Convention,
+    camera: CameraDesc,
+    shaders: Shaders,
+    #[serde(default)]
+    environment: Option<EnvironmentDesc>,
+    #[serde(default)]
+    meshes: BTreeMap<String, MeshDesc>,
+    #[serde(default)]
+    materials: BTreeMap<String, MaterialDesc>,
+    #[serde(default)]
+    lights: Vec<LightDesc>,
+    #[serde(default)]
+    nodes: Vec<NodeDesc>,
+    #[serde(default)]
+    animation: Option<AnimationDesc>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct CameraDesc {
+    #[serde(default)]
+    position: Vec3,
+    /// Euler angles in degrees, ignored with `look_at`.
+    #[serde(default)]
+    rotation: Vec3,
+    #[serde(default)]
+    look_at: Option<Vec3>,
+    #[serde(default = "y_up")]
+    up: Vec3,
+    projection: ProjectionDesc,
+    /// Moves the camera along its view axis until every instance is in view.
+    #[serde(default)]
+    frame: bool,
+}
+
+#[derive(Deserialize)]
+enum ProjectionDesc {
+    /// `fovy` in degrees.
+    Perspective {
+        fovy: f32,
+        near: f32,
+        far: f32,
+    },
+    Orthographic {
+        height: f32,
+        near: f32,
+        far: f32,
+    },
+    OffAxis {
+        left: f32,
+        right: f32,
+        bottom: f32,
+        top: f32,
+        near: f32,
+        far: f32,
+    },
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct EnvironmentDesc {
+    name: String,
+    source: EnvironmentSource,
+    #[serde(default)]
+    sampler: Sampler,
+    lookup: Lookup,
+}
+
+#[derive(Deserialize)]
+enum EnvironmentSource {
+    Equirectangular(PathBuf),
+    /// Faces laid out as a horizontal or vertical cross.
+    Cross(PathBuf),
+    /// +X, -X, +Y, -Y, +Z, -Z.
+    Faces([PathBuf; 6]),
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct MeshDesc {
+    path: PathBuf,
+    /// Number of simplified levels to generate, none by default. Not for meshes
+    /// with morph targets.
+    #[serde(default)]
+    lod_levels: usize,
+    /// Named shapes of the mesh, each from a file with the same triangles in the
+    /// same order, like a set of PLY files exported from one base mesh.
+    #[serde(default)]
+    morph_targets: Vec<(String, PathBuf)>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct MaterialDesc {
+    #[serde(default)]
+    sampler: Sampler,
+    #[serde(default)]
+    textures: Vec<TextureDesc>,
+    #[serde(default)]
+    normal_maps: Vec<NormalMapDesc>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct TextureDesc {
+    name: String,
+    path: PathBuf,
+    color_space: ColorSpace,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct NormalMapDesc {
+    name: String,
+    path: PathBuf,
+    convention: NormalMapConvention,
+}
+
+#[derive(Deserialize)]
+enum LightDesc {
+    /// `direction` points towards the light.
+    Directional {
+        direction: Vec3,
+        #[serde(default = "one")]
+        intensity: f32,
+    },
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct NodeDesc {
+    name: String,
+    #[serde(default)]
+    position: Vec3,
+    /// Euler angles in degrees.
+    #[serde(default)]
+    rotation: Vec3,
+    #[serde(default = "unit_scale")]
+    scale: Vec3,
+    #[serde(default)]
+    mesh: Option<String>,
+    #[serde(default)]
+    material: Option<String>,
+    /// By morph target name of `mesh`.
+    #[serde(default)]
+    morph_weights: BTreeMap<String, f32>,
+    /// Imports the scene of a glTF file below this node, drawn with `material`.
+    #[serde(default)]
+    gltf: Option<PathBuf>,
+    /// How the skins of `gltf` deform their meshes.
+    #[serde(default)]
+    skinning: Skinning,
+    #[serde(default)]
+    children: Vec<NodeDesc>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct AnimationDesc {
+    #[serde(default)]
+    timeline: Option<TimelineDesc>,
+    /// Tracks by node name.
+    #[serde(default)]
+    nodes: BTreeMap<String, TransformTracksDesc>,
+    /// Morph target weight tracks by node name, then target name.
+    #[serde(default)]
+    morphs: BTreeMap<String, BTreeMap<String, TrackDesc<f32>>>,
+    #[serde(default)]
+    camera: Option<CameraTracksDesc>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct TimelineDesc {
+    #[serde(default)]
+    start: f32,
+    /// The last key of any track by default.
+    #[serde(default)]
+    end: Option<f32>,
+    #[serde(default = "default_fps")]
+    fps: f32,
+    #[serde(default)]
+    looping: bool,
+}
+
+/// Keys are `(time, value)` pairs. Splines get smooth tangents through the keys.
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct TrackDesc<V> {
+    #[serde(default = "linear")]
+    interpolation: Interpolation,
+    keys: Vec<(f32, V)>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct TransformTracksDesc {
+    #[serde(default)]
+    position: Option<TrackDesc<Vec3>>,
+    /// Euler angles in degrees.
+    #[serde(default)]
+    rotation: Option<TrackDesc<Vec3>>,
+    #[serde(default)]
+    scale: Option<TrackDesc<Vec3>>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct CameraTracksDesc {
+    #[serde(default)]
+    position: Option<TrackDesc<Vec3>>,
+    /// Euler angles in degrees, ignored with `look_at`.
+    #[serde(default)]
+    rotation: Option<TrackDesc<Vec3>>,
+    #[serde(default)]
+    look_at: Option<TrackDesc<Vec3>>,
+    #[serde(default = "y_up")]
+    up: Vec3,
+    /// In degrees.
+    #[serde(default)]
+    fovy: Option<TrackDesc<f32>>,
+    #[serde(default)]
+    height: Option<TrackDesc<f32>>,
+}
+
+fn track<V: Copy, T: Animatable>(
+    desc: &Option<TrackDesc<V>>,
+    value: impl Fn(V) -> T,
+) -> Option<Track<T>> {
+    desc.as_ref().map(|desc| track_of(desc, value))
+}
+
+fn track_of<V: Copy, T: Animatable>(desc: &TrackDesc<V>, value: impl Fn(V) -> T) -> Track<T> {
+    let mut track = Track::new(desc.interpolation);
+    for (time, v) in desc.keys.iter() {
+        track.insert(*time, value(*v));
+    }
+    if desc.interpolation == Interpolation::CubicSpline {
+        track.smooth_tangents();
+    }
+    track
+}
+
+fn transform_tracks(
+    position: &Option<TrackDesc<Vec3>>,
+    rotation_desc: &Option<TrackDesc<Vec3>>,
+    scale: &Option<TrackDesc<Vec3>>,
+) -> TransformTracks {
+    TransformTracks {
+        position: track(position, vector),
+        rotation: track(rotation_desc, rotation),
+        scale: track(scale, vector),
+    }
+}
+
+/// Index of the morph target named `target` of the mesh `node` draws.
+fn morph_target(scene: &Scene, node: NodeId, target: &str) -> Result<usize, SceneError> {
+    let node = scene.node(node);
+    node.mesh
+        .and_then(|(mesh, _)| scene.mesh(mesh).find_morph_target(target))
+        .ok_or_else(|| {
+            SceneError::new(SceneErrorKind::UnknownMorphTarget {
+                node: node.name.clone(),
+                target: target.to_owned(),
+            })
+        })
+}
+
+/// Loads a RON scene file and everything it refers to. Paths in the file are
+/// relative to the working directory, like the rest of `res/`.
+pub fn load_scene(path: PathBuf) -> Result<SceneSetup, SceneError> {
+    let text = fs::read_to_string(&path).map_err(|e| SceneError::from(e).in_file(path.clone()))?;
+    parse_scene(&text).map_err(|e| e.in_file(path))
+}
+
+pub fn parse_scene(text: &str) -> Result<SceneSetup, SceneError> {
+    let file: SceneFile =
+        ron::de::from_str(text).map_err(|e| SceneError::new(SceneErrorKind::Parse(e)))?;
+
+    let mut scene = Scene::new();
+
+    let mut meshes = BTreeMap::new();
+    let mut lods = Vec::new();
+    for (name, desc) in file.meshes.iter() {
+        let mut mesh = load_mesh(&desc.path)?;
+        for (name, path) in desc.morph_targets.iter() {
+            let target = mesh
+                .morph_target_to(name, &load_mesh(path)?)
+                .ok_or_else(|| {
+                    SceneError::new(SceneErrorKind::MorphTargetMismatch(path.clone()))
+                })?;
+            mesh.morph_targets.push(target);
+        }
+        if desc.lod_levels > 0 && !mesh.morph_targets.is_empty() {
+            return Err(SceneError::new(SceneErrorKind::MorphedLod(name.clone())));
+        }
+        let levels = if desc.lod_levels > 0 {
+            Some(LodChain::generate(&mesh, desc.lod_levels))
+        } else {
+            None
+        };
+
+        let id = scene.add_mesh(mesh);
+        if let Some(levels) = levels {
+            lods.push((id, levels));
+        }
+        meshes.insert(name.as_str(), id);
+    }
+
+    let mut materials = BTreeMap::new();
+    for (name, desc) in file.materials.iter() {
+        let textures = desc
+            .textures
+            .iter()
+            .map(|t| {
+                Ok(TextureBinding {
+                    name: t.name.clone(),
+                    texture: load_texture(&t.path, t.color_space)?,
+                    sampler: desc.sampler,
+                })
+            })
+            .collect::<Result<_, SceneError>>()?;
+        let normal_maps = desc
+            .normal_maps
+            .iter()
+            .map(|n| {
+                Ok(NormalMapBinding {
+                    name: n.name.clone(),
+                    texture: load_texture(&n.path, ColorSpace::Linear)?,
+                    sampler: desc.sampler,
+                    convention: n.convention,
+                })
+            })
+            .collect::<Result<_, SceneError>>()?;
+
+        let id = scene.add_material(Material {
+            name: name.clone(),
+            textures,
+            normal_maps,
+        });
+        materials.insert(name.as_str(), id);
+    }
+
+    fn add_nodes(
+        scene: &mut Scene,
+        parent: Option<NodeId>,
+        descs: &[NodeDesc],
+        meshes: &BTreeMap<&str, MeshId>,
+        materials: &BTreeMap<&str, MaterialId>,
+        imported: &mut Animation,
+    ) -> Result<(), SceneError> {
+        for desc in descs {
+            let transform = Transform::new(
+                Point3::from_vec(vector(desc.position)),
+                rotation(desc.rotation),
+                vector(desc.scale),
+            );
+            let mut node = Node::new(&desc.name, transform);
+
+            let material = || {
+                let material = desc.material.as_ref().ok_or_else(|| {
+                    SceneError::new(SceneErrorKind::MissingMaterial(desc.name.clone()))
+                })?;
+                materials.get(material.as_str()).copied().ok_or_else(|| {
+                    SceneError::new(SceneErrorKind::UnknownMaterial(material.clone()))
+                })
+            };
+
+            if let Some(mesh) = &desc.mesh {
+                let mesh = *meshes
+                    .get(mesh.as_str())
+                    .ok_or_else(|| SceneError::new(SceneErrorKind::UnknownMesh(mesh.clone())))?;
+                node = node.with_mesh(mesh, material()?);
+            }
+
+            let id = scene.add_node(parent, node);
+            for (target, weight) in desc.morph_weights.iter() {
+                let i = morph_target(scene, id, target)?;
+                let weights = &mut scene.node_mut(id).morph_weights;
+                if weights.len() <= i {
+                    weights.resize(i + 1, 0.0);
+                }
+                weights[i] = *weight;
+            }
+            if let Some(path) = &desc.gltf {
+                let import = import_gltf(path, scene, Some(id), material()?, desc.skinning)?;
+                imported.nodes.extend(import.tracks);
+                imported.morphs.extend(import.morphs);
+            }
+            add_nodes(scene, Some(id), &desc.children, meshes, materials, imported)?;
+        }
+        Ok(())
+    }
+    let mut imported = Animation::default();
+    add_nodes(
+        &mut scene,
+        None,
+        &file.nodes,
+        &meshes,
+        &materials,
+        &mut imported,
+    )?;
+
+    scene.lights = file
+        .lights
+        .iter()
+        .map(|l| match *l {
+            LightDesc::Directional {
+                direction,
+                intensity,
+            } => Light::Directional {
+                direction: vector(direction),
+                intensity,
+            },
+        })
+        .collect();
+
+    // Tracks of imported glTF files play along with the ones of the scene file
+    let animation = match &file.animation {
+        Some(desc) => {
+            let mut animation = imported;
+            let find_node = |name: &String| {
+                scene
+                    .find_node(name)
+                    .ok_or_else(|| SceneError::new(SceneErrorKind::UnknownNode(name.clone())))
+            };
+            for (name, t) in desc.nodes.iter() {
+                let tracks = transform_tracks(&t.position, &t.rotation, &t.scale);
+                animation.nodes.push((find_node(name)?, tracks));
+            }
+            for (name, targets) in desc.morphs.iter() {
+                let node = find_node(name)?;
+                let mut tracks = MorphTracks::default();
+                for (target, t) in targets.iter() {
+                    let i = morph_target(&scene, node, target)?;
+                    if tracks.weights.len() <= i {
+                        tracks.weights.resize(i + 1, None);
+                    }
+                    tracks.weights[i] = Some(track_of(t, |weight| weight));
+                }
+                animation.morphs.push((node, tracks));
+            }
+            animation.camera = desc.camera.as_ref().map(|c| CameraTracks {
+                transform: transform_tracks(&c.position, &c.rotation, &None),
+                look_at: track(&c.look_at, vector),
+                up: vector(c.up),
+                fovy: track(&c.fovy, |degrees| Rad::from(Deg(degrees))),
+                height: track(&c.height, |height| height),
+            });
+            Some(animation)
+        }
+        None if !imported.nodes.is_empty() || !imported.morphs.is_empty() => Some(imported),
+        None => None,
+    };
+
+    let timeline = {
+        let duration = animation.as_ref().map_or(0.0, Animation::duration);
+        match file.animation.as_ref().and_then(|a| a.timeline.as_ref()) {
+            Some(t) => Timeline {
+                start: t.start,
+                end: t.end.unwrap_or(duration),
+                fps: t.fps,
+                looping: t.looping,
+            },
+            None => Timeline {
+                end: duration,
+                ..Timeline::default()
+            },
+        }
+    };
+    if let Some(animation) = &animation {
+        animation.pose_scene(timeline.start, &mut scene);
+    }
+
+    let environment = match file.environment {
+        Some(desc) => Some(EnvironmentBinding {
+            name: desc.name,
+            environment: load_environment(desc.source)?,
+            sampler: desc.sampler,
+            lookup: desc.lookup,
+        }),
+        None => None,
+    };
+
+    let camera = {
+        let c = &file.camera;
+        let projection = match c.projection {
+            ProjectionDesc::Perspective { fovy, near, far } => Projection::Perspective {
+                fovy: Rad::from(Deg(fovy)),
+                near,
+                far,
+            },
+            ProjectionDesc::Orthographic { height, near, far } => {
+                Projection::Orthographic { height, near, far }
+            }
+            ProjectionDesc::OffAxis {
+                left,
+                right,
+                bottom,
+                top,
+                near,
+                far,
+            } => Projection::OffAxis {
+                left,
+                right,
+                bottom,
+                top,
+                near,
+                far,
+            },
+        };
+
+        let transform = Transform {
+            position: Point3::from_vec(vector(c.position)),
+            rotation: rotation(c.rotation),
+            ..Transform::default()
+        };
+        let mut camera = Camera::new(transform, projection).with_convention(file.convention);
+        if let Some(target) = c.look_at {
+            camera.look_at(Point3::from_vec(vector(target)), vector(c.up));
+        }
+        if c.frame {
+            if let Some(sphere) = scene.bounding_sphere() {
+                let aspect = file.output.width as f32 / file.output.height.max(1) as f32;
+                camera.frame(&sphere, aspect);
+            }
+        }
+        if let Some(animation) = &animation {
+            animation.pose_camera(timeline.start, &mut camera);
+        }
+        camera
+    };
+
+    Ok(SceneSetup {
+        scene,
+        camera,
+        output: file.output,
+        shaders: file.shaders,
+        environment,
+        lods,
+        animation,
+        timeline,
+    })
+}
+
+fn load_mesh(path: &Path) -> Result<Mesh, SceneError> {
+    let extension = path
+        .extension()
+        .and_then(|e| e.to_str())
+        .map(|e| e.to_ascii_lowercase());
+    let mesh = match extension.as_deref() {
+        Some("ply") => mesh::load_ply(path.to_path_buf())?,
+        Some("obj") => mesh::load_obj(path.to_path_buf())?,
+        Some("stl") => mesh::load_stl(
+            path.to_path_buf(),
+            NormalMode::Smooth {
+                weighting: NormalWeighting::Angle,
+                crease_angle: Some(Rad::from(Deg(60.0))),
+            },
+        )?,
+        _ => {
+            return Err(SceneError::new(SceneErrorKind::UnsupportedMesh(
+                path.to_path_buf(),
+            )))
+        }
+    };
+    Ok(mesh)
+}
+
+fn texture_error(path: &Path) -> impl FnOnce(image::ImageError) -> SceneError + '_ {
+    move |error| {
+        SceneError::new(SceneErrorKind::Texture {
+            path: path.to_path_buf(),
+            error,
+        })
+    }
+}
+
+fn load_texture(path: &Path, color_space: ColorSpace) -> Result<Texture2D, SceneError> {
+    Texture2D::load(path.to_path_buf(), color_space).map_err(texture_error(path))
+}
+
+fn load_environment(source: EnvironmentSource) -> Result<Environment, SceneError> {
+    // Environments are lighting, LDR ones are sRGB encoded like any other color
+    Ok(match source {
+        EnvironmentSource::Equirectangular(path) => {
+            Environment::Equirectangular(load_texture(&path, ColorSpace::Srgb)?)
+        }
+        EnvironmentSource::Cross(path) => Environment::Cube(
+            TextureCube::load_cross(path.clone(), ColorSpace::Srgb)
+                .map_err(texture_error(&path))?,
+        ),
+        EnvironmentSource::Faces(paths) => Environment::Cube(
+            TextureCube::load_faces(&paths, ColorSpace::Srgb).map_err(texture_error(&paths[0]))?,
+        ),
+    })
+}
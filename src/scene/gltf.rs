@@ -0,0 +1,508 @@
+use super::{MaterialId, MeshId, Node, NodeId, Scene, SceneError, SceneErrorKind, SkinId};
+use crate::animation::{Animatable, Interpolation, MorphTracks, Track, TransformTracks};
+use crate::mesh::{IndexedMesh, Mesh, MorphTarget, NormalMode, Vertex};
+use crate::skinning::{Skeleton, Skinning};
+use crate::transform::Transform;
+
+use ::gltf::animation::util::ReadOutputs;
+use ::gltf::mesh::Mode;
+use cgmath::{Matrix4, Point3, Quaternion, SquareMatrix, Vector2, Vector3, Vector4};
+use serde::Deserialize;
+
+use std::collections::{BTreeMap, HashMap};
+use std::path::Path;
+
+/// What `import_gltf` added to the scene.
+#[derive(Clone, Debug, Default)]
+pub struct GltfImport {
+    /// Top level nodes of the file's scene.
+    pub roots: Vec<NodeId>,
+    /// Tracks of every animation in the file moving the imported nodes, they all
+    /// play at once.
+    pub tracks: Vec<(NodeId, TransformTracks)>,
+    /// Tracks of the same animations blending morph targets.
+    pub morphs: Vec<(NodeId, MorphTracks)>,
+}
+
+/// Morph target names are not part of glTF itself, exporters put them here.
+#[derive(Default, Deserialize)]
+struct MeshExtras {
+    #[serde(rename = "targetNames", default)]
+    target_names: Vec<String>,
+}
+
+/// Adds the default scene of a glTF file below `parent`. Every mesh is drawn with
+/// `material`, glTF materials are ignored. Skins become skeletons of the imported
+/// joint nodes, deforming with `skinning`.
+pub fn import_gltf(
+    path: &Path,
+    scene: &mut Scene,
+    parent: Option<NodeId>,
+    material: MaterialId,
+    skinning: Skinning,
+) -> Result<GltfImport, SceneError> {
+    let (document, buffers, _) = ::gltf::import(path).map_err(|error| {
+        SceneError::new(SceneErrorKind::Gltf {
+            path: path.to_path_buf(),
+            error,
+        })
+    })?;
+    let buffer = |b: ::gltf::Buffer| Some(&buffers[b.index()][..]);
+
+    let gltf_scene = match document
+        .default_scene()
+        .or_else(|| document.scenes().next())
+    {
+        Some(s) => s,
+        None => return Ok(GltfImport::default()),
+    };
+
+    fn add_node(
+        scene: &mut Scene,
+        parent: Option<NodeId>,
+        node: ::gltf::Node,
+        nodes: &mut HashMap<usize, NodeId>,
+    ) -> NodeId {
+        let (t, r, s) = node.transform().decomposed();
+        let transform = Transform::new(
+            Point3::new(t[0], t[1], t[2]),
+            Quaternion::new(r[3], r[0], r[1], r[2]),
+            Vector3::new(s[0], s[1], s[2]),
+        );
+        let id = scene.add_node(parent, Node::new(node.name().unwrap_or(""), transform));
+        nodes.insert(node.index(), id);
+        for child in node.children() {
+            add_node(scene, Some(id), child, nodes);
+        }
+        id
+    }
+    let mut nodes = HashMap::new();
+    let roots = gltf_scene
+        .nodes()
+        .map(|n| add_node(scene, parent, n, &mut nodes))
+        .collect();
+
+    // Meshes and skins are shared between nodes, only the first use loads them
+    let mut meshes: HashMap<usize, MeshId> = HashMap::new();
+    let mut skins: HashMap<usize, Option<SkinId>> = HashMap::new();
+    for node in document.nodes() {
+        let id = match nodes.get(&node.index()) {
+            Some(id) => *id,
+            None => continue,
+        };
+
+        if let Some(mesh) = node.mesh() {
+            let mesh_id = *meshes
+                .entry(mesh.index())
+                .or_insert_with(|| scene.add_mesh(load_mesh(&mesh, buffer)));
+            let weights = node.weights().or_else(|| mesh.weights()).unwrap_or(&[]);
+            let node = scene.node_mut(id);
+            node.mesh = Some((mesh_id, material));
+            node.morph_weights = weights.to_vec();
+        }
+
+        if let Some(skin) = node.skin() {
+            let skin_id = *skins.entry(skin.index()).or_insert_with(|| {
+                // Joints outside the imported scene cannot be posed
+                let joints = skin
+                    .joints()
+                    .map(|j| nodes.get(&j.index()).copied())
+                    .collect::<Option<Vec<_>>>()?;
+                let inverse_bind_matrices = match skin.reader(buffer).read_inverse_bind_matrices() {
+                    Some(matrices) => matrices.map(Matrix4::from).collect(),
+                    None => vec![Matrix4::identity(); joints.len()],
+                };
+
+                Some(scene.add_skin(Skeleton {
+                    joints,
+                    inverse_bind_matrices,
+                    method: skinning,
+                }))
+            });
+            scene.node_mut(id).skin = skin_id;
+        }
+    }
+
+    let mut tracks: BTreeMap<usize, TransformTracks> = BTreeMap::new();
+    let mut morphs: BTreeMap<usize, MorphTracks> = BTreeMap::new();
+    for channel in document.animations().flat_map(|a| a.channels()) {
+        let target = channel.target().node().index();
+        if !nodes.contains_key(&target) {
+            continue;
+        }
+
+        let reader = channel.reader(buffer);
+        let times: Vec<f32> = match reader.read_inputs() {
+            Some(times) => times.collect(),
+            None => continue,
+        };
+        let interpolation = match channel.sampler().interpolation() {
+            ::gltf::animation::Interpolation::Step => Interpolation::Step,
+            ::gltf::animation::Interpolation::Linear => Interpolation::Linear,
+            ::gltf::animation::Interpolation::CubicSpline => Interpolation::CubicSpline,
+        };
+
+        let entry = tracks.entry(target).or_default();
+        let morph_entry = morphs.entry(target).or_default();
+        match reader.read_outputs() {
+            Some(ReadOutputs::Translations(values)) => {
+                entry.position = Some(track(interpolation, &times, values.map(Vector3::from)));
+            }
+            Some(ReadOutputs::Rotations(values)) => {
+                // Linear rotations mean slerp in glTF
+                let interpolation = match interpolation {
+                    Interpolation::Linear => Interpolation::Slerp,
+                    other => other,
+                };
+                let values = values
+                    .into_f32()
+                    .map(|r| Quaternion::new(r[3], r[0], r[1], r[2]));
+                entry.rotation = Some(track(interpolation, &times, values));
+            }
+            Some(ReadOutputs::Scales(values)) => {
+                entry.scale = Some(track(interpolation, &times, values.map(Vector3::from)));
+            }
+            Some(ReadOutputs::MorphTargetWeights(values)) => {
+                // Every key holds the weight of each target in turn, splines hold
+                // all in tangents, then all weights, then all out tangents
+                let values: Vec<f32> = values.into_f32().collect();
+                let per_key = if interpolation == Interpolation::CubicSpline {
+                    3
+                } else {
+                    1
+                };
+                let count = values.len() / (times.len() * per_key).max(1);
+                morph_entry.weights = (0..count)
+                    .map(|i| {
+                        let values = values.iter().skip(i).step_by(count).copied();
+                        Some(track(interpolation, &times, values))
+                    })
+                    .collect();
+            }
+            None => {}
+        }
+    }
+
+    Ok(GltfImport {
+        roots,
+        tracks: tracks
+            .into_iter()
+            .map(|(node, tracks)| (nodes[&node], tracks))
+            .collect(),
+        morphs: morphs
+            .into_iter()
+            .filter(|(_, tracks)| !tracks.weights.is_empty())
+            .map(|(node, tracks)| (nodes[&node], tracks))
+            .collect(),
+    })
+}
+
+/// Splines store an in tangent, the value and an out tangent per key.
+fn track<T: Animatable>(
+    interpolation: Interpolation,
+    times: &[f32],
+    values: impl Iterator<Item = T>,
+) -> Track<T> {
+    let mut track = Track::new(interpolation);
+    let values: Vec<T> = values.collect();
+    if interpolation == Interpolation::CubicSpline {
+        for (time, key) in times.iter().zip(values.chunks_exact(3)) {
+            track.insert_spline(*time, key[0], key[1], key[2]);
+        }
+    } else {
+        for (time, value) in times.iter().zip(values) {
+            track.insert(*time, value);
+        }
+    }
+    track
+}
+
+/// Merges the triangle primitives of a mesh, other primitive modes are skipped.
+/// Morph targets keep their position and normal deltas, tangent deltas are
+/// ignored.
+fn load_mesh<'s, F>(mesh: &::gltf::Mesh, buffer: F) -> Mesh
+where
+    F: Clone + for<'a> Fn(::gltf::Buffer<'a>) -> Option<&'s [u8]>,
+{
+    let mut indexed = IndexedMesh::default();
+    let mut has_normals = true;
+    let mut has_tangents = true;
+    // Deltas per vertex of `indexed` until the triangles are laid out
+    let mut targets: Vec<MorphTarget> = Vec::new();
+    let mut has_normal_deltas = false;
+
+    for primitive in mesh.primitives().filter(|p| p.mode() == Mode::Triangles) {
+        let reader = primitive.reader(buffer.clone());
+        let positions = match reader.read_positions() {
+            Some(positions) => positions,
+            None => continue,
+        };
+        let mut vertices: Vec<Vertex> = positions.map(|p| Vertex::new(p.into())).collect();
+
+        match reader.read_normals() {
+            Some(normals) => {
+                for (v, n) in vertices.iter_mut().zip(normals) {
+                    v.normal = n.into();
+                }
+            }
+            None => has_normals = false,
+        }
+        // glTF puts the origin of UV space at the top left, flipping v mirrors the
+        // tangent space so the bitangent sign flips with it
+        match reader.read_tangents() {
+            Some(tangents) => {
+                for (v, t) in vertices.iter_mut().zip(tangents) {
+                    v.tangent = Vector4::new(t[0], t[1], t[2], -t[3]);
+                }
+            }
+            None => has_tangents = false,
+        }
+        if let Some(uvs) = reader.read_tex_coords(0) {
+            for (v, uv) in vertices.iter_mut().zip(uvs.into_f32()) {
+                v.uv = Vector2::new(uv[0], 1.0 - uv[1]);
+            }
+        }
+        if let Some(uvs) = reader.read_tex_coords(1) {
+            for (v, uv) in vertices.iter_mut().zip(uvs.into_f32()) {
+                v.uv1 = Vector2::new(uv[0], 1.0 - uv[1]);
+            }
+        }
+        if let Some(colors) = reader.read_colors(0) {
+            for (v, c) in vertices.iter_mut().zip(colors.into_rgba_f32()) {
+                v.color = c.into();
+            }
+        }
+        if let Some(joints) = reader.read_joints(0) {
+            for (v, j) in vertices.iter_mut().zip(joints.into_u16()) {
+                v.joints = Vector4::new(j[0] as u32, j[1] as u32, j[2] as u32, j[3] as u32);
+            }
+        }
+        if let Some(weights) = reader.read_weights(0) {
+            for (v, w) in vertices.iter_mut().zip(weights.into_f32()) {
+                v.weights = w.into();
+            }
+        }
+
+        let base = indexed.vertices.len();
+        let zero = Vector3::new(0.0, 0.0, 0.0);
+        for (i, (positions, normals, _)) in reader.read_morph_targets().enumerate() {
+            if targets.len() <= i {
+                let target = MorphTarget {
+                    name: String::new(),
+                    position_deltas: vec![zero; base],
+                    normal_deltas: vec![zero; base],
+                };
+                targets.resize(i + 1, target);
+            }
+            let target = &mut targets[i];
+            let positions = positions.into_iter().flatten().map(Vector3::from);
+            target.position_deltas.extend(positions);
+            has_normal_deltas |= normals.is_some();
+            let normals = normals.into_iter().flatten().map(Vector3::from);
+            target.normal_deltas.extend(normals);
+        }
+        // Primitives with fewer targets, or targets without some of the deltas
+        for target in targets.iter_mut() {
+            target.position_deltas.resize(base + vertices.len(), zero);
+            target.normal_deltas.resize(base + vertices.len(), zero);
+        }
+
+        let base = base as u32;
+        let indices: Vec<u32> = match reader.read_indices() {
+            Some(indices) => indices.into_u32().collect(),
+            None => (0..vertices.len() as u32).collect(),
+        };
+        indexed.faces.extend(
+            indices
+                .chunks_exact(3)
+                .map(|f| [base + f[0], base + f[1], base + f[2]]),
+        );
+        indexed.vertices.extend(vertices);
+    }
+
+    let mut result = if has_normals {
+        if !has_tangents {
+            indexed.generate_tangents();
+        }
+        indexed.to_mesh()
+    } else {
+        let mut mesh = indexed.to_mesh();
+        mesh.generate_normals(NormalMode::default());
+        mesh.generate_tangents();
+        mesh
+    };
+
+    let names = mesh
+        .extras()
+        .as_ref()
+        .and_then(|extras| serde_json::from_str::<MeshExtras>(extras.get()).ok())
+        .unwrap_or_default()
+        .target_names;
+    let corners = |deltas: &[Vector3<f32>]| {
+        indexed
+            .faces
+            .iter()
+            .flatten()
+            .map(|i| deltas[*i as usize])
+            .collect()
+    };
+    result.morph_targets = targets
+        .iter()
+        .enumerate()
+        .map(|(i, target)| MorphTarget {
+            name: names.get(i).cloned().unwrap_or_else(|| i.to_string()),
+            position_deltas: corners(&target.position_deltas),
+            normal_deltas: if has_normal_deltas {
+                corners(&target.normal_deltas)
+            } else {
+                Vec::new()
+            },
+        })
+        .collect();
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::scene::Material;
+    use cgmath::InnerSpace;
+
+    /// A triangle skinned to a root joint and a tip joint one unit up, with an
+    /// animation moving the tip one unit along x.
+    fn write_skinned_triangle(dir: &Path) -> std::path::PathBuf {
+        fn push(data: &mut Vec<u8>, values: &[f32]) {
+            for v in values {
+                data.extend_from_slice(&v.to_le_bytes());
+            }
+        }
+        let mut data = Vec::<u8>::new();
+        // Positions at 0, joints at 36, weights at 48
+        push(&mut data, &[0.0, 0.0, 0.0, 1.0, 0.0, 0.0, 0.0, 1.0, 0.0]);
+        data.extend_from_slice(&[0, 0, 0, 0, 0, 0, 0, 0, 1, 0, 0, 0]);
+        push(
+            &mut data,
+            &[1.0, 0.0, 0.0, 0.0, 1.0, 0.0, 0.0, 0.0, 1.0, 0.0, 0.0, 0.0],
+        );
+        // Inverse bind matrices at 96, column major
+        push(
+            &mut data,
+            &[
+                1.0, 0.0, 0.0, 0.0, 0.0, 1.0, 0.0, 0.0, 0.0, 0.0, 1.0, 0.0, 0.0, 0.0, 0.0, 1.0,
+            ],
+        );
+        push(
+            &mut data,
+            &[
+                1.0, 0.0, 0.0, 0.0, 0.0, 1.0, 0.0, 0.0, 0.0, 0.0, 1.0, 0.0, 0.0, -1.0, 0.0, 1.0,
+            ],
+        );
+        // Key times at 224, translations at 232
+        push(&mut data, &[0.0, 1.0]);
+        push(&mut data, &[0.0, 1.0, 0.0, 1.0, 1.0, 0.0]);
+        std::fs::write(dir.join("skinned.bin"), &data).unwrap();
+
+        let gltf = r#"{
+            "asset": { "version": "2.0" },
+            "scene": 0,
+            "scenes": [{ "nodes": [0, 1] }],
+            "nodes": [
+                { "name": "body", "mesh": 0, "skin": 0 },
+                { "name": "root", "children": [2] },
+                { "name": "tip", "translation": [0, 1, 0] }
+            ],
+            "meshes": [{
+                "primitives": [{
+                    "attributes": { "POSITION": 0, "JOINTS_0": 1, "WEIGHTS_0": 2 }
+                }]
+            }],
+            "skins": [{ "joints": [1, 2], "inverseBindMatrices": 3 }],
+            "animations": [{
+                "channels": [{ "sampler": 0, "target": { "node": 2, "path": "translation" } }],
+                "samplers": [{ "input": 4, "output": 5 }]
+            }],
+            "buffers": [{ "uri": "skinned.bin", "byteLength": 256 }],
+            "bufferViews": [
+                { "buffer": 0, "byteOffset": 0, "byteLength": 36 },
+                { "buffer": 0, "byteOffset": 36, "byteLength": 12 },
+                { "buffer": 0, "byteOffset": 48, "byteLength": 48 },
+                { "buffer": 0, "byteOffset": 96, "byteLength": 128 },
+                { "buffer": 0, "byteOffset": 224, "byteLength": 8 },
+                { "buffer": 0, "byteOffset": 232, "byteLength": 24 }
+            ],
+            "accessors": [
+                { "bufferView": 0, "componentType": 5126, "count": 3, "type": "VEC3",
+                  "min": [0, 0, 0], "max": [1, 1, 0] },
+                { "bufferView": 1, "componentType": 5121, "count": 3, "type": "VEC4" },
+                { "bufferView": 2, "componentType": 5126, "count": 3, "type": "VEC4" },
+                { "bufferView": 3, "componentType": 5126, "count": 2, "type": "MAT4" },
+                { "bufferView": 4, "componentType": 5126, "count": 2, "type": "SCALAR",
+                  "min": [0], "max": [1] },
+                { "bufferView": 5, "componentType": 5126, "count": 2, "type": "VEC3" }
+            ]
+        }"#;
+        let path = dir.join("skinned.gltf");
+        std::fs::write(&path, gltf).unwrap();
+        path
+    }
+
+    #[test]
+    fn imports_skins_and_poses_them() {
+        let dir = std::env::temp_dir().join(format!("skinned_gltf_{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = write_skinned_triangle(&dir);
+
+        let mut scene = Scene::new();
+        let material = scene.add_material(Material::default());
+        let import = import_gltf(&path, &mut scene, None, material, Skinning::Linear).unwrap();
+        std::fs::remove_dir_all(&dir).ok();
+
+        let body = scene.find_node("body").unwrap();
+        let root = scene.find_node("root").unwrap();
+        let tip = scene.find_node("tip").unwrap();
+        assert_eq!(import.roots, vec![body, root]);
+
+        let skeleton = scene.skin(scene.node(body).skin.unwrap());
+        assert_eq!(skeleton.joints, vec![root, tip]);
+        assert_eq!(skeleton.method, Skinning::Linear);
+        assert_eq!(
+            skeleton.inverse_bind_matrices[1],
+            Matrix4::from_translation(Vector3::new(0.0, -1.0, 0.0))
+        );
+
+        let (mesh, _) = scene.node(body).mesh.unwrap();
+        let corner = |mesh: &Mesh, position: Vector3<f32>| {
+            *mesh
+                .vertices()
+                .iter()
+                .find(|v| v.position == position)
+                .unwrap()
+        };
+        let top = corner(scene.mesh(mesh), Vector3::new(0.0, 1.0, 0.0));
+        assert_eq!(top.joints, Vector4::new(1, 0, 0, 0));
+        assert_eq!(top.weights, Vector4::new(1.0, 0.0, 0.0, 0.0));
+
+        // At the end of the animation the tip and the corner on it moved along x
+        let (node, tracks) = &import.tracks[0];
+        assert_eq!(*node, tip);
+        tracks.apply(1.0, &mut scene.node_mut(tip).transform);
+        let instance = scene.instances()[0];
+        let palette = scene.joint_palette(&instance).unwrap();
+        let posed = |position| {
+            palette
+                .skin_vertex(&corner(scene.mesh(mesh), position))
+                .position
+        };
+        assert_eq!(
+            posed(Vector3::new(0.0, 0.0, 0.0)),
+            Vector3::new(0.0, 0.0, 0.0)
+        );
+        assert_eq!(
+            posed(Vector3::new(1.0, 0.0, 0.0)),
+            Vector3::new(1.0, 0.0, 0.0)
+        );
+        assert!(
+            (posed(Vector3::new(0.0, 1.0, 0.0)) - Vector3::new(1.0, 1.0, 0.0)).magnitude() < 1e-6
+        );
+    }
+}
@@ -100,6 +100,48 @@ impl Shader {
         Ok(())
     }
 
+    /// Must be called before `compile` to take effect.
+    pub fn bind_attribute_location(&self, location: GLuint, name: &str) {
+        unsafe {
+            let c_str = CString::new(name.as_bytes()).unwrap();
+            gl::BindAttribLocation(self.handle, location, c_str.as_ptr());
+        }
+    }
+
+    /// Names and locations of the vertex inputs the linked program actually uses.
+    pub fn active_attributes(&self) -> Vec<(String, GLint)> {
+        unsafe {
+            let mut count = 0;
+            gl::GetProgramiv(self.handle, gl::ACTIVE_ATTRIBUTES, &mut count);
+            let mut max_len = 0;
+            gl::GetProgramiv(self.handle, gl::ACTIVE_ATTRIBUTE_MAX_LENGTH, &mut max_len);
+
+            (0..count as GLuint)
+                .map(|i| {
+                    let mut buf = vec![0u8; max_len.max(1) as usize];
+                    let mut len = 0;
+                    let mut size = 0;
+                    let mut ty = 0;
+                    gl::GetActiveAttrib(
+                        self.handle,
+                        i,
+                        max_len,
+                        &mut len,
+                        &mut size,
+                        &mut ty,
+                        buf.as_mut_ptr() as *mut GLchar,
+                    );
+                    buf.truncate(len as usize);
+
+                    let name = String::from_utf8_lossy(&buf).into_owned();
+                    let c_str = CString::new(name.as_bytes()).unwrap();
+                    let location = gl::GetAttribLocation(self.handle, c_str.as_ptr());
+                    (name, location)
+                })
+                .collect()
+        }
+    }
+
     pub fn set_uniform<T>(&self, id: &str, val: T)
     where
         T: Uniform,
@@ -0,0 +1,256 @@
+use crate::mesh::{safe_normalize, Triangle, Vertex};
+use crate::scene::NodeId;
+use crate::transform::{self, Transform};
+
+use cgmath::prelude::*;
+use cgmath::{Matrix3, Matrix4, Quaternion, Vector3, Vector4};
+use serde::Deserialize;
+
+#[derive(Copy, Clone, Debug, Default, PartialEq, Deserialize)]
+pub enum Skinning {
+    /// Blends the joint matrices. Cheap and handles scaled joints, but twisting
+    /// joints collapse the mesh around them.
+    #[default]
+    Linear,
+    /// Blends rigid joint transforms as dual quaternions, which keeps volume
+    /// around twisting joints. Joint scale is ignored.
+    DualQuaternion,
+}
+
+/// Joints a mesh is skinned to, like a glTF skin. The joints are nodes of the
+/// scene, so animating them poses the mesh.
+#[derive(Clone, Debug)]
+pub struct Skeleton {
+    pub joints: Vec<NodeId>,
+    /// Per joint, maps mesh space to the space of the joint in bind pose.
+    pub inverse_bind_matrices: Vec<Matrix4<f32>>,
+    pub method: Skinning,
+}
+
+/// Rotation and translation as a unit dual quaternion, `real + dual * e`.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct DualQuaternion {
+    pub real: Quaternion<f32>,
+    pub dual: Quaternion<f32>,
+}
+
+impl DualQuaternion {
+    pub fn new(rotation: Quaternion<f32>, translation: Vector3<f32>) -> DualQuaternion {
+        DualQuaternion {
+            real: rotation,
+            dual: Quaternion::from_sv(0.0, translation) * rotation * 0.5,
+        }
+    }
+
+    /// The rigid part of an affine matrix, scale and shear are dropped.
+    pub fn from_matrix(m: Matrix4<f32>) -> DualQuaternion {
+        DualQuaternion::new(Transform::from_matrix(m).rotation, m.w.truncate())
+    }
+
+    pub fn translation(&self) -> Vector3<f32> {
+        (self.dual * self.real.conjugate()).v * 2.0
+    }
+
+    pub fn transform_point(&self, p: Vector3<f32>) -> Vector3<f32> {
+        self.real.rotate_vector(p) + self.translation()
+    }
+
+    pub fn transform_vector(&self, v: Vector3<f32>) -> Vector3<f32> {
+        self.real.rotate_vector(v)
+    }
+
+    /// Scales both parts so `real` is a unit quaternion again, as needed after
+    /// blending.
+    pub fn normalize(&self) -> DualQuaternion {
+        let length = self.real.magnitude();
+        DualQuaternion {
+            real: self.real / length,
+            dual: self.dual / length,
+        }
+    }
+}
+
+/// Joint transforms of a posed skeleton, ready to skin vertices with.
+#[derive(Clone, Debug)]
+pub enum JointPalette {
+    Linear(Vec<Matrix4<f32>>),
+    DualQuaternion(Vec<DualQuaternion>),
+}
+
+impl JointPalette {
+    /// `matrices` map mesh space in bind pose to mesh space in the current pose.
+    pub fn new(matrices: Vec<Matrix4<f32>>, method: Skinning) -> JointPalette {
+        match method {
+            Skinning::Linear => JointPalette::Linear(matrices),
+            Skinning::DualQuaternion => JointPalette::DualQuaternion(
+                matrices
+                    .into_iter()
+                    .map(DualQuaternion::from_matrix)
+                    .collect(),
+            ),
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        match self {
+            JointPalette::Linear(matrices) => matrices.len(),
+            JointPalette::DualQuaternion(joints) => joints.len(),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// The vertex moved by the joints it is weighted to. Vertices without any
+    /// weight stay where they are.
+    pub fn skin_vertex(&self, v: &Vertex) -> Vertex {
+        let total = v.weights.x + v.weights.y + v.weights.z + v.weights.w;
+        if total <= 0.0 {
+            return *v;
+        }
+        let influences = (0..4)
+            .map(|i| (v.joints[i] as usize, v.weights[i] / total))
+            .filter(|(_, weight)| *weight != 0.0);
+
+        match self {
+            JointPalette::Linear(matrices) => {
+                let m = influences.fold(Matrix4::zero(), |m, (joint, weight)| {
+                    m + matrices[joint] * weight
+                });
+                let linear = Matrix3::from_cols(m.x.truncate(), m.y.truncate(), m.z.truncate());
+
+                Vertex {
+                    position: (m * v.position.extend(1.0)).truncate(),
+                    // Blended matrices scale, so both come out at any length
+                    normal: safe_normalize(transform::normal_matrix(&m) * v.normal),
+                    tangent: safe_normalize(linear * v.tangent.truncate()).extend(v.tangent.w),
+                    ..*v
+                }
+            }
+            JointPalette::DualQuaternion(joints) => {
+                // Every joint on the hemisphere of the most weighted one, or
+                // blending takes the long way around
+                let (heaviest, _) =
+                    influences
+                        .clone()
+                        .fold((0, 0.0), |heaviest, (joint, weight)| {
+                            if weight > heaviest.1 {
+                                (joint, weight)
+                            } else {
+                                heaviest
+                            }
+                        });
+                let reference = joints[heaviest].real;
+                let blended = influences.fold(
+                    DualQuaternion {
+                        real: Quaternion::zero(),
+                        dual: Quaternion::zero(),
+                    },
+                    |sum, (joint, weight)| {
+                        let dq = joints[joint];
+                        let weight = weight * reference.dot(dq.real).signum();
+                        DualQuaternion {
+                            real: sum.real + dq.real * weight,
+                            dual: sum.dual + dq.dual * weight,
+                        }
+                    },
+                );
+                let dq = blended.normalize();
+
+                Vertex {
+                    position: dq.transform_point(v.position),
+                    normal: dq.transform_vector(v.normal),
+                    tangent: dq
+                        .transform_vector(v.tangent.truncate())
+                        .extend(v.tangent.w),
+                    ..*v
+                }
+            }
+        }
+    }
+
+    pub fn skin_triangle(&self, tri: &Triangle) -> Triangle {
+        Triangle(
+            self.skin_vertex(&tri.0),
+            self.skin_vertex(&tri.1),
+            self.skin_vertex(&tri.2),
+        )
+    }
+
+    /// Joints as the vertex shaders take them in `joint_matrices`. Dual
+    /// quaternions go in the first two columns as xyzw, real part first.
+    pub fn shader_matrices(&self) -> Vec<Matrix4<f32>> {
+        match self {
+            JointPalette::Linear(matrices) => matrices.clone(),
+            JointPalette::DualQuaternion(joints) => {
+                let xyzw = |q: Quaternion<f32>| Vector4::new(q.v.x, q.v.y, q.v.z, q.s);
+                joints
+                    .iter()
+                    .map(|dq| {
+                        Matrix4::from_cols(
+                            xyzw(dq.real),
+                            xyzw(dq.dual),
+                            Vector4::zero(),
+                            Vector4::zero(),
+                        )
+                    })
+                    .collect()
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn weighted(joints: [u32; 4], weights: [f32; 4]) -> Vertex {
+        Vertex {
+            normal: Vector3::new(0.0, 0.6, 0.8),
+            tangent: Vector4::new(1.0, 0.0, 0.0, 1.0),
+            joints: joints.into(),
+            weights: weights.into(),
+            ..Vertex::new(Vector3::new(1.0, 2.0, 3.0))
+        }
+    }
+
+    #[test]
+    fn linear_blending_keeps_normals_and_tangents_unit_length() {
+        let palette = JointPalette::new(
+            vec![
+                Matrix4::from_scale(2.0),
+                Matrix4::from_nonuniform_scale(1.0, 3.0, 0.5),
+            ],
+            Skinning::Linear,
+        );
+        let v = palette.skin_vertex(&weighted([0, 1, 0, 0], [0.5, 0.5, 0.0, 0.0]));
+
+        assert!((v.position - Vector3::new(1.5, 5.0, 3.75)).magnitude() < 1e-5);
+        assert!((v.normal.magnitude() - 1.0).abs() < 1e-6);
+        assert!((v.tangent.truncate().magnitude() - 1.0).abs() < 1e-6);
+        assert_eq!(v.tangent.w, 1.0);
+    }
+
+    #[test]
+    fn dual_quaternions_blend_on_the_hemisphere_of_the_heaviest_joint() {
+        // Rotations of 170 and -170 degrees are 20 degrees apart, but on opposite
+        // hemispheres and both close to orthogonal to the lightly weighted
+        // identity joint
+        let joint = |degrees: f32| {
+            DualQuaternion::new(
+                Quaternion::from_angle_z(cgmath::Deg(degrees)),
+                Vector3::zero(),
+            )
+        };
+        let palette = JointPalette::DualQuaternion(vec![joint(0.0), joint(170.0), joint(-170.0)]);
+        let v = palette.skin_vertex(&Vertex {
+            position: Vector3::new(1.0, 0.0, 0.0),
+            ..weighted([0, 1, 2, 0], [0.1, 0.5, 0.4, 0.0])
+        });
+
+        // Close to a half turn, not back at the identity
+        assert!(v.position.x < -0.9, "{:?}", v.position);
+        assert!((v.position.magnitude() - 1.0).abs() < 1e-5);
+    }
+}
@@ -0,0 +1,617 @@
+use cgmath::prelude::*;
+use cgmath::{Vector2, Vector3, Vector4};
+use serde::Deserialize;
+
+use std::f32::consts::PI;
+use std::fs;
+use std::io::BufReader;
+use std::path::PathBuf;
+
+/// How texel data stored in an image is interpreted.
+#[derive(Copy, Clone, Debug, PartialEq, Deserialize)]
+pub enum ColorSpace {
+    /// Color channels are sRGB encoded and decoded to linear on load, alpha is
+    /// always linear. The right choice for albedo and other authored colors.
+    Srgb,
+    /// Channels are used as they are, e.g. for normal maps or masks.
+    Linear,
+}
+
+/// Texture coordinate handling outside of [0, 1].
+#[derive(Copy, Clone, Debug, PartialEq, Deserialize)]
+pub enum Wrap {
+    Repeat,
+    Clamp,
+    Mirror,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Deserialize)]
+pub enum Filter {
+    /// Nearest texel of the nearest mip level.
+    Nearest,
+    /// Bilinear filtering in the nearest mip level.
+    Bilinear,
+    /// Bilinear filtering in the two closest mip levels, blended by level of detail.
+    Trilinear,
+}
+
+/// Sampling state, kept apart from the texture like a GL sampler object.
+#[derive(Copy, Clone, Debug, PartialEq, Deserialize)]
+#[serde(default)]
+pub struct Sampler {
+    pub filter: Filter,
+    pub wrap_u: Wrap,
+    pub wrap_v: Wrap,
+    /// Upper bound on the number of samples taken along the major axis of the
+    /// pixel footprint, with the same meaning as `GL_TEXTURE_MAX_ANISOTROPY`.
+    /// 1 disables anisotropic filtering.
+    pub max_anisotropy: f32,
+}
+
+impl Default for Sampler {
+    fn default() -> Sampler {
+        Sampler {
+            filter: Filter::Trilinear,
+            wrap_u: Wrap::Repeat,
+            wrap_v: Wrap::Repeat,
+            max_anisotropy: 1.0,
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+struct MipLevel {
+    width: u32,
+    height: u32,
+    /// Linear RGBA, bottom row first.
+    texels: Vec<Vector4<f32>>,
+}
+
+/// A 2D texture, usually with a full mip chain, stored as linear floating point
+/// RGBA.
+///
+/// Rows are stored bottom up, so like GL `uv = (0, 0)` is the bottom left
+/// corner of the image as it is seen in an image viewer.
+#[derive(Clone, Debug)]
+pub struct Texture2D {
+    levels: Vec<MipLevel>,
+}
+
+impl Texture2D {
+    /// Loads any format the image crate supports. Radiance HDR files keep their
+    /// full range and are always linear, `color_space` only applies to the others.
+    pub fn load(path: PathBuf, color_space: ColorSpace) -> Result<Texture2D, image::ImageError> {
+        if image::ImageFormat::from_path(&path).ok() == Some(image::ImageFormat::Hdr) {
+            return Texture2D::load_hdr(path);
+        }
+        Ok(Texture2D::from_image(&image::open(path)?, color_space))
+    }
+
+    pub fn load_hdr(path: PathBuf) -> Result<Texture2D, image::ImageError> {
+        let reader = BufReader::new(fs::File::open(path).map_err(image::ImageError::IoError)?);
+        let decoder = image::hdr::HdrDecoder::new(reader)?;
+        let metadata = decoder.metadata();
+        let pixels = decoder.read_image_hdr()?;
+
+        let texels = pixels
+            .chunks(metadata.width as usize)
+            .rev()
+            .flatten()
+            .map(|p| Vector4::new(p[0], p[1], p[2], 1.0))
+            .collect();
+        Ok(Texture2D::from_texels(
+            metadata.width,
+            metadata.height,
+            texels,
+        ))
+    }
+
+    pub fn from_image(image: &image::DynamicImage, color_space: ColorSpace) -> Texture2D {
+        let image = image.to_rgba8();
+        let (width, height) = image.dimensions();
+
+        let decode = |c: u8| {
+            let c = c as f32 / 255.0;
+            match color_space {
+                ColorSpace::Srgb => srgb_to_linear(c),
+                ColorSpace::Linear => c,
+            }
+        };
+
+        let mut texels = Vec::with_capacity(width as usize * height as usize);
+        for y in (0..height).rev() {
+            for x in 0..width {
+                let p = image.get_pixel(x, y).0;
+                texels.push(Vector4::new(
+                    decode(p[0]),
+                    decode(p[1]),
+                    decode(p[2]),
+                    p[3] as f32 / 255.0,
+                ));
+            }
+        }
+
+        Texture2D::from_texels(width, height, texels)
+    }
+
+    /// Builds a texture from linear RGBA texels, bottom row first, and generates
+    /// its mip chain.
+    pub fn from_texels(width: u32, height: u32, texels: Vec<Vector4<f32>>) -> Texture2D {
+        let mut levels = Texture2D::single_level(width, height, texels).levels;
+
+        // Box filter down to 1x1, mips are averaged in linear space
+        while {
+            let last = levels.last().unwrap();
+            last.width > 1 || last.height > 1
+        } {
+            let previous = levels.last().unwrap();
+            let width = (previous.width / 2).max(1);
+            let height = (previous.height / 2).max(1);
+
+            let mut texels = Vec::with_capacity(width as usize * height as usize);
+            for y in 0..height {
+                for x in 0..width {
+                    let texel = |dx: u32, dy: u32| {
+                        let sx = (x * 2 + dx).min(previous.width - 1);
+                        let sy = (y * 2 + dy).min(previous.height - 1);
+                        previous.texels[(sy * previous.width + sx) as usize]
+                    };
+                    texels.push((texel(0, 0) + texel(1, 0) + texel(0, 1) + texel(1, 1)) * 0.25);
+                }
+            }
+
+            levels.push(MipLevel {
+                width,
+                height,
+                texels,
+            });
+        }
+
+        Texture2D { levels }
+    }
+
+    /// Builds a texture from linear RGBA texels, bottom row first, without mip
+    /// levels. For data that must not be averaged, like depth.
+    pub fn single_level(width: u32, height: u32, texels: Vec<Vector4<f32>>) -> Texture2D {
+        assert!(width > 0 && height > 0, "texture must not be empty");
+        assert_eq!(texels.len(), width as usize * height as usize);
+
+        Texture2D {
+            levels: vec![MipLevel {
+                width,
+                height,
+                texels,
+            }],
+        }
+    }
+
+    pub fn width(&self) -> u32 {
+        self.levels[0].width
+    }
+
+    pub fn height(&self) -> u32 {
+        self.levels[0].height
+    }
+
+    /// Copies a rectangle of the full resolution image into a new texture.
+    /// `x` and `y` address the top left corner like in an image viewer.
+    pub fn crop(&self, x: u32, y: u32, width: u32, height: u32) -> Texture2D {
+        let level = &self.levels[0];
+        assert!(x + width <= level.width && y + height <= level.height);
+
+        let bottom = level.height - (y + height);
+        let texels = (bottom..bottom + height)
+            .flat_map(|row| {
+                let start = (row * level.width + x) as usize;
+                level.texels[start..start + width as usize].iter().cloned()
+            })
+            .collect();
+        Texture2D::from_texels(width, height, texels)
+    }
+
+    /// Rotates the full resolution image by 180 degrees.
+    fn rotate_180(&self) -> Texture2D {
+        let level = &self.levels[0];
+        let texels = level.texels.iter().rev().cloned().collect();
+        Texture2D::from_texels(level.width, level.height, texels)
+    }
+
+    pub fn mip_levels(&self) -> usize {
+        self.levels.len()
+    }
+
+    pub fn level_size(&self, level: usize) -> (u32, u32) {
+        (self.levels[level].width, self.levels[level].height)
+    }
+
+    /// Linear RGBA texels of a mip level, bottom row first.
+    pub fn level_texels(&self, level: usize) -> &[Vector4<f32>] {
+        &self.levels[level].texels
+    }
+
+    /// Reads a single texel, wrapping out of range coordinates.
+    pub fn fetch(&self, level: usize, x: i32, y: i32, wrap_u: Wrap, wrap_v: Wrap) -> Vector4<f32> {
+        let level = &self.levels[level.min(self.levels.len() - 1)];
+        let x = wrap(x, level.width, wrap_u);
+        let y = wrap(y, level.height, wrap_v);
+        level.texels[(y * level.width + x) as usize]
+    }
+
+    /// Level of detail for the given screen space UV derivatives, as GL computes
+    /// it from the larger of the two footprint axes.
+    pub fn lod(&self, duv_dx: Vector2<f32>, duv_dy: Vector2<f32>) -> f32 {
+        let size = Vector2::new(self.width() as f32, self.height() as f32);
+        let dx = duv_dx.mul_element_wise(size);
+        let dy = duv_dy.mul_element_wise(size);
+        let rho2 = dx.magnitude2().max(dy.magnitude2());
+        // log2(sqrt(x)) without the square root
+        0.5 * rho2.max(f32::MIN_POSITIVE).log2()
+    }
+
+    /// Samples with the level of detail derived from screen space UV derivatives.
+    ///
+    /// With anisotropic filtering enabled this follows the footprint assembly of
+    /// EXT_texture_filter_anisotropic: the level of detail is chosen for the minor
+    /// axis of the footprint, and up to `max_anisotropy` samples spread along the
+    /// major axis are averaged.
+    pub fn sample_grad(
+        &self,
+        sampler: &Sampler,
+        uv: Vector2<f32>,
+        duv_dx: Vector2<f32>,
+        duv_dy: Vector2<f32>,
+    ) -> Vector4<f32> {
+        if sampler.max_anisotropy <= 1.0 {
+            return self.sample_lod(sampler, uv, self.lod(duv_dx, duv_dy));
+        }
+
+        let size = Vector2::new(self.width() as f32, self.height() as f32);
+        let px = duv_dx.mul_element_wise(size).magnitude();
+        let py = duv_dy.mul_element_wise(size).magnitude();
+        let (p_max, p_min, major) = if px >= py {
+            (px, py, duv_dx)
+        } else {
+            (py, px, duv_dy)
+        };
+
+        let ratio = (p_max / p_min.max(f32::MIN_POSITIVE)).ceil();
+        let count = ratio.min(sampler.max_anisotropy.floor()).max(1.0);
+        let lod = (p_max / count).max(f32::MIN_POSITIVE).log2();
+
+        let mut sum = Vector4::zero();
+        for i in 0..count as usize {
+            let t = (i as f32 + 0.5) / count - 0.5;
+            sum += self.sample_lod(sampler, uv + major * t, lod);
+        }
+        sum / count
+    }
+
+    /// Samples at an explicit level of detail, 0 being the full resolution image.
+    pub fn sample_lod(&self, sampler: &Sampler, uv: Vector2<f32>, lod: f32) -> Vector4<f32> {
+        let max_level = (self.levels.len() - 1) as f32;
+        let lod = lod.clamp(0.0, max_level);
+
+        match sampler.filter {
+            Filter::Nearest => self.sample_nearest(sampler, uv, lod.round() as usize),
+            Filter::Bilinear => self.sample_bilinear(sampler, uv, lod.round() as usize),
+            Filter::Trilinear => {
+                let level = lod.floor();
+                let a = self.sample_bilinear(sampler, uv, level as usize);
+                if level >= max_level {
+                    return a;
+                }
+                let b = self.sample_bilinear(sampler, uv, level as usize + 1);
+                a.lerp(b, lod - level)
+            }
+        }
+    }
+
+    fn sample_nearest(&self, sampler: &Sampler, uv: Vector2<f32>, level: usize) -> Vector4<f32> {
+        let l = &self.levels[level];
+        let x = (uv.x * l.width as f32).floor() as i32;
+        let y = (uv.y * l.height as f32).floor() as i32;
+        self.fetch(level, x, y, sampler.wrap_u, sampler.wrap_v)
+    }
+
+    fn sample_bilinear(&self, sampler: &Sampler, uv: Vector2<f32>, level: usize) -> Vector4<f32> {
+        let l = &self.levels[level];
+        // Texel centers sit at half integer coordinates
+        let x = uv.x * l.width as f32 - 0.5;
+        let y = uv.y * l.height as f32 - 0.5;
+        let (x0, y0) = (x.floor(), y.floor());
+        let (fx, fy) = (x - x0, y - y0);
+        let (x0, y0) = (x0 as i32, y0 as i32);
+
+        let texel =
+            |dx: i32, dy: i32| self.fetch(level, x0 + dx, y0 + dy, sampler.wrap_u, sampler.wrap_v);
+        let bottom = texel(0, 0).lerp(texel(1, 0), fx);
+        let top = texel(0, 1).lerp(texel(1, 1), fx);
+        bottom.lerp(top, fy)
+    }
+}
+
+/// Texture sampled on the Rust side for a shadelang input.
+///
+/// Shadelang has no texture type, so the rasterizer samples `texture` itself,
+/// at the UVs of a `sample(<name>, uv)` call in the shader or at the
+/// interpolated `uv` for an `in Vec3 <name>` or `in Vec4 <name>` global.
+#[derive(Clone, Debug)]
+pub struct TextureBinding {
+    pub name: String,
+    pub texture: Texture2D,
+    pub sampler: Sampler,
+}
+
+/// Direction of the green channel in a tangent space normal map.
+#[derive(Copy, Clone, Debug, PartialEq, Deserialize)]
+pub enum NormalMapConvention {
+    /// Green points along +V, as exported for GL, Blender and most DCC tools.
+    OpenGl,
+    /// Green points along -V, as exported for DirectX and Unreal.
+    DirectX,
+}
+
+impl NormalMapConvention {
+    /// Unit tangent space normal stored in a normal map texel.
+    pub fn decode(self, texel: Vector4<f32>) -> Vector3<f32> {
+        let mut n = texel.truncate() * 2.0 - Vector3::new(1.0, 1.0, 1.0);
+        if self == NormalMapConvention::DirectX {
+            n.y = -n.y;
+        }
+
+        // Filtering shortens normals, a zero vector means a broken texel
+        if n.magnitude2() > f32::MIN_POSITIVE {
+            n.normalize()
+        } else {
+            Vector3::unit_z()
+        }
+    }
+
+    /// Sign to multiply the green channel with to get the GL convention.
+    pub fn green_sign(self) -> f32 {
+        match self {
+            NormalMapConvention::OpenGl => 1.0,
+            NormalMapConvention::DirectX => -1.0,
+        }
+    }
+}
+
+/// Normal map sampled on the Rust side for a shadelang input, see
+/// `TextureBinding`. The input receives the perturbed normal in the same space as
+/// the interpolated `normal`, i.e. the tangent frame has already been applied.
+#[derive(Clone, Debug)]
+pub struct NormalMapBinding {
+    pub name: String,
+    /// Must be loaded with `ColorSpace::Linear`.
+    pub texture: Texture2D,
+    pub sampler: Sampler,
+    pub convention: NormalMapConvention,
+}
+
+fn wrap(c: i32, size: u32, mode: Wrap) -> u32 {
+    let size = size as i32;
+    let c = match mode {
+        Wrap::Repeat => c.rem_euclid(size),
+        Wrap::Clamp => c.clamp(0, size - 1),
+        Wrap::Mirror => {
+            let c = c.rem_euclid(2 * size);
+            if c >= size {
+                2 * size - 1 - c
+            } else {
+                c
+            }
+        }
+    };
+    c as u32
+}
+
+pub fn srgb_to_linear(c: f32) -> f32 {
+    if c <= 0.04045 {
+        c / 12.92
+    } else {
+        ((c + 0.055) / 1.055).powf(2.4)
+    }
+}
+
+pub fn linear_to_srgb(c: f32) -> f32 {
+    if c <= 0.0031308 {
+        c * 12.92
+    } else {
+        1.055 * c.powf(1.0 / 2.4) - 0.055
+    }
+}
+
+/// Faces of a cube texture in GL order.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum CubeFace {
+    PositiveX,
+    NegativeX,
+    PositiveY,
+    NegativeY,
+    PositiveZ,
+    NegativeZ,
+}
+
+impl CubeFace {
+    pub const ALL: [CubeFace; 6] = [
+        CubeFace::PositiveX,
+        CubeFace::NegativeX,
+        CubeFace::PositiveY,
+        CubeFace::NegativeY,
+        CubeFace::PositiveZ,
+        CubeFace::NegativeZ,
+    ];
+
+    /// Face hit by `direction` and the coordinates on it, following the GL cube
+    /// map selection rules. Faces are images as seen from inside the cube.
+    pub fn from_direction(direction: Vector3<f32>) -> (CubeFace, Vector2<f32>) {
+        let d = direction;
+        let (ax, ay, az) = (d.x.abs(), d.y.abs(), d.z.abs());
+
+        let (face, sc, tc, ma) = if ax >= ay && ax >= az {
+            if d.x >= 0.0 {
+                (CubeFace::PositiveX, -d.z, -d.y, ax)
+            } else {
+                (CubeFace::NegativeX, d.z, -d.y, ax)
+            }
+        } else if ay >= az {
+            if d.y >= 0.0 {
+                (CubeFace::PositiveY, d.x, d.z, ay)
+            } else {
+                (CubeFace::NegativeY, d.x, -d.z, ay)
+            }
+        } else if d.z >= 0.0 {
+            (CubeFace::PositiveZ, d.x, -d.y, az)
+        } else {
+            (CubeFace::NegativeZ, -d.x, -d.y, az)
+        };
+
+        let ma = ma.max(f32::MIN_POSITIVE);
+        let s = 0.5 * (sc / ma + 1.0);
+        let t = 0.5 * (tc / ma + 1.0);
+        // GL counts t from the first row in memory, the top row of the image
+        (face, Vector2::new(s, 1.0 - t))
+    }
+}
+
+/// Six square textures forming a cube, sampled by direction.
+#[derive(Clone, Debug)]
+pub struct TextureCube {
+    faces: Vec<Texture2D>,
+}
+
+impl TextureCube {
+    /// Faces in the order of `CubeFace::ALL`.
+    pub fn from_faces(faces: Vec<Texture2D>) -> TextureCube {
+        assert_eq!(faces.len(), 6, "a cube texture needs six faces");
+        TextureCube { faces }
+    }
+
+    /// Loads six images in the order of `CubeFace::ALL`.
+    pub fn load_faces(
+        paths: &[PathBuf; 6],
+        color_space: ColorSpace,
+    ) -> Result<TextureCube, image::ImageError> {
+        let faces = paths
+            .iter()
+            .map(|path| Texture2D::load(path.clone(), color_space))
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(TextureCube::from_faces(faces))
+    }
+
+    /// Loads a single image holding all faces as a horizontal (4x3 faces) or
+    /// vertical (3x4 faces) cross:
+    ///
+    /// ```text
+    ///       +Y                   +Y
+    ///  -X   +Z   +X   -Z    -X   +Z   +X
+    ///       -Y                   -Y
+    ///                            -Z
+    /// ```
+    ///
+    /// In the vertical layout -Z is stored upside down.
+    pub fn load_cross(
+        path: PathBuf,
+        color_space: ColorSpace,
+    ) -> Result<TextureCube, image::ImageError> {
+        let cross = Texture2D::load(path, color_space)?;
+        let (width, height) = (cross.width(), cross.height());
+
+        let (size, positions, vertical) = if width * 3 == height * 4 {
+            (
+                width / 4,
+                [(2, 1), (0, 1), (1, 0), (1, 2), (1, 1), (3, 1)],
+                false,
+            )
+        } else if width * 4 == height * 3 {
+            (
+                width / 3,
+                [(2, 1), (0, 1), (1, 0), (1, 2), (1, 1), (1, 3)],
+                true,
+            )
+        } else {
+            return Err(image::ImageError::Parameter(
+                image::error::ParameterError::from_kind(
+                    image::error::ParameterErrorKind::DimensionMismatch,
+                ),
+            ));
+        };
+
+        let mut faces = positions
+            .iter()
+            .map(|(x, y)| cross.crop(x * size, y * size, size, size))
+            .collect::<Vec<_>>();
+        if vertical {
+            faces[5] = faces[5].rotate_180();
+        }
+        Ok(TextureCube::from_faces(faces))
+    }
+
+    pub fn face(&self, face: CubeFace) -> &Texture2D {
+        &self.faces[face as usize]
+    }
+
+    /// Samples along `direction`, which does not need to be normalized. Faces are
+    /// always clamped at their edges, whatever the sampler says.
+    pub fn sample_lod(&self, sampler: &Sampler, direction: Vector3<f32>, lod: f32) -> Vector4<f32> {
+        let (face, uv) = CubeFace::from_direction(direction);
+        let sampler = Sampler {
+            wrap_u: Wrap::Clamp,
+            wrap_v: Wrap::Clamp,
+            ..*sampler
+        };
+        self.face(face).sample_lod(&sampler, uv, lod)
+    }
+}
+
+/// A texture covering every direction, used for backgrounds and reflections.
+#[derive(Clone, Debug)]
+pub enum Environment {
+    Cube(TextureCube),
+    /// Latitude/longitude panorama, typically an HDR image. The center of the
+    /// image looks along +Z and the top row straight up.
+    Equirectangular(Texture2D),
+}
+
+impl Environment {
+    pub fn sample_lod(&self, sampler: &Sampler, direction: Vector3<f32>, lod: f32) -> Vector4<f32> {
+        match self {
+            Environment::Cube(cube) => cube.sample_lod(sampler, direction, lod),
+            Environment::Equirectangular(texture) => {
+                let sampler = Sampler {
+                    wrap_u: Wrap::Repeat,
+                    wrap_v: Wrap::Clamp,
+                    ..*sampler
+                };
+                texture.sample_lod(&sampler, equirectangular_uv(direction), lod)
+            }
+        }
+    }
+}
+
+/// Where an environment is looked up for a shadelang input.
+#[derive(Copy, Clone, Debug, PartialEq, Deserialize)]
+pub enum Lookup {
+    /// Along the interpolated normal.
+    Normal,
+    /// Along the view direction mirrored about the normal.
+    Reflection,
+}
+
+/// Environment sampled on the Rust side for a shadelang input, see
+/// `TextureBinding`.
+#[derive(Clone, Debug)]
+pub struct EnvironmentBinding {
+    pub name: String,
+    pub environment: Environment,
+    pub sampler: Sampler,
+    pub lookup: Lookup,
+}
+
+fn equirectangular_uv(direction: Vector3<f32>) -> Vector2<f32> {
+    let d = direction.normalize();
+    Vector2::new(
+        0.5 + d.x.atan2(d.z) / (2.0 * PI),
+        0.5 + d.y.clamp(-1.0, 1.0).asin() / PI,
+    )
+}
@@ -1,11 +1,15 @@
 use cgmath::prelude::*;
 use cgmath::Deg;
-use cgmath::{Point3, Quaternion, Vector3};
+use cgmath::{Matrix3, Matrix4, Point3, Quaternion, Vector3};
 
+use std::ops::Mul;
+
+/// Translation, rotation and scale, applied to a point in the reverse order.
 #[derive(Debug, Clone)]
 pub struct Transform {
     pub position: Point3<f32>,
     pub rotation: Quaternion<f32>,
+    pub scale: Vector3<f32>,
 }
 
 impl Default for Transform {
@@ -13,11 +17,105 @@ impl Default for Transform {
         Transform {
             position: Point3::new(0.0, 0.0, 0.0),
             rotation: Quaternion::from_angle_x(Deg(0.0)),
+            scale: Vector3::new(1.0, 1.0, 1.0),
         }
     }
 }
 
 impl Transform {
+    pub fn new(position: Point3<f32>, rotation: Quaternion<f32>, scale: Vector3<f32>) -> Transform {
+        Transform {
+            position,
+            rotation,
+            scale,
+        }
+    }
+
+    /// Decomposes an affine matrix. Shear cannot be represented and is lost, a
+    /// mirroring is kept as a negative X scale.
+    pub fn from_matrix(m: Matrix4<f32>) -> Transform {
+        let mut x = m.x.truncate();
+        let y = m.y.truncate();
+        let z = m.z.truncate();
+
+        let mut scale = Vector3::new(x.magnitude(), y.magnitude(), z.magnitude());
+        if Matrix3::from_cols(x, y, z).determinant() < 0.0 {
+            scale.x = -scale.x;
+            x = -x;
+        }
+
+        let axis = |v: Vector3<f32>, length: f32| {
+            if length.abs() > f32::EPSILON {
+                v / length.abs()
+            } else {
+                Vector3::zero()
+            }
+        };
+        let mut basis = Matrix3::from_cols(axis(x, scale.x), axis(y, scale.y), axis(z, scale.z));
+
+        // A zero scale leaves its axis undefined, rebuild it from the others
+        if basis.x == Vector3::zero() {
+            basis.x = basis.y.cross(basis.z);
+        }
+        if basis.y == Vector3::zero() {
+            basis.y = basis.z.cross(basis.x);
+        }
+        if basis.z == Vector3::zero() {
+            basis.z = basis.x.cross(basis.y);
+        }
+
+        Transform {
+            position: Point3::from_vec(m.w.truncate()),
+            rotation: Quaternion::from(basis).normalize(),
+            scale,
+        }
+    }
+
+    /// Model matrix, translation * rotation * scale.
+    pub fn to_matrix(&self) -> Matrix4<f32> {
+        Matrix4::from_translation(self.position.to_vec())
+            * Matrix4::from(self.rotation)
+            * Matrix4::from_nonuniform_scale(self.scale.x, self.scale.y, self.scale.z)
+    }
+
+    /// Inverse transpose of the upper 3x3 of `to_matrix`, for transforming
+    /// normals so they stay perpendicular to non-uniformly scaled surfaces.
+    pub fn normal_matrix(&self) -> Matrix3<f32> {
+        let inverse_scale =
+            Vector3::new(1.0 / self.scale.x, 1.0 / self.scale.y, 1.0 / self.scale.z);
+        Matrix3::from(self.rotation) * Matrix3::from_diagonal(inverse_scale)
+    }
+
+    /// Whether the transform mirrors, which turns the winding of triangles around.
+    pub fn is_mirrored(&self) -> bool {
+        self.scale.x * self.scale.y * self.scale.z < 0.0
+    }
+
+    /// Inverse of `to_matrix`.
+    pub fn inverse_matrix(&self) -> Matrix4<f32> {
+        let inverse_rotation = self.rotation.invert();
+        Matrix4::from_nonuniform_scale(1.0 / self.scale.x, 1.0 / self.scale.y, 1.0 / self.scale.z)
+            * Matrix4::from(inverse_rotation)
+            * Matrix4::from_translation(-self.position.to_vec())
+    }
+
+    /// Inverse transform. Exact for uniform scale, otherwise the inverse would need
+    /// shear and this is the decomposition of `inverse_matrix`.
+    pub fn inverse(&self) -> Transform {
+        Transform::from_matrix(self.inverse_matrix())
+    }
+
+    pub fn transform_point(&self, p: Point3<f32>) -> Point3<f32> {
+        self.position
+            + self
+                .rotation
+                .rotate_vector(self.scale.mul_element_wise(p.to_vec()))
+    }
+
+    pub fn transform_vector(&self, v: Vector3<f32>) -> Vector3<f32> {
+        self.rotation.rotate_vector(self.scale.mul_element_wise(v))
+    }
+
     pub fn forward(&self) -> Vector3<f32> {
         self.rotation.rotate_vector(Vector3::new(0.0, 0.0, 1.0))
     }
@@ -34,3 +132,36 @@ impl Transform {
         -self.up()
     }
 }
+
+/// Inverse transpose of the upper 3x3 of `model`, for matrices that are not a
+/// single `Transform` such as composed world matrices.
+pub fn normal_matrix(model: &Matrix4<f32>) -> Matrix3<f32> {
+    let linear = Matrix3::from_cols(model.x.truncate(), model.y.truncate(), model.z.truncate());
+    linear
+        .invert()
+        .map(|inverse| inverse.transpose())
+        .unwrap_or(linear)
+}
+
+/// `parent * child` places `child` in the space of `parent`. Scales multiply per
+/// axis, which is exact unless the parent scales non-uniformly and the child is
+/// rotated, where the true result would be sheared.
+impl Mul<&Transform> for &Transform {
+    type Output = Transform;
+
+    fn mul(self, child: &Transform) -> Transform {
+        Transform {
+            position: self.transform_point(child.position),
+            rotation: self.rotation * child.rotation,
+            scale: self.scale.mul_element_wise(child.scale),
+        }
+    }
+}
+
+impl Mul for Transform {
+    type Output = Transform;
+
+    fn mul(self, child: Transform) -> Transform {
+        &self * &child
+    }
+}